@@ -0,0 +1,101 @@
+use std::{sync::{atomic::{AtomicBool, Ordering}, mpsc::{self, Receiver}, Arc}, thread::JoinHandle};
+
+use crate::{boardb::BoardB, gameb::GameB, pieces::Side, san, search::{self, SearchConfig, SearchResult}, tt::TranspositionTable};
+
+
+
+//moves of a line that still fit into the side panel
+const SHOWN_PLIES: usize = 8;
+
+//Background multi-PV analysis for the side panel. Follows the position on the board and starts
+//over whenever it changes, the last finished iteration is kept for drawing.
+pub struct Analysis {
+    config: SearchConfig,
+    tt: Arc<TranspositionTable>,
+    //position of the running search
+    position: Option<(BoardB, Side, u64)>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+    results: Option<Receiver<SearchResult>>,
+    latest: Option<SearchResult>,
+}
+
+impl Analysis {
+    pub fn new(multi_pv: usize, threads: usize, tt: Arc<TranspositionTable>) -> Self {
+        Self {
+            config: SearchConfig { depth: search::MAX_DEPTH, threads, multi_pv, ..Default::default() },
+            tt,
+            position: None,
+            stop: Arc::new(AtomicBool::new(false)),
+            thread: None,
+            results: None,
+            latest: None,
+        }
+    }
+
+    //call every frame while the panel is open
+    pub fn update(&mut self, game: &GameB) {
+        let key = game.board.hash(game.turn);
+        if self.position.is_none_or(|(_, _, k)| k != key) {
+            self.stop();
+            self.position = Some((game.board, game.turn, key));
+            self.stop = Arc::new(AtomicBool::new(false));
+            //a new channel per search, results of the old one can't arrive anymore
+            let (sender, receiver) = mpsc::channel();
+            self.results = Some(receiver);
+            let (board, turn, config, tt, stop) = (game.board, game.turn, self.config, self.tt.clone(), self.stop.clone());
+            self.thread = Some(std::thread::spawn(move || {
+                search::analyse(board, turn, config, &tt, &stop, &mut |result| {
+                    let _ = sender.send(result.clone());
+                });
+            }));
+        }
+        if let Some(results) = &self.results && let Some(result) = results.try_iter().last() {
+            self.latest = Some(result);
+        }
+    }
+
+    //waits for the search thread, it notices the stop within a few nodes
+    pub fn stop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+        self.position = None;
+        self.results = None;
+        self.latest = None;
+    }
+
+    //text rows of the panel: a header, then evaluation and line of every candidate, best first
+    pub fn rows(&self) -> Vec<String> {
+        let (Some(result), Some((board, turn, _))) = (&self.latest, self.position) else {
+            return vec!["analysing...".to_owned()];
+        };
+        let mut rows = vec![format!("depth {}  nodes {}", result.depth, result.nodes)];
+        for (n, line) in result.lines.iter().enumerate() {
+            //scores are from the side to move, the panel shows them from white's view
+            let score = if turn == Side::White {line.score} else {-line.score};
+            rows.push(format!("{}.  {}", n + 1, score_text(score)));
+            rows.push(format!("    {}", san_line(&board, turn, &line.pv)));
+        }
+        rows
+    }
+}
+
+fn score_text(score: i32) -> String {
+    match search::mate_in(score) {
+        Some(moves) => format!("#{}", moves),
+        None => format!("{:+.2}", score as f32 / 100.0),
+    }
+}
+
+fn san_line(board: &BoardB, turn: Side, pv: &[(u8, u8)]) -> String {
+    let (mut board, mut turn) = (*board, turn);
+    let mvs: Vec<String> = pv.iter().take(SHOWN_PLIES).map(|(from, to)| {
+        let san = san::to_san(&board, turn, *from, *to);
+        board.make_move(*from, *to);
+        turn = !turn;
+        san
+    }).collect();
+    mvs.join(" ")
+}
@@ -2,11 +2,15 @@ use std::{cmp::{min, max}, slice::Iter, collections::HashMap};
 
 use vecm::vec::{Vec2i, PolyVec2};
 
-use crate::{pieces::{Side, PieceType}, pos, castle::{Castle, self}};
+use crate::{pieces::{Side, PieceType}, pos, castle::{Castle, self}, zobrist, nnue::{self, Accumulator}, eval};
 
 
 
 
+const ROOK_DIRS: [(i8, i8); 4] = [(1, 0), (-1, 0), (0, 1), (0, -1)];
+const BISHOP_DIRS: [(i8, i8); 4] = [(1, 1), (1, -1), (-1, 1), (-1, -1)];
+const KNIGHT_JUMPS: [(i8, i8); 8] = [(-1, 2), (1, 2), (-1, -2), (1, -2), (2, 1), (2, -1), (-2, 1), (-2, -1)];
+
 #[derive(Clone, Copy)]
 pub struct BoardB {
     pub board: [Option<Piece>; 64],
@@ -15,7 +19,9 @@ pub struct BoardB {
     black_castle: Castle,
     pub kings: (u8, u8),
     //pos_index of possible en passant, if > 63, not possible
-    en_passant: u8
+    en_passant: u8,
+    //first layer of the network for this position, set_piece and remove_piece keep it up to date
+    accumulator: Accumulator,
 }
 
 impl BoardB {    
@@ -46,7 +52,8 @@ impl BoardB {
             white_castle: castle,
             black_castle: castle,
             kings,
-            en_passant: 64
+            en_passant: 64,
+            accumulator: Accumulator::new(&board),
         }
     }
 
@@ -76,7 +83,20 @@ impl BoardB {
             white_castle: castle,
             black_castle: castle,
             kings,
-            en_passant: 64
+            en_passant: 64,
+            accumulator: Accumulator::new(&board),
+        }
+    }
+
+    //no pieces, no castling rights
+    pub fn empty() -> Self {
+        Self {
+            board: [None; 64],
+            white_castle: Castle::forbid(),
+            black_castle: Castle::forbid(),
+            kings: (64, 64),
+            en_passant: 64,
+            accumulator: Accumulator::new(&[None; 64]),
         }
     }
 
@@ -85,11 +105,16 @@ impl BoardB {
     }
 
     pub fn remove_piece(&mut self, i: u8) {
-        self.board[i as usize] = None;
+        if let Some(old) = self.board[i as usize].take() {
+            self.accumulator.remove(old, i);
+        }
     }
     //may be faster if positions are switched
     pub fn set_piece(&mut self, i: u8, piece: Piece) {
-        self.board[i as usize] = Some(piece);
+        if let Some(old) = self.board[i as usize].replace(piece) {
+            self.accumulator.remove(old, i);
+        }
+        self.accumulator.add(piece, i);
     }
 
     fn valid_moves_for_piece(&self, i: u8, from_castling_check: bool) -> u64 {
@@ -178,18 +203,21 @@ impl BoardB {
             }
 
             let mut castle = |y: i8, castle: Castle| {
+                if !castle.long && !castle.short {
+                    return;
+                }
                 //for long: x = 2,3 for short: x = 5,6 / check for check
-                let mvs = self.valid_moves_as_array(!piece.side, true, true);
+                let attacked = |x: i8| self.attackers_to(Pos::new(x,y).to_i(), !piece.side) > 0;
                 if 
                     castle.long && 
                     !self.space_occupied(Pos::new(1,y), Pos::new(3,y)) && 
-                    (2..=4).all(|x| !self.threathens(Pos::new(x,y).to_i(), &mvs))
+                    !(2..=4).any(attacked)
                 {
                     add_pos(Pos::new(2,y).to_i(), valid_mvs);
                 }
                 if  castle.short && 
                     !self.space_occupied(Pos::new(5,y), Pos::new(6,y)) &&
-                    (4..=6).all(|x| !self.threathens(Pos::new(x,y).to_i(), &mvs))
+                    !(4..=6).any(attacked)
                 {
                     add_pos(Pos::new(6,y).to_i(), valid_mvs);
                 }
@@ -234,7 +262,7 @@ impl BoardB {
                 } 
             };
             //detect en passant
-            if self.en_passant < 64 {
+            if self.en_passant < 64 && self.get_piece_at_pos(self.en_passant).is_some_and(|p| p.side != piece.side) {
                 let en_passant_pos = Pos::from_i(self.en_passant);
                 if (en_passant_pos.x - piece_pos.x).abs() == 1 && en_passant_pos.y == piece_pos.y {
                     add_pos((self.en_passant as i8 + 8 * y_dir) as u8, valid_mvs)
@@ -252,6 +280,11 @@ impl BoardB {
         }
         valid_moves
     }    
+    //moves ignoring checks and castling
+    pub fn pseudo_moves(&self, i: u8) -> u64 {
+        self.valid_moves_for_piece(i, true)
+    }
+
     pub fn i_to_xy(&self, i: u8) -> Pos {
         let x = i % 8;
         let y = i / 8 ;
@@ -298,6 +331,14 @@ impl BoardB {
         mvs
     }
 
+    pub fn piece_count(&self) -> usize {
+        self.board.iter().filter(|p| p.is_some()).count()
+    }
+
+    pub fn has_castling_rights(&self) -> bool {
+        self.white_castle.short || self.white_castle.long || self.black_castle.short || self.black_castle.long
+    }
+
     pub fn find_king(&self, side: Side) -> u8 {
         match side {
             Side::Black => self.kings.1,
@@ -317,6 +358,11 @@ impl BoardB {
 
     //Returns captured piece
     pub fn make_move(&mut self, from: u8, to: u8) -> Option<Piece> {
+        self.make_move_promoting(from, to, PieceType::Queen)
+    }
+
+    //promotion is only used if a pawn reaches the last rank
+    pub fn make_move_promoting(&mut self, from: u8, to: u8, promotion: PieceType) -> Option<Piece> {
         let captured_piece = self.get_piece_at_pos(to);
         let mut piece = self.get_piece_at_pos(from).expect("Tried to move non existing piece");
         match piece.ty {
@@ -364,9 +410,11 @@ impl BoardB {
                 }
                 if (from_xy.y - to_xy.y).abs() > 1 {
                     self.en_passant = to;
+                } else {
+                    self.en_passant = 64;
                 }
                 if to_xy.y == 0 || to_xy.y == 7 {
-                    piece = Piece::new(PieceType::Queen, piece.side);
+                    piece = Piece::new(promotion, piece.side);
                 }
             },
             _ => {self.en_passant = 64}
@@ -393,17 +441,6 @@ impl BoardB {
         self.threathens(self.find_king(side), mvs_for_piece)
     }
 
-    //used for check indicator
-    pub fn is_check_from_hm(&self, mvs_for_piece: &HashMap<u8, u64>, side: Side) -> bool {
-        let victim = 1_u64 << self.find_king(side);
-        for (_, piece_mvs) in mvs_for_piece {
-            if victim & piece_mvs > 0 {
-                return true
-            }
-        }
-        false
-    }
-
     fn threathens(&self, pos: u8, mvs_for_piece: &[u64; 16]) -> bool{
         let victim = 1_u64 << pos;
         for piece_mvs in mvs_for_piece {
@@ -417,7 +454,362 @@ impl BoardB {
     pub fn is_check_after(&self, from: u8, to: u8, side: Side) -> bool {
         let mut board = *self;
         board.make_move(from, to);
-        board.is_check(&board.valid_moves_as_array(!side, false, true), side)
+        board.checkers(side) > 0
+    }
+
+    //bitmap of all squares with a piece
+    pub fn occupancy(&self) -> u64 {
+        self.board.iter().enumerate().filter(|(_, p)| p.is_some()).fold(0, |bits, (i, _)| bits | 1 << i)
+    }
+
+    //bitmap of the pieces of side that attack square, no matter if the move would be legal
+    pub fn attackers_to(&self, square: u8, side: Side) -> u64 {
+        self.attackers_with(square, side, self.occupancy())
+    }
+
+    //attackers as if only the pieces in occupied were on the board, sliders see through the rest
+    fn attackers_with(&self, square: u8, side: Side, occupied: u64) -> u64 {
+        let target = Pos::from_i(square);
+        let mut attackers = 0;
+        let mut add = |pos: Pos, types: &[PieceType]| {
+            if self.xy_on_board(pos) && occupied >> pos.to_i() & 1 == 1
+                && self.get_piece_at_pos(pos.to_i()).is_some_and(|p| p.side == side && types.contains(&p.ty))
+            {
+                attackers |= 1 << pos.to_i();
+            }
+        };
+        //white pawns move up the board, to smaller y
+        let pawn_y = match side {
+            Side::White => 1,
+            Side::Black => -1,
+        };
+        add(target + Pos::new(-1, pawn_y), &[PieceType::Pawn]);
+        add(target + Pos::new(1, pawn_y), &[PieceType::Pawn]);
+        for (x, y) in KNIGHT_JUMPS {
+            add(target + Pos::new(x, y), &[PieceType::Knight]);
+        }
+        for (x, y) in ROOK_DIRS.into_iter().chain(BISHOP_DIRS) {
+            add(target + Pos::new(x, y), &[PieceType::King]);
+        }
+        for (dirs, slider) in [(ROOK_DIRS, PieceType::Rook), (BISHOP_DIRS, PieceType::Bishop)] {
+            for (x, y) in dirs {
+                if let Some(blocker) = self.first_on_ray(target, Pos::new(x, y), occupied) {
+                    add(Pos::from_i(blocker), &[slider, PieceType::Queen]);
+                }
+            }
+        }
+        attackers
+    }
+
+    //first square with a piece in occupied, walking from pos in dir
+    fn first_on_ray(&self, pos: Pos, dir: Pos, occupied: u64) -> Option<u8> {
+        let mut pos = pos + dir;
+        while self.xy_on_board(pos) {
+            if occupied >> pos.to_i() & 1 == 1 {
+                return Some(pos.to_i());
+            }
+            pos += dir;
+        }
+        None
+    }
+
+    //the first two pieces on every ray from square, for x-rays and pins
+    fn ray_pairs(&self, square: u8) -> impl Iterator<Item = (u8, u8, PieceType)> + '_ {
+        let occupied = self.occupancy();
+        let from = Pos::from_i(square);
+        ROOK_DIRS.into_iter().map(|d| (d, PieceType::Rook))
+            .chain(BISHOP_DIRS.into_iter().map(|d| (d, PieceType::Bishop)))
+            .filter_map(move |((x, y), slider)| {
+                let dir = Pos::new(x, y);
+                let first = self.first_on_ray(from, dir, occupied)?;
+                let second = self.first_on_ray(Pos::from_i(first), dir, occupied)?;
+                Some((first, second, slider))
+            })
+    }
+
+    fn slides_like(&self, i: u8, side: Side, slider: PieceType) -> bool {
+        self.get_piece_at_pos(i).is_some_and(|p| p.side == side && (p.ty == slider || p.ty == PieceType::Queen))
+    }
+
+    //sliders of side that would attack square if the first piece in their way was gone
+    pub fn xray_attackers(&self, square: u8, side: Side) -> u64 {
+        self.ray_pairs(square)
+            .filter(|(_, behind, slider)| self.slides_like(*behind, side, *slider))
+            .fold(0, |bits, (_, behind, _)| bits | 1 << behind)
+    }
+
+    //pieces of side that can't leave the line between their king and an enemy slider
+    pub fn pinned(&self, side: Side) -> u64 {
+        self.ray_pairs(self.find_king(side))
+            .filter(|(first, behind, slider)| {
+                self.get_piece_at_pos(*first).is_some_and(|p| p.side == side) && self.slides_like(*behind, !side, *slider)
+            })
+            .fold(0, |bits, (first, _, _)| bits | 1 << first)
+    }
+
+    //enemy pieces giving check to the king of side
+    pub fn checkers(&self, side: Side) -> u64 {
+        self.attackers_to(self.find_king(side), !side)
+    }
+
+    //Static exchange evaluation: material the moving side wins if both sides keep recapturing on
+    //to with their least valuable attacker and may stop whenever that is better. Pins are ignored
+    //and en passant counts as a move to an empty square.
+    pub fn see(&self, from: u8, to: u8) -> i32 {
+        let Some(piece) = self.get_piece_at_pos(from) else {
+            return 0;
+        };
+        let mut gain = [0; 33];
+        gain[0] = self.get_piece_at_pos(to).map_or(0, |p| p.value());
+        let mut on_square = piece.value();
+        let mut occupied = self.occupancy() & !(1 << from);
+        let mut side = !piece.side;
+        //with no slider lined up behind the first piece of a ray nobody joins the exchange later,
+        //then the attackers only have to be found once
+        let lined_up = (self.xray_attackers(to, Side::White) | self.xray_attackers(to, Side::Black)) != 0;
+        let direct = (self.attackers_to(to, Side::White), self.attackers_to(to, Side::Black));
+        let mut depth = 0;
+        loop {
+            depth += 1;
+            //what the side to recapture wins if the piece on the square is taken and lost again
+            gain[depth] = on_square - gain[depth - 1];
+            if max(-gain[depth - 1], gain[depth]) < 0 {
+                break;
+            }
+            let attackers = match (lined_up, side) {
+                (true, _) => self.attackers_with(to, side, occupied),
+                (false, Side::White) => direct.0 & occupied,
+                (false, Side::Black) => direct.1 & occupied,
+            };
+            let Some((square, attacker)) = self.least_valuable(attackers) else {
+                break;
+            };
+            //the king may only take last
+            if attacker.ty == PieceType::King && self.attackers_with(to, !side, occupied & !(1 << square)) > 0 {
+                break;
+            }
+            occupied &= !(1 << square);
+            on_square = attacker.value();
+            side = !side;
+        }
+        while depth > 1 {
+            depth -= 1;
+            gain[depth - 1] = -max(-gain[depth - 1], gain[depth]);
+        }
+        gain[0]
+    }
+
+    fn least_valuable(&self, pieces: u64) -> Option<(u8, Piece)> {
+        const ORDER: [PieceType; 6] = [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen, PieceType::King];
+        ORDER.iter().find_map(|ty| {
+            let mut pieces = pieces;
+            while let Some(i) = pieces.pop_first() {
+                if let Some(piece) = self.get_piece_at_pos(i) && piece.ty == *ty {
+                    return Some((i, piece));
+                }
+            }
+            None
+        })
+    }
+
+    //squares the piece on i attacks, sliders stop at the first piece in occupied
+    fn attacks_from(&self, i: u8, piece: Piece, occupied: u64) -> u64 {
+        let from = Pos::from_i(i);
+        let mut attacks = 0;
+        let mut add = |pos: Pos| {
+            if self.xy_on_board(pos) {
+                attacks |= 1 << pos.to_i();
+            }
+        };
+        match piece.ty {
+            PieceType::Pawn => {
+                let y_dir = match piece.side {
+                    Side::White => -1,
+                    Side::Black => 1,
+                };
+                add(from + Pos::new(-1, y_dir));
+                add(from + Pos::new(1, y_dir));
+            },
+            PieceType::Knight => KNIGHT_JUMPS.into_iter().for_each(|(x, y)| add(from + Pos::new(x, y))),
+            PieceType::King => ROOK_DIRS.into_iter().chain(BISHOP_DIRS).for_each(|(x, y)| add(from + Pos::new(x, y))),
+            slider => {
+                let dirs = match slider {
+                    PieceType::Rook => &ROOK_DIRS[..],
+                    PieceType::Bishop => &BISHOP_DIRS[..],
+                    _ => &[(1, 0), (-1, 0), (0, 1), (0, -1), (1, 1), (1, -1), (-1, 1), (-1, -1)][..],
+                };
+                for (x, y) in dirs {
+                    let dir = Pos::new(*x, *y);
+                    let mut pos = from + dir;
+                    while self.xy_on_board(pos) {
+                        add(pos);
+                        if occupied >> pos.to_i() & 1 == 1 {
+                            break;
+                        }
+                        pos += dir;
+                    }
+                }
+            },
+        }
+        attacks
+    }
+
+    //squares from a to the edge of the board through b, a and b have to share a line
+    fn line_through(&self, a: u8, b: u8) -> u64 {
+        let (a, b) = (Pos::from_i(a), Pos::from_i(b));
+        let dir = Pos::new((b.x - a.x).signum(), (b.y - a.y).signum());
+        let mut line = 0;
+        let mut pos = a + dir;
+        while self.xy_on_board(pos) {
+            line |= 1 << pos.to_i();
+            pos += dir;
+        }
+        line
+    }
+
+    //squares strictly between a and b, nothing if they don't share a line
+    fn between(&self, a: u8, b: u8) -> u64 {
+        let (pa, pb) = (Pos::from_i(a), Pos::from_i(b));
+        let (dx, dy) = (pb.x - pa.x, pb.y - pa.y);
+        if dx != 0 && dy != 0 && dx.abs() != dy.abs() {
+            return 0;
+        }
+        let dir = Pos::new(dx.signum(), dy.signum());
+        let mut bits = 0;
+        let mut pos = pa + dir;
+        while pos != pb {
+            bits |= 1 << pos.to_i();
+            pos += dir;
+        }
+        bits
+    }
+
+    //legal captures and promotions, for the quiescence search. Promotions are listed once and played
+    //as a queen.
+    pub fn captures(&self, side: Side, mvs: &mut MoveList) {
+        self.generate(side, MoveKind::Captures, mvs);
+    }
+
+    //legal moves that give check without capturing or promoting
+    pub fn quiet_checks(&self, side: Side, mvs: &mut MoveList) {
+        self.generate(side, MoveKind::QuietChecks, mvs);
+    }
+
+    //all legal moves if side is in check, nothing otherwise
+    pub fn evasions(&self, side: Side, mvs: &mut MoveList) {
+        self.generate(side, MoveKind::Evasions, mvs);
+    }
+
+    //Legality comes from pins and checkers instead of playing every move on a copy, only en
+    //passant and castling are tried out.
+    fn generate(&self, side: Side, kind: MoveKind, mvs: &mut MoveList) {
+        mvs.clear();
+        let occupied = self.occupancy();
+        let own = self.side_bits(side);
+        let enemy = occupied & !own;
+        let king = self.find_king(side);
+        let checkers = self.attackers_with(king, !side, occupied);
+        if kind == MoveKind::Evasions && checkers == 0 {
+            return;
+        }
+        //non king moves have to capture the only checker or block it
+        let target_mask = match checkers.count_ones() {
+            0 => u64::MAX,
+            1 => checkers | self.between(king, checkers.trailing_zeros() as u8),
+            _ => 0,
+        };
+        let pinned = self.pinned(side);
+        let wanted = |from: u8, to: u8, capture: bool, promotion: bool| match kind {
+            MoveKind::Captures => capture || promotion,
+            MoveKind::QuietChecks => !capture && !promotion && self.gives_check(from, to, side, occupied),
+            MoveKind::Evasions => true,
+        };
+
+        let mut pieces = own;
+        while let Some(from) = pieces.pop_first() {
+            let piece = self.board[from as usize].expect("Own pieces are on the board");
+            if piece.ty == PieceType::King {
+                //castling is only a quiet move out of check
+                let mut targets = self.valid_moves_for_piece(from, checkers > 0 || kind != MoveKind::QuietChecks);
+                while let Some(to) = targets.pop_first() {
+                    let castling = (Pos::from_i(to).x - Pos::from_i(from).x).abs() == 2;
+                    let legal = if castling {
+                        self.is_legal_on_copy(from, to, side)
+                    } else {
+                        self.attackers_with(to, !side, occupied & !(1 << from)) == 0
+                    };
+                    if legal && wanted(from, to, enemy >> to & 1 == 1, false) {
+                        mvs.push((from, to));
+                    }
+                }
+                continue;
+            }
+            let mut mask = target_mask;
+            if pinned >> from & 1 == 1 {
+                mask &= self.line_through(king, from);
+            }
+            let mut targets = if piece.ty == PieceType::Pawn {
+                self.pawn_targets(from, piece, occupied, enemy)
+            } else {
+                self.attacks_from(from, piece, occupied) & !own
+            };
+            while let Some(to) = targets.pop_first() {
+                let en_passant = piece.ty == PieceType::Pawn && Pos::from_i(to).x != Pos::from_i(from).x && enemy >> to & 1 == 0;
+                let legal = if en_passant {self.is_legal_on_copy(from, to, side)} else {mask >> to & 1 == 1};
+                let promotion = piece.ty == PieceType::Pawn && (to < 8 || to >= 56);
+                if legal && wanted(from, to, en_passant || enemy >> to & 1 == 1, promotion) {
+                    mvs.push((from, to));
+                }
+            }
+        }
+    }
+
+    //pushes, captures and en passant of the pawn on from
+    fn pawn_targets(&self, from: u8, piece: Piece, occupied: u64, enemy: u64) -> u64 {
+        let pos = Pos::from_i(from);
+        let (y_dir, start_y) = match piece.side {
+            Side::White => (-1, 6),
+            Side::Black => (1, 1),
+        };
+        let mut targets = self.attacks_from(from, piece, occupied) & enemy;
+        let one = pos + Pos::new(0, y_dir);
+        if self.xy_on_board(one) && occupied >> one.to_i() & 1 == 0 {
+            targets |= 1 << one.to_i();
+            let two = one + Pos::new(0, y_dir);
+            if pos.y == start_y && occupied >> two.to_i() & 1 == 0 {
+                targets |= 1 << two.to_i();
+            }
+        }
+        if self.en_passant < 64 && self.get_piece_at_pos(self.en_passant).is_some_and(|p| p.side != piece.side) {
+            let victim = Pos::from_i(self.en_passant);
+            if victim.y == pos.y && (victim.x - pos.x).abs() == 1 {
+                targets |= 1 << (victim + Pos::new(0, y_dir)).to_i();
+            }
+        }
+        targets
+    }
+
+    fn is_legal_on_copy(&self, from: u8, to: u8, side: Side) -> bool {
+        let mut board = *self;
+        board.make_move(from, to);
+        board.checkers(side) == 0
+    }
+
+    //the quiet move from -> to checks the enemy king directly or by uncovering a slider
+    fn gives_check(&self, from: u8, to: u8, side: Side, occupied: u64) -> bool {
+        let piece = self.board[from as usize].expect("Moving piece is on the board");
+        let enemy_king = self.find_king(!side);
+        if piece.ty == PieceType::King && (Pos::from_i(to).x - Pos::from_i(from).x).abs() == 2 {
+            let mut board = *self;
+            board.make_move(from, to);
+            return board.checkers(!side) > 0;
+        }
+        let after = occupied & !(1 << from) | 1 << to;
+        self.attacks_from(to, piece, after) >> enemy_king & 1 == 1 || self.attackers_with(enemy_king, side, after) > 0
+    }
+
+    fn side_bits(&self, side: Side) -> u64 {
+        self.board.iter().enumerate().filter(|(_, p)| p.is_some_and(|p| p.side == side)).fold(0, |bits, (i, _)| bits | 1 << i)
     }
 
     fn xy_on_board(&self, pos: Pos) -> bool {
@@ -455,13 +847,25 @@ impl BoardB {
             }
         };
 
-        fn pos(s: &str) -> Result<u8, FenError> {
-            let a = s.chars().next().ok_or(FenError::EnPassant)?;
-            let b = s.chars().next().ok_or(FenError::EnPassant)?;
-            if s.chars().next().is_some() || !('a'..='h').contains(&a) || !('1'..='8').contains(&a) {
+        //fen stores the square behind the pawn, the board the pawn itself. The pawn was pushed by the
+        //side that isn't to move, so it has to be there.
+        fn en_passant_pawn(s: &str, turn: Side, board: &[Option<Piece>; 64]) -> Result<u8, FenError> {
+            let mut chars = s.chars();
+            let file = chars.next().ok_or(FenError::EnPassant)?;
+            let rank = chars.next().ok_or(FenError::EnPassant)?;
+            let (expected_rank, y_dir) = match turn {
+                Side::Black => ('3', -1),
+                Side::White => ('6', 1),
+            };
+            if chars.next().is_some() || !('a'..='h').contains(&file) || rank != expected_rank {
                 return Err(FenError::EnPassant);
             }
-            Ok(pos!((a as u8 - b'a'), (b as u8 - b'1')))
+            let target = Pos::new((file as u8 - b'a') as i8, 8 - (rank as u8 - b'0') as i8);
+            let pawn = (target + Pos::new(0, y_dir)).to_i();
+            match board[pawn as usize] {
+                Some(piece) if piece.ty == PieceType::Pawn && piece.side != turn => Ok(pawn),
+                _ => Err(FenError::EnPassant),
+            }
         }
 
         let pieces = sections.next().ok_or(FenError::Pieces)?;
@@ -500,7 +904,7 @@ impl BoardB {
         let en_passant_possible = match sections.next().ok_or(FenError::EnPassant)?
          {
             "-" => 64,
-            s => pos(s)?
+            s => en_passant_pawn(s, turn, &board)?
         };
 
         let _halfmoves: &str = sections.next().ok_or(FenError::HalfMoves)?;
@@ -515,21 +919,119 @@ impl BoardB {
                 white_castle,
                 black_castle,
                 kings,
-                en_passant: en_passant_possible
+                en_passant: en_passant_possible,
+                accumulator: Accumulator::new(&board),
             },
             turn
         ))
     }
 
 
+    pub fn to_fen(&self, turn: Side, halfmoves: usize, fullmoves: usize) -> String {
+        let mut fen = String::new();
+        for y in 0..8 {
+            let mut empty = 0;
+            for x in 0..8 {
+                match self.get_piece_at_pos(pos!(x, y) as u8) {
+                    Some(piece) => {
+                        if empty > 0 {
+                            fen.push_str(&empty.to_string());
+                            empty = 0;
+                        }
+                        let c = match piece.ty {
+                            PieceType::Pawn => 'p',
+                            PieceType::Knight => 'n',
+                            PieceType::Bishop => 'b',
+                            PieceType::Rook => 'r',
+                            PieceType::Queen => 'q',
+                            PieceType::King => 'k',
+                        };
+                        fen.push(if piece.side == Side::White {c.to_ascii_uppercase()} else {c});
+                    },
+                    None => empty += 1,
+                }
+            }
+            if empty > 0 {
+                fen.push_str(&empty.to_string());
+            }
+            if y < 7 {
+                fen.push('/');
+            }
+        }
+        let castles: String = [(self.white_castle.short, 'K'), (self.white_castle.long, 'Q'), (self.black_castle.short, 'k'), (self.black_castle.long, 'q')]
+            .into_iter()
+            .filter_map(|(allowed, c)| allowed.then_some(c))
+            .collect();
+        //the square behind the pawn that just moved two squares
+        let en_passant = match self.get_piece_at_pos(self.en_passant) {
+            Some(pawn) => {
+                let target = Pos::from_i(self.en_passant) + Pos::new(0, if pawn.side == Side::White {1} else {-1});
+                format!("{}{}", (b'a' + target.x as u8) as char, 8 - target.y)
+            },
+            None => "-".to_owned(),
+        };
+        format!(
+            "{} {} {} {} {} {}",
+            fen,
+            if turn == Side::White {"w"} else {"b"},
+            if castles.is_empty() {"-"} else {&castles},
+            en_passant,
+            halfmoves,
+            fullmoves
+        )
+    }
+
+    //no side can mate anymore: bare kings or a single minor piece
+    pub fn insufficient_material(&self) -> bool {
+        let mut others = self.board.iter().flatten().filter(|p| p.ty != PieceType::King);
+        match (others.next(), others.next()) {
+            (None, _) => true,
+            (Some(piece), None) => matches!(piece.ty, PieceType::Knight | PieceType::Bishop),
+            _ => false,
+        }
+    }
+
+    //network evaluation if one is loaded, material otherwise
     pub fn evaluate(&self, side: Side) -> i32 {
-        self.board.into_iter()
-            .filter_map(|p| p )
-            .map(|p| {
-                let s = if side == p.side {1} else {-1};
-                p.value() * s
-            })
-            .sum()
+        match nnue::get() {
+            Some(network) => network.evaluate(&self.accumulator, side),
+            None => self.evaluate_material(side),
+        }
+    }
+
+    pub fn evaluate_material(&self, side: Side) -> i32 {
+        eval::params().evaluate(&self.board, side)
+    }
+
+    pub fn hash(&self, turn: Side) -> u64 {
+        let mut hash = 0;
+        for (i, piece) in self.board.iter().enumerate() {
+            if let Some(piece) = piece {
+                hash ^= zobrist::piece_key(*piece, i as u8);
+            }
+        }
+        let castles = [self.white_castle.short, self.white_castle.long, self.black_castle.short, self.black_castle.long];
+        for (n, allowed) in castles.into_iter().enumerate() {
+            if allowed {
+                hash ^= zobrist::RANDOM64[zobrist::CASTLE_OFFSET + n];
+            }
+        }
+        //like polyglot, en passant only counts if a pawn of the side to move can actually capture
+        if self.en_passant < 64 {
+            let ep = Pos::from_i(self.en_passant);
+            let can_capture = [-1, 1].into_iter()
+                .map(|x_dir| ep + Pos::new(x_dir, 0))
+                .filter(|pos| self.xy_on_board(*pos))
+                .filter_map(|pos| self.get_piece_at_pos(pos.to_i()))
+                .any(|p| p.ty == PieceType::Pawn && p.side == turn);
+            if can_capture {
+                hash ^= zobrist::RANDOM64[zobrist::EN_PASSANT_OFFSET + ep.x as usize];
+            }
+        }
+        if turn == Side::White {
+            hash ^= zobrist::RANDOM64[zobrist::TURN_OFFSET];
+        }
+        hash
     }
 
     pub fn print_board(&self) {
@@ -565,16 +1067,9 @@ impl BoardB {
 
 
 
-const PAWN_VALUE: i32 = 100;
-const KNIGHT_VALUE: i32 = 300;
-const BISHOP_VALUE: i32 = 300;
-const ROOK_VALUE: i32 = 500;
-const QUEEN_VALUE: i32 = 900;
-
-
 //piece = u8  | Dont care bits -> XXXX1111
 //first relevant bit = side /  white = 0, black = 1
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, PartialEq)]
 pub struct Piece {
     pub ty: PieceType,
     pub side: Side
@@ -588,14 +1083,7 @@ impl Piece {
 
 
     pub fn value(&self) -> i32 {
-        match self.ty {
-            PieceType::Queen => QUEEN_VALUE,
-            PieceType::King => 0,
-            PieceType::Knight => KNIGHT_VALUE,
-            PieceType::Bishop => BISHOP_VALUE,
-            PieceType::Rook => ROOK_VALUE,
-            PieceType::Pawn => PAWN_VALUE,
-        }
+        eval::params().piece_value(self.ty)
     }
 }
 
@@ -628,10 +1116,78 @@ impl BitMap for u64 {
         }
         ones
     }
+
+    fn pop_first(&mut self) -> Option<u8> {
+        if *self == 0 {
+            return None;
+        }
+        let first = self.trailing_zeros() as u8;
+        *self &= *self - 1;
+        Some(first)
+    }
 }
 
 pub trait BitMap {
     fn ones(self) -> Vec<u8>;
+    //removes and returns the lowest set bit, without allocating like ones
+    fn pop_first(&mut self) -> Option<u8>;
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum MoveKind {
+    Captures,
+    QuietChecks,
+    Evasions,
+}
+
+//Fixed size move buffer for the generators, no position has more than 218 legal moves. Every
+//generator clears it first, so it holds a subset of the legal moves of one position, also when the
+//quiescence search appends the quiet checks to the captures. Moves are (from, to) pairs, promotions
+//are to a queen like in make_move, underpromotions are never generated.
+const MOVE_LIST_CAPACITY: usize = 256;
+
+pub struct MoveList {
+    mvs: [(u8, u8); MOVE_LIST_CAPACITY],
+    len: usize,
+}
+
+impl MoveList {
+    pub fn new() -> Self {
+        Self { mvs: [(0, 0); MOVE_LIST_CAPACITY], len: 0 }
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    //more moves than the capacity would be a generator bug, the index panics in release builds too
+    pub fn push(&mut self, mv: (u8, u8)) {
+        debug_assert!(self.len < MOVE_LIST_CAPACITY, "move list full");
+        self.mvs[self.len] = mv;
+        self.len += 1;
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn as_slice(&self) -> &[(u8, u8)] {
+        &self.mvs[..self.len]
+    }
+
+    pub fn as_mut_slice(&mut self) -> &mut [(u8, u8)] {
+        &mut self.mvs[..self.len]
+    }
+}
+
+impl Default for MoveList {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 #[derive(Debug)]
@@ -644,4 +1200,129 @@ pub enum FenError {
     HalfMoves,
     FullMoves,
     MissingSection(u32)
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{san::square_from_str, search};
+    use super::*;
+
+
+
+    fn sq(s: &str) -> u8 {
+        square_from_str(s).unwrap()
+    }
+
+    fn tos(board: &BoardB, side: Side, from: &str) -> u64 {
+        let mut mvs = HashMap::new();
+        board.valid_moves(side, &mut mvs);
+        mvs.get(&sq(from)).copied().unwrap_or(0)
+    }
+
+    #[test]
+    fn single_push_clears_en_passant() {
+        //1. e4 a6 2. e5 d5 3. h3 h6, the chance to take on d6 is gone
+        let mut board = BoardB::with_starting_pos();
+        for (from, to) in [("e2", "e4"), ("a7", "a6"), ("e4", "e5"), ("d7", "d5")] {
+            board.make_move(sq(from), sq(to));
+        }
+        assert_ne!(tos(&board, Side::White, "e5") & 1 << sq("d6"), 0);
+        for (from, to) in [("h2", "h3"), ("h7", "h6")] {
+            board.make_move(sq(from), sq(to));
+        }
+        assert_eq!(tos(&board, Side::White, "e5") & 1 << sq("d6"), 0);
+    }
+
+    #[test]
+    fn en_passant_only_takes_enemy_pawns() {
+        //1. d4 a6 2. e4, the d pawn stands next to its own double pushed pawn
+        let mut board = BoardB::with_starting_pos();
+        for (from, to) in [("d2", "d4"), ("a7", "a6"), ("e2", "e4")] {
+            board.make_move(sq(from), sq(to));
+        }
+        assert_eq!(tos(&board, Side::White, "d4"), 1 << sq("d5"));
+    }
+
+    #[test]
+    fn fen_en_passant() {
+        let fen = "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1";
+        let (board, turn) = BoardB::from_fen(fen).unwrap();
+        assert_eq!(tos(&board, turn, "e5"), 1 << sq("e6") | 1 << sq("d6"));
+        assert_eq!(board.to_fen(turn, 0, 1), fen);
+        let (board, turn) = BoardB::from_fen("4k3/8/8/8/3Pp3/8/8/4K3 b - d3 0 1").unwrap();
+        assert_eq!(tos(&board, turn, "e4"), 1 << sq("e3") | 1 << sq("d3"));
+        //d3 is behind a white pawn, c6 has no pawn in front of it
+        for square in ["d5", "d", "d66", "i6", "d3", "c6", "e6"] {
+            let fen = format!("4k3/8/8/3pP3/8/8/8/4K3 w - {} 0 1", square);
+            assert!(matches!(BoardB::from_fen(&fen), Err(FenError::EnPassant)), "{}", square);
+        }
+        for square in ["d6", "e3"] {
+            let fen = format!("4k3/8/8/8/3Pp3/8/8/4K3 b - {} 0 1", square);
+            assert!(matches!(BoardB::from_fen(&fen), Err(FenError::EnPassant)), "{}", square);
+        }
+    }
+
+    #[test]
+    fn see_counts_xrays() {
+        //the rook on d1 backs up the one on d2, in the last one the queen on d8 the rook on d7
+        let (board, _) = BoardB::from_fen("3r4/8/8/3n4/8/8/3R4/3RK2k w - - 0 1").unwrap();
+        let knight = Piece::new(PieceType::Knight, Side::Black).value();
+        let rook = Piece::new(PieceType::Rook, Side::White).value();
+        assert_eq!(board.see(sq("d2"), sq("d5")), knight);
+        let (board, _) = BoardB::from_fen("3r4/8/8/3n4/8/8/3R4/4K2k w - - 0 1").unwrap();
+        assert_eq!(board.see(sq("d2"), sq("d5")), knight - rook);
+        let (board, _) = BoardB::from_fen("3q4/3r4/8/3n4/8/8/3R4/3RK2k w - - 0 1").unwrap();
+        assert_eq!(board.xray_attackers(sq("d5"), Side::White), 1 << sq("d1"));
+        assert_eq!(board.xray_attackers(sq("d5"), Side::Black), 1 << sq("d8"));
+        assert_eq!(board.see(sq("d2"), sq("d5")), knight - rook);
+        //without anything lined up the attackers are found once
+        let (board, _) = BoardB::from_fen("7k/8/4p3/3n4/8/2N5/8/4K3 w - - 0 1").unwrap();
+        assert_eq!(board.xray_attackers(sq("d5"), Side::White) | board.xray_attackers(sq("d5"), Side::Black), 0);
+        assert_eq!(board.see(sq("c3"), sq("d5")), 0);
+    }
+
+    fn generated(board: &BoardB, turn: Side, generate: fn(&BoardB, Side, &mut MoveList)) -> Vec<(u8, u8)> {
+        let mut mvs = MoveList::new();
+        generate(board, turn, &mut mvs);
+        let mut mvs = mvs.as_slice().to_vec();
+        mvs.sort_unstable();
+        mvs
+    }
+
+    #[test]
+    fn generators_match_legal_moves() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R b KQkq - 0 1",
+            "8/2p5/3p4/KP5r/1R3p1k/8/4P1P1/8 w - - 0 1",
+            "r3k2r/Pppp1ppp/1b3nbN/nP6/BBP1P3/q4N2/Pp1P2PP/R2Q1RK1 w kq - 0 1",
+            "rnbq1k1r/pp1Pbppp/2p5/8/2B5/8/PPP1NnPP/RNBQK2R w KQ - 1 8",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+            //in check: single, double, and one that en passant answers
+            "4k3/8/8/8/8/8/3q4/4K3 w - - 0 1",
+            "4k3/8/8/8/1b6/8/8/R3K2r w - - 0 1",
+            "8/8/8/2k5/3Pp3/8/8/4K3 b - d3 0 1",
+        ];
+        for fen in fens {
+            let (board, turn) = BoardB::from_fen(fen).unwrap();
+            let legal = search::legal_moves(&board, turn);
+            let capture = |&(from, to): &(u8, u8)| {
+                let pawn = board.board[from as usize].is_some_and(|p| p.ty == PieceType::Pawn);
+                board.occupied(to) || pawn && (from % 8 != to % 8 || to / 8 == 0 || to / 8 == 7)
+            };
+            let gives_check = |&(from, to): &(u8, u8)| {
+                let mut b = board;
+                b.make_move(from, to);
+                b.checkers(!turn) > 0
+            };
+            let captures: Vec<_> = legal.iter().copied().filter(capture).collect();
+            assert_eq!(generated(&board, turn, BoardB::captures), captures, "{}", fen);
+            let checks: Vec<_> = legal.iter().copied().filter(|mv| !capture(mv) && gives_check(mv)).collect();
+            assert_eq!(generated(&board, turn, BoardB::quiet_checks), checks, "{}", fen);
+            let evasions = if board.checkers(turn) > 0 { legal } else { Vec::new() };
+            assert_eq!(generated(&board, turn, BoardB::evasions), evasions, "{}", fen);
+        }
+    }
+}
@@ -0,0 +1,276 @@
+use std::{path::Path, collections::HashMap, io::Write};
+
+use rand::Rng;
+
+use crate::{boardb::{BoardB, Pos, PosTrait}, pieces::{Side, PieceType}};
+
+
+
+//polyglot .bin books, see http://hgm.nubati.net/book_format.html
+//every entry is 16 bytes big endian: key u64 | move u16 | weight u16 | learn u32, sorted by key
+const ENTRY_SIZE: usize = 16;
+
+#[derive(Debug)]
+pub enum BookError {
+    Io(std::io::Error),
+    //file length is not a multiple of the entry size
+    Size(usize),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct BookMove {
+    pub from: u8,
+    pub to: u8,
+    pub weight: u16,
+}
+
+#[derive(Clone, Copy)]
+struct BookEntry {
+    key: u64,
+    mv: u16,
+    weight: u16,
+}
+
+pub struct OpeningBook {
+    entries: Vec<BookEntry>,
+}
+
+impl OpeningBook {
+    pub fn load(path: &Path) -> Result<Self, BookError> {
+        Self::from_bytes(&std::fs::read(path).map_err(BookError::Io)?)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, BookError> {
+        if bytes.len() % ENTRY_SIZE != 0 {
+            return Err(BookError::Size(bytes.len()));
+        }
+        let mut entries: Vec<BookEntry> = bytes.chunks_exact(ENTRY_SIZE)
+            .map(|e| BookEntry {
+                key: u64::from_be_bytes(e[0..8].try_into().unwrap()),
+                mv: u16::from_be_bytes([e[8], e[9]]),
+                weight: u16::from_be_bytes([e[10], e[11]]),
+            })
+            .collect();
+        //books should already be sorted, but lookups rely on it
+        entries.sort_by_key(|e| e.key);
+        Ok(Self { entries })
+    }
+
+    //(key, move, weight) triples, see encode_move
+    pub fn from_entries(entries: impl IntoIterator<Item = (u64, u16, u16)>) -> Self {
+        let mut entries: Vec<BookEntry> = entries.into_iter()
+            .map(|(key, mv, weight)| BookEntry { key, mv, weight })
+            .collect();
+        entries.sort_by_key(|e| (e.key, std::cmp::Reverse(e.weight)));
+        Self { entries }
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), BookError> {
+        let mut bytes = Vec::with_capacity(self.entries.len() * ENTRY_SIZE);
+        for e in &self.entries {
+            bytes.extend(e.key.to_be_bytes());
+            bytes.extend(e.mv.to_be_bytes());
+            bytes.extend(e.weight.to_be_bytes());
+            //learn field, unused
+            bytes.extend(0_u32.to_be_bytes());
+        }
+        std::fs::File::create(path)
+            .and_then(|mut f| f.write_all(&bytes))
+            .map_err(BookError::Io)
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    //legal book moves for the position, heaviest first
+    pub fn moves(&self, board: &BoardB, turn: Side) -> Vec<BookMove> {
+        let key = board.hash(turn);
+        let start = self.entries.partition_point(|e| e.key < key);
+        let mut legal = HashMap::with_capacity(16);
+        board.valid_moves(turn, &mut legal);
+        let mut mvs: Vec<BookMove> = self.entries[start..].iter()
+            .take_while(|e| e.key == key)
+            .filter_map(|e| {
+                let (from, to) = decode_move(board, e.mv);
+                let legal_tos = legal.get(&from)?;
+                (legal_tos & 1 << to != 0).then_some(BookMove { from, to, weight: e.weight })
+            })
+            .collect();
+        mvs.sort_by_key(|mv| std::cmp::Reverse(mv.weight));
+        mvs
+    }
+
+    //random book move weighted by the book weights, or always the heaviest one if best_only
+    pub fn pick<R: Rng>(&self, board: &BoardB, turn: Side, best_only: bool, rng: &mut R) -> Option<BookMove> {
+        let mvs = self.moves(board, turn);
+        if best_only {
+            return mvs.first().copied();
+        }
+        let total: u32 = mvs.iter().map(|mv| mv.weight as u32).sum();
+        if total == 0 {
+            return mvs.first().copied();
+        }
+        let mut n = rng.gen_range(0..total);
+        for mv in &mvs {
+            if n < mv.weight as u32 {
+                return Some(*mv);
+            }
+            n -= mv.weight as u32;
+        }
+        None
+    }
+}
+
+//to file 0..3 | to row 3..6 | from file 6..9 | from row 9..12 | promotion 12..15, rows counted from white's side
+fn decode_move(board: &BoardB, mv: u16) -> (u8, u8) {
+    let square = |file: u16, row: u16| Pos::new(file as i8, 7 - row as i8);
+    let from = square(mv >> 6 & 7, mv >> 9 & 7);
+    let mut to = square(mv & 7, mv >> 3 & 7);
+    //castling is stored as king takes rook
+    let is_king = board.get_piece_at_pos(from.to_i()).is_some_and(|p| p.ty == PieceType::King);
+    if is_king && (to.x - from.x).abs() > 1 {
+        to.x = if to.x > from.x {6} else {2};
+    }
+    (from.to_i(), to.to_i())
+}
+
+pub fn encode_move(board: &BoardB, from: u8, to: u8, promotion: Option<PieceType>) -> u16 {
+    let from_pos = Pos::from_i(from);
+    let mut to_pos = Pos::from_i(to);
+    let is_king = board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::King);
+    if is_king && (to_pos.x - from_pos.x).abs() > 1 {
+        to_pos.x = if to_pos.x > from_pos.x {7} else {0};
+    }
+    let promotion = match promotion {
+        Some(PieceType::Knight) => 1,
+        Some(PieceType::Bishop) => 2,
+        Some(PieceType::Rook) => 3,
+        Some(PieceType::Queen) => 4,
+        _ => 0,
+    };
+    to_pos.x as u16 | ((7 - to_pos.y) as u16) << 3 | (from_pos.x as u16) << 6 | ((7 - from_pos.y) as u16) << 9 | promotion << 12
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+
+    use crate::san::square_from_str;
+    use super::*;
+
+
+
+    //start position key from the polyglot spec, e2e4 60, d2d4 30, g1f3 10 and the illegal e2e5
+    const START_ENTRIES: [[u8; 16]; 4] = [
+        [0x46, 0x3b, 0x96, 0x18, 0x16, 0x91, 0xfc, 0x9c, 0x03, 0x1c, 0x00, 0x3c, 0, 0, 0, 0],
+        [0x46, 0x3b, 0x96, 0x18, 0x16, 0x91, 0xfc, 0x9c, 0x02, 0xdb, 0x00, 0x1e, 0, 0, 0, 0],
+        [0x46, 0x3b, 0x96, 0x18, 0x16, 0x91, 0xfc, 0x9c, 0x01, 0x95, 0x00, 0x0a, 0, 0, 0, 0],
+        [0x46, 0x3b, 0x96, 0x18, 0x16, 0x91, 0xfc, 0x9c, 0x03, 0x24, 0x00, 0x64, 0, 0, 0, 0],
+    ];
+    //a different key that sorts before the start position
+    const OTHER_ENTRY: [u8; 16] = [0x00, 0x3b, 0x96, 0x18, 0x16, 0x91, 0xfc, 0x9c, 0x03, 0x1c, 0x7f, 0xff, 0, 0, 0, 0];
+
+    fn sq(s: &str) -> u8 {
+        square_from_str(s).unwrap()
+    }
+
+    fn start_book() -> OpeningBook {
+        let bytes: Vec<u8> = START_ENTRIES.iter().rev().chain([&OTHER_ENTRY]).flatten().copied().collect();
+        OpeningBook::from_bytes(&bytes).unwrap()
+    }
+
+    fn play(moves: &[(&str, &str)]) -> (BoardB, Side) {
+        let mut board = BoardB::with_starting_pos();
+        let mut turn = Side::White;
+        for (from, to) in moves {
+            board.make_move(sq(from), sq(to));
+            turn = !turn;
+        }
+        (board, turn)
+    }
+
+    #[test]
+    fn keys_match_polyglot() {
+        //test positions from the polyglot spec, the last one has an en passant capture
+        let cases: [(&[(&str, &str)], u64); 5] = [
+            (&[], 0x463b96181691fc9c),
+            (&[("e2", "e4")], 0x823c9b50fd114196),
+            (&[("e2", "e4"), ("d7", "d5")], 0x0756b94461c50fb0),
+            (&[("e2", "e4"), ("d7", "d5"), ("e4", "e5")], 0x662fafb965db29d4),
+            (&[("e2", "e4"), ("d7", "d5"), ("e4", "e5"), ("f7", "f5")], 0x22a48b5a8e47ff78),
+        ];
+        for (moves, key) in cases {
+            let (board, turn) = play(moves);
+            assert_eq!(board.hash(turn), key, "{:?}", moves);
+        }
+    }
+
+    #[test]
+    fn lookup() {
+        let book = start_book();
+        assert!(matches!(OpeningBook::from_bytes(&[0; 17]), Err(BookError::Size(17))));
+        assert_eq!(book.len(), 5);
+        let (board, turn) = play(&[]);
+        let mvs: Vec<(u8, u8, u16)> = book.moves(&board, turn).iter().map(|mv| (mv.from, mv.to, mv.weight)).collect();
+        assert_eq!(mvs, [(sq("e2"), sq("e4"), 60), (sq("d2"), sq("d4"), 30), (sq("g1"), sq("f3"), 10)]);
+        let (board, turn) = play(&[("e2", "e4")]);
+        assert!(book.moves(&board, turn).is_empty());
+    }
+
+    #[test]
+    fn best_only() {
+        let book = start_book();
+        let (board, turn) = play(&[]);
+        let mut rng = StdRng::seed_from_u64(1);
+        for _ in 0..20 {
+            let mv = book.pick(&board, turn, true, &mut rng).unwrap();
+            assert_eq!((mv.from, mv.to), (sq("e2"), sq("e4")));
+        }
+    }
+
+    #[test]
+    fn weighted_pick() {
+        let book = start_book();
+        let (board, turn) = play(&[]);
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut counts = HashMap::new();
+        for _ in 0..10000 {
+            let mv = book.pick(&board, turn, false, &mut rng).unwrap();
+            *counts.entry(mv.from).or_insert(0) += 1;
+        }
+        //60/30/10 percent, the illegal heavy entry is never picked
+        assert_eq!(counts.len(), 3);
+        for (from, expected) in [("e2", 6000), ("d2", 3000), ("g1", 1000)] {
+            let count: i32 = counts[&sq(from)];
+            assert!((count - expected).abs() < 300, "{} picked {} times", from, count);
+        }
+    }
+
+    #[test]
+    fn castling() {
+        let (board, turn) = BoardB::from_fen("r3k2r/8/8/8/8/8/8/R3K2R w KQkq - 0 1").unwrap();
+        let key = board.hash(turn).to_be_bytes();
+        //e1h1 and e1a1, king takes rook
+        let mut bytes = Vec::new();
+        for mv in [[0x01, 0x07], [0x01, 0x00]] {
+            bytes.extend(key);
+            bytes.extend(mv);
+            bytes.extend([0, 1, 0, 0, 0, 0]);
+        }
+        let book = OpeningBook::from_bytes(&bytes).unwrap();
+        let mut mvs: Vec<(u8, u8)> = book.moves(&board, turn).iter().map(|mv| (mv.from, mv.to)).collect();
+        mvs.sort();
+        let mut expected = [(sq("e1"), sq("g1")), (sq("e1"), sq("c1"))];
+        expected.sort();
+        assert_eq!(mvs, expected);
+        assert_eq!(encode_move(&board, sq("e1"), sq("g1"), None), 0x0107);
+        assert_eq!(encode_move(&board, sq("e1"), sq("c1"), None), 0x0100);
+    }
+}
@@ -0,0 +1,105 @@
+use std::{collections::HashMap, fs::File, io::BufReader, path::{Path, PathBuf}};
+
+use crate::{book::{self, OpeningBook, BookError}, pgn::PgnError, pgn_tree::PgnTreeReader, pieces::Side};
+
+
+
+pub struct BookFilter {
+    //games shorter than this are skipped
+    pub min_ply: usize,
+    //only the first max_ply half moves of a game go into the book
+    pub max_ply: usize,
+    //both players need at least this rating, games without ratings count as 0
+    pub min_elo: u32,
+    //accepted result tokens, all if empty
+    pub results: Vec<String>,
+}
+
+impl Default for BookFilter {
+    fn default() -> Self {
+        Self { min_ply: 0, max_ply: 30, min_elo: 0, results: Vec::new() }
+    }
+}
+
+#[derive(Debug)]
+pub enum BuildError {
+    Io(std::io::Error),
+    Book(BookError),
+}
+
+#[derive(Default, Debug)]
+pub struct BuildStats {
+    pub games: usize,
+    pub used: usize,
+    pub invalid: usize,
+    pub entries: usize,
+}
+
+//builds a polyglot book from every .pgn file in dir
+pub fn build(dir: &Path, out: &Path, filter: &BookFilter) -> Result<BuildStats, BuildError> {
+    let mut files: Vec<PathBuf> = std::fs::read_dir(dir).map_err(BuildError::Io)?
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("pgn")))
+        .collect();
+    files.sort();
+
+    let mut stats = BuildStats::default();
+    //summed half points from the view of the side that played the move,
+    //so the weight grows both with how often and how successfully a move was played
+    let mut moves: HashMap<(u64, u16), u32> = HashMap::new();
+    for path in files {
+        let reader = PgnTreeReader::new(BufReader::new(File::open(&path).map_err(BuildError::Io)?));
+        for game in reader {
+            stats.games += 1;
+            let game = match game {
+                Ok(game) => game,
+                Err(PgnError::Io(err)) => return Err(BuildError::Io(err)),
+                Err(err) => {
+                    eprintln!("{}: skipping game {}: {:?}", path.display(), stats.games, err);
+                    stats.invalid += 1;
+                    continue;
+                }
+            };
+            let elo = |tag| game.tag(tag).and_then(|e| e.parse::<u32>().ok()).unwrap_or(0);
+            if game.moves.len() < filter.min_ply
+                || elo("WhiteElo").min(elo("BlackElo")) < filter.min_elo
+                || (!filter.results.is_empty() && !filter.results.contains(&game.result)) {
+                continue;
+            }
+            stats.used += 1;
+            for (board, turn, mv) in game.replay().into_iter().take(filter.max_ply) {
+                //win = 2, draw = 1, loss = 0 for the side that moved, unknown results count as draw
+                let score = match (game.result.as_str(), turn) {
+                    ("1-0", Side::White) | ("0-1", Side::Black) => 2,
+                    ("1-0", Side::Black) | ("0-1", Side::White) => 0,
+                    _ => 1,
+                };
+                let key = (board.hash(turn), book::encode_move(&board, mv.from, mv.to, mv.promotion));
+                *moves.entry(key).or_default() += score;
+            }
+        }
+    }
+
+    //moves that only ever lost are left out
+    let mut max_weight_by_key: HashMap<u64, u32> = HashMap::new();
+    for ((key, _), score) in &moves {
+        let max = max_weight_by_key.entry(*key).or_default();
+        *max = (*max).max(*score);
+    }
+    let entries = moves.into_iter()
+        .filter(|(_, score)| *score > 0)
+        .map(|((key, mv), score)| {
+            //keep the ratio between moves of one position if the weight does not fit into 16 bits
+            let max = max_weight_by_key[&key];
+            let weight = if max > u16::MAX as u32 {
+                (score as u64 * u16::MAX as u64 / max as u64).max(1) as u16
+            } else {
+                score as u16
+            };
+            (key, mv, weight)
+        });
+    let book = OpeningBook::from_entries(entries);
+    stats.entries = book.len();
+    book.save(out).map_err(BuildError::Book)?;
+    Ok(stats)
+}
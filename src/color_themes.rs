@@ -12,11 +12,14 @@ pub struct ColorTheme {
     pub last_move_primary: Color,
     pub last_move_secondary: Color,
     pub progress: Color,
+    //last move highlight when it was played from the opening book
+    pub book_primary: Color,
+    pub book_secondary: Color,
 }
 
 impl ColorTheme {
-    pub fn new(board_primary: Color, board_secondary: Color, valid_moves: Color, selection: Color, check: Color, last_move: Color, last_move_primary: Color,  last_move_secondary: Color, progress: Color) -> Self {
-        Self {board_primary, board_secondary, valid_moves, selection, check, last_move_primary,  last_move_secondary, progress}
+    pub fn new(board_primary: Color, board_secondary: Color, valid_moves: Color, selection: Color, check: Color, last_move: Color, last_move_primary: Color,  last_move_secondary: Color, progress: Color, book_primary: Color, book_secondary: Color) -> Self {
+        Self {board_primary, board_secondary, valid_moves, selection, check, last_move_primary,  last_move_secondary, progress, book_primary, book_secondary}
     }
 
     pub fn blue_theme() -> Self {
@@ -28,7 +31,9 @@ impl ColorTheme {
             last_move_primary: Color::RGB(169,202,142),
             last_move_secondary: Color::RGB(124,172,112),
             check: Color::RGB(230,55,96),
-            progress: Color::RGB(9, 132, 227)
+            progress: Color::RGB(9, 132, 227),
+            book_primary: Color::RGB(196,160,224),
+            book_secondary: Color::RGB(156,112,196)
         }
     }
 
@@ -41,7 +46,9 @@ impl ColorTheme {
             last_move_primary: Color::RGB(226,242,108),
             last_move_secondary: Color::RGB(186,202,68),
             check: Color::RGB(230,55,96),
-            progress: Color::RGB(136, 206, 106)
+            progress: Color::RGB(136, 206, 106),
+            book_primary: Color::RGB(196,160,224),
+            book_secondary: Color::RGB(156,112,196)
         }
     }
 
@@ -54,7 +61,9 @@ impl ColorTheme {
             last_move_primary: Color::RGB(226,242,108),
             last_move_secondary: Color::RGB(186,202,68),
             check: Color::RGB(13,56,166),
-            progress: Color::RGB(253, 121, 168)
+            progress: Color::RGB(253, 121, 168),
+            book_primary: Color::RGB(129,236,236),
+            book_secondary: Color::RGB(0,206,201)
         }
     }
 
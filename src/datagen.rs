@@ -0,0 +1,125 @@
+use std::{collections::HashSet, fs::File, io::{BufWriter, Write}, path::Path, sync::{atomic::{AtomicUsize, Ordering}, Mutex}};
+
+use rand::{Rng, seq::SliceRandom};
+
+use crate::{gameb::{GameB, GameState}, pieces::Side, seed, search::{self, SearchConfig}, tt::TranspositionTable};
+
+
+
+//Self-play training data. Every line of the output is one position:
+//  <fen> | <score> | <result>
+//score is the search score in centipawns and result 1.0 / 0.5 / 0.0 the final game result,
+//both from white's view. Positions in check and the random opening moves are left out.
+pub struct DatagenConfig {
+    pub games: usize,
+    pub threads: usize,
+    pub depth: usize,
+    //searches stop after this many nodes, depth is still the upper bound
+    pub nodes: Option<u64>,
+    //random moves at the start of each game, so games don't repeat
+    pub random_plies: usize,
+    //games still running after this many plies count as draws
+    pub max_plies: usize,
+    pub hash_mb: usize,
+}
+
+impl Default for DatagenConfig {
+    fn default() -> Self {
+        Self { games: 100, threads: 1, depth: 4, nodes: None, random_plies: 8, max_plies: 300, hash_mb: 16 }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct DatagenStats {
+    pub games: usize,
+    pub positions: usize,
+    pub duplicates: usize,
+}
+
+struct Output {
+    writer: BufWriter<File>,
+    //positions already written, by hash
+    seen: HashSet<u64>,
+    stats: DatagenStats,
+}
+
+pub fn run(out: &Path, config: &DatagenConfig) -> Result<DatagenStats, std::io::Error> {
+    let output = Mutex::new(Output { writer: BufWriter::new(File::create(out)?), seen: HashSet::new(), stats: DatagenStats::default() });
+    let next_game = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..config.threads.max(1)).map(|_| s.spawn(|| -> Result<(), std::io::Error> {
+            //every thread plays its own games with a single threaded search
+            let tt = TranspositionTable::new(config.hash_mb);
+            loop {
+                let n = next_game.fetch_add(1, Ordering::Relaxed);
+                if n >= config.games {
+                    return Ok(());
+                }
+                tt.clear();
+                //by game, not by thread, which thread plays which game is up to timing
+                let Some((positions, result)) = play_game(config, &tt, &mut seed::rng(n as u64)) else {
+                    continue;
+                };
+                let mut output = output.lock().expect("Datagen output poisoned");
+                let output = &mut *output;
+                output.stats.games += 1;
+                for (fen, hash, score) in positions {
+                    if !output.seen.insert(hash) {
+                        output.stats.duplicates += 1;
+                        continue;
+                    }
+                    writeln!(output.writer, "{} | {} | {:.1}", fen, score, result)?;
+                    output.stats.positions += 1;
+                }
+                if output.stats.games % 10 == 0 {
+                    println!("{} games, {} positions", output.stats.games, output.stats.positions);
+                }
+            }
+        })).collect();
+        handles.into_iter().try_for_each(|h| h.join().expect("Datagen thread panicked"))
+    })?;
+    let mut output = output.into_inner().expect("Datagen output poisoned");
+    output.writer.flush()?;
+    Ok(output.stats)
+}
+
+//(fen, hash, white score)
+type Sample = (String, u64, i32);
+
+//every recorded position and the result for white, None if the random opening already ended the game
+fn play_game<R: Rng>(config: &DatagenConfig, tt: &TranspositionTable, rng: &mut R) -> Option<(Vec<Sample>, f32)> {
+    let mut game = GameB::vcpu(config.depth);
+    for _ in 0..config.random_plies {
+        let (from, to) = *search::legal_moves(&game.board, game.turn).choose(rng)?;
+        game.make_move(from, to);
+        if game.state != GameState::Running {
+            return None;
+        }
+    }
+
+    let mut positions = Vec::new();
+    let search_config = SearchConfig { depth: config.depth, threads: 1, nodes: config.nodes, ..Default::default() };
+    let mut adjudicated = None;
+    while game.state == GameState::Running && game.ply < config.max_plies {
+        let result = search::search(game.board, game.turn, search_config, tt, None);
+        let Some((from, to)) = result.mv else {
+            break;
+        };
+        let white_score = if game.turn == Side::White {result.score} else {-result.score};
+        //a found mate or tablebase win decides the game, no need to play it out
+        if search::is_decisive(result.score) {
+            adjudicated = Some(if white_score > 0 {1.0} else {0.0});
+            break;
+        }
+        if !search::in_check(&game.board, game.turn) {
+            positions.push((game.to_fen(), game.board.hash(game.turn), white_score));
+        }
+        game.make_move(from, to);
+    }
+    let result = adjudicated.unwrap_or(match game.state {
+        GameState::Winner(Side::White) => 1.0,
+        GameState::Winner(Side::Black) => 0.0,
+        _ => 0.5,
+    });
+    Some((positions, result))
+}
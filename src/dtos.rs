@@ -17,6 +17,22 @@ pub struct Move {
     pub y2: i8,
 }
 
+//plies to take back, asked for by one player and accepted by the other
+#[serializable]
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Takeback {
+    Request(u32),
+    Accept(u32),
+}
+
+//everything that goes over the connection once the game runs
+#[serializable]
+#[derive(Debug)]
+pub enum GameMessage {
+    Move(Move),
+    Takeback(Takeback),
+}
+
 #[serializable]
 pub struct GameInfo {
     pub other_player: String,
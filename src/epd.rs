@@ -0,0 +1,306 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, time::Instant};
+
+use crate::{boardb::BoardB, pieces::Side, san, search::{self, SearchConfig}, tt::TranspositionTable, uci};
+
+
+
+#[derive(Debug)]
+pub enum EpdError {
+    Io(std::io::Error),
+    //(line, reason)
+    Parse(usize, String),
+    NoPositions,
+}
+
+//Test suites like WAC or ECM: every position has best moves (bm) or moves to avoid (am), a
+//position is solved if the engine's move is one of the best moves and none of the avoided ones.
+pub struct EpdConfig {
+    //positions searched at the same time, every one single threaded
+    pub threads: usize,
+    pub depth: Option<usize>,
+    //milliseconds per position
+    pub movetime: Option<u64>,
+    pub hash_mb: usize,
+    //csv with one row per position
+    pub output: Option<PathBuf>,
+}
+
+impl Default for EpdConfig {
+    fn default() -> Self {
+        Self { threads: 1, depth: None, movetime: None, hash_mb: 16, output: None }
+    }
+}
+
+#[derive(Default, Debug)]
+pub struct EpdStats {
+    pub positions: usize,
+    pub solved: usize,
+    pub nodes: u64,
+    pub ms: u64,
+}
+
+pub struct EpdPosition {
+    //from the id opcode, the line number if there is none
+    pub id: String,
+    pub board: BoardB,
+    pub turn: Side,
+    pub best: Vec<(u8, u8)>,
+    pub avoid: Vec<(u8, u8)>,
+    //operands as written in the file, for the report
+    pub expected: String,
+}
+
+struct Solution {
+    mv: String,
+    solved: bool,
+    depth: usize,
+    nodes: u64,
+    ms: u64,
+    //depth and time from which on the engine kept a solving move
+    found: Option<(usize, u64)>,
+}
+
+pub fn run(path: &Path, config: &EpdConfig) -> Result<EpdStats, EpdError> {
+    let positions = load(path)?;
+    if positions.is_empty() {
+        return Err(EpdError::NoPositions);
+    }
+    //without a depth only the time ends the search
+    let search_config = SearchConfig { depth: config.depth.unwrap_or(search::MAX_DEPTH), ..Default::default() };
+    let solutions: Mutex<Vec<Option<Solution>>> = Mutex::new((0..positions.len()).map(|_| None).collect());
+    let next = AtomicUsize::new(0);
+    std::thread::scope(|s| {
+        for _ in 0..config.threads.max(1) {
+            s.spawn(|| {
+                let tt = TranspositionTable::new(config.hash_mb);
+                loop {
+                    let n = next.fetch_add(1, Ordering::Relaxed);
+                    let Some(position) = positions.get(n) else {
+                        return;
+                    };
+                    tt.clear();
+                    let solution = solve(position, search_config, config.movetime, &tt);
+                    println!(
+                        "{:<12} {:<7} {:<8} ({}{})  depth {}  {:.2}s",
+                        position.id, if solution.solved {"solved"} else {"failed"}, solution.mv,
+                        if position.best.is_empty() {"am "} else {"bm "}, position.expected, solution.depth, solution.ms as f64 / 1000.0
+                    );
+                    solutions.lock().expect("Epd results poisoned")[n] = Some(solution);
+                }
+            });
+        }
+    });
+
+    let solutions: Vec<Solution> = solutions.into_inner().expect("Epd results poisoned").into_iter().flatten().collect();
+    if let Some(out) = &config.output {
+        write_results(out, &positions, &solutions).map_err(EpdError::Io)?;
+    }
+    let mut stats = EpdStats { positions: solutions.len(), ..Default::default() };
+    for solution in &solutions {
+        stats.solved += solution.solved as usize;
+        stats.nodes += solution.nodes;
+        stats.ms += solution.ms;
+    }
+    Ok(stats)
+}
+
+fn solve(position: &EpdPosition, config: SearchConfig, movetime: Option<u64>, tt: &TranspositionTable) -> Solution {
+    let solves = |mv: (u8, u8)| (position.best.is_empty() || position.best.contains(&mv)) && !position.avoid.contains(&mv);
+    let stop = Arc::new(AtomicBool::new(false));
+    if let Some(ms) = movetime {
+        uci::stop_after(stop.clone(), ms);
+    }
+    let start = Instant::now();
+    let mut found = None;
+    let result = search::analyse(position.board, position.turn, config, tt, &stop, &mut |result| {
+        match result.mv {
+            Some(mv) if solves(mv) => {
+                found.get_or_insert((result.depth, start.elapsed().as_millis() as u64));
+            },
+            _ => found = None,
+        }
+    });
+    let ms = start.elapsed().as_millis() as u64;
+    match result.mv {
+        //the move can come from an iteration the time cut short, it was never reported
+        Some((from, to)) if solves((from, to)) => Solution {
+            mv: san::to_san(&position.board, position.turn, from, to),
+            solved: true,
+            depth: result.depth,
+            nodes: result.nodes,
+            ms,
+            found: found.or(Some((result.depth, ms))),
+        },
+        Some((from, to)) => Solution {
+            mv: san::to_san(&position.board, position.turn, from, to),
+            solved: false,
+            depth: result.depth,
+            nodes: result.nodes,
+            ms,
+            found: None,
+        },
+        None => Solution { mv: "-".to_owned(), solved: false, depth: result.depth, nodes: result.nodes, ms, found: None },
+    }
+}
+
+fn write_results(out: &Path, positions: &[EpdPosition], solutions: &[Solution]) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(out)?);
+    writeln!(writer, "id,solved,move,expected,depth,nodes,ms,found_depth,found_ms")?;
+    for (position, solution) in positions.iter().zip(solutions) {
+        let (found_depth, found_ms) = solution.found.map_or((String::new(), String::new()), |(d, ms)| (d.to_string(), ms.to_string()));
+        writeln!(
+            writer, "\"{}\",{},{},\"{}{}\",{},{},{},{},{}",
+            position.id.replace('"', "\"\""), solution.solved as u8, solution.mv, if position.best.is_empty() {"am "} else {"bm "},
+            position.expected, solution.depth, solution.nodes, solution.ms, found_depth, found_ms
+        )?;
+    }
+    writer.flush()
+}
+
+//one position per line, the four fen fields followed by operations like bm Qxf7+; id "WAC.001";
+pub fn load(path: &Path) -> Result<Vec<EpdPosition>, EpdError> {
+    let reader = BufReader::new(File::open(path).map_err(EpdError::Io)?);
+    let mut positions = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(EpdError::Io)?;
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let mut position = parse_line(&line).map_err(|reason| EpdError::Parse(n + 1, reason))?;
+        if position.id.is_empty() {
+            position.id = format!("line {}", n + 1);
+        }
+        positions.push(position);
+    }
+    Ok(positions)
+}
+
+fn parse_line(line: &str) -> Result<EpdPosition, String> {
+    let fields: Vec<&str> = line.splitn(5, ' ').collect();
+    if fields.len() < 4 {
+        return Err("missing fen fields".to_owned());
+    }
+    let (board, turn) = BoardB::from_fen(&format!("{} 0 1", fields[..4].join(" "))).map_err(|err| format!("invalid fen {:?}", err))?;
+    let mut position = EpdPosition { id: String::new(), board, turn, best: Vec::new(), avoid: Vec::new(), expected: String::new() };
+    for (opcode, operands) in operations(fields.get(4).unwrap_or(&"")) {
+        let moves = || operands.iter().map(|mv| parse_move(&board, turn, mv).ok_or_else(|| format!("illegal move {}", mv))).collect::<Result<Vec<_>, _>>();
+        match opcode.as_str() {
+            "bm" => {
+                position.best = moves()?;
+                position.expected = operands.join(" ");
+            },
+            "am" => {
+                position.avoid = moves()?;
+                if position.best.is_empty() {
+                    position.expected = operands.join(" ");
+                }
+            },
+            "id" => position.id = operands.join(" "),
+            //c0, ce, pv and the like don't matter for solving
+            _ => {},
+        }
+    }
+    if position.best.is_empty() && position.avoid.is_empty() {
+        return Err("no bm or am operation".to_owned());
+    }
+    Ok(position)
+}
+
+//splits 'bm Nf6 Ng5; id "WAC 1";' into opcodes and operands, quoted operands keep their spaces and semicolons
+fn operations(text: &str) -> Vec<(String, Vec<String>)> {
+    let mut operations = Vec::new();
+    let mut tokens: Vec<String> = Vec::new();
+    let mut token = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' | ' ' | '\t' if !quoted => {
+                if !token.is_empty() {
+                    tokens.push(std::mem::take(&mut token));
+                }
+                if c == ';' && !tokens.is_empty() {
+                    let opcode = tokens.remove(0);
+                    operations.push((opcode, std::mem::take(&mut tokens)));
+                }
+            },
+            _ => token.push(c),
+        }
+    }
+    if !token.is_empty() {
+        tokens.push(token);
+    }
+    if !tokens.is_empty() {
+        let opcode = tokens.remove(0);
+        operations.push((opcode, tokens));
+    }
+    operations
+}
+
+//suites mostly use SAN, a few use coordinates like e2e4
+fn parse_move(board: &BoardB, turn: Side, mv: &str) -> Option<(u8, u8)> {
+    match san::parse_san(board, turn, mv) {
+        Ok(mv) => Some((mv.from, mv.to)),
+        Err(_) => uci::parse_move(board, turn, mv).map(|(from, to, _)| (from, to)),
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::san::square_from_str;
+    use super::*;
+
+
+
+    fn mv(s: &str) -> (u8, u8) {
+        (square_from_str(&s[..2]).unwrap(), square_from_str(&s[2..]).unwrap())
+    }
+
+    #[test]
+    fn operations_with_several_moves() {
+        let position = parse_line("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e4 d4 Nf3; am f3 g2g4; id \"start; 1\"; c0 \"any\";").unwrap();
+        assert_eq!(position.best, [mv("e2e4"), mv("d2d4"), mv("g1f3")]);
+        assert_eq!(position.avoid, [mv("f2f3"), mv("g2g4")]);
+        assert_eq!(position.id, "start; 1");
+        assert_eq!(position.expected, "e4 d4 Nf3");
+        assert!(position.turn == Side::White);
+
+        let position = parse_line("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - am Ra8#").unwrap();
+        assert!(position.best.is_empty() && position.id.is_empty());
+        assert_eq!(position.expected, "Ra8#");
+    }
+
+    #[test]
+    fn broken_lines() {
+        for line in [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - bm e5;",
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - id \"no moves\";",
+        ] {
+            assert!(parse_line(line).is_err(), "{}", line);
+        }
+    }
+
+    //the mate is found at any depth, so bm passes and am fails
+    #[test]
+    fn pass_and_fail() {
+        let dir = std::env::temp_dir().join(format!("chess-cp-epd-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let suite = dir.join("suite.epd");
+        let csv = dir.join("results.csv");
+        let config = EpdConfig { depth: Some(2), output: Some(csv.clone()), ..EpdConfig::default() };
+        let mut results = Vec::new();
+        for op in ["bm", "am"] {
+            std::fs::write(&suite, format!("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - {} Ra8#; id \"back rank\";\n", op)).unwrap();
+            let stats = run(&suite, &config).unwrap();
+            results.push((stats.positions, stats.solved, std::fs::read_to_string(&csv).unwrap()));
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!((results[0].0, results[0].1), (1, 1));
+        assert!(results[0].2.lines().nth(1).unwrap().starts_with("\"back rank\",1,Ra8#,\"bm Ra8#\","), "{}", results[0].2);
+        assert_eq!((results[1].0, results[1].1), (1, 0));
+        assert!(results[1].2.lines().nth(1).unwrap().starts_with("\"back rank\",0,Ra8#,\"am Ra8#\","), "{}", results[1].2);
+    }
+}
@@ -0,0 +1,109 @@
+use std::{fs, path::Path, sync::OnceLock};
+
+use crate::{boardb::Piece, pieces::{Side, PieceType}};
+
+
+
+//Every weight of the handcrafted evaluation. Parameter files are plain text with one
+//"<name> <value>" per line, '#' starts a comment and missing names keep their default.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EvalParams {
+    pub pawn: i32,
+    pub knight: i32,
+    pub bishop: i32,
+    pub rook: i32,
+    pub queen: i32,
+}
+
+impl Default for EvalParams {
+    fn default() -> Self {
+        Self { pawn: 100, knight: 300, bishop: 300, rook: 500, queen: 900 }
+    }
+}
+
+#[derive(Debug)]
+pub enum EvalError {
+    Io(std::io::Error),
+    //(line, content)
+    Syntax(usize, String),
+    UnknownParam(String),
+}
+
+static PARAMS: OnceLock<EvalParams> = OnceLock::new();
+
+//has to happen before the first evaluation, afterwards the defaults are fixed
+pub fn init(params: EvalParams) {
+    if PARAMS.set(params).is_err() {
+        panic!("Evaluation parameters initialized twice");
+    }
+}
+
+pub fn params() -> &'static EvalParams {
+    PARAMS.get_or_init(EvalParams::default)
+}
+
+impl EvalParams {
+    pub const NAMES: [&'static str; 5] = ["pawn", "knight", "bishop", "rook", "queen"];
+
+    pub fn piece_value(&self, ty: PieceType) -> i32 {
+        match ty {
+            PieceType::Queen => self.queen,
+            PieceType::King => 0,
+            PieceType::Knight => self.knight,
+            PieceType::Bishop => self.bishop,
+            PieceType::Rook => self.rook,
+            PieceType::Pawn => self.pawn,
+        }
+    }
+
+    //centipawns from the view of side
+    pub fn evaluate(&self, board: &[Option<Piece>; 64], side: Side) -> i32 {
+        board.iter()
+            .flatten()
+            .map(|p| {
+                let s = if side == p.side {1} else {-1};
+                self.piece_value(p.ty) * s
+            })
+            .sum()
+    }
+
+    //all weights in NAMES order, for the tuner
+    pub fn get_mut(&mut self, n: usize) -> &mut i32 {
+        match n {
+            0 => &mut self.pawn,
+            1 => &mut self.knight,
+            2 => &mut self.bishop,
+            3 => &mut self.rook,
+            4 => &mut self.queen,
+            _ => panic!("No evaluation parameter {}", n),
+        }
+    }
+
+    pub fn get(&self, n: usize) -> i32 {
+        [self.pawn, self.knight, self.bishop, self.rook, self.queen][n]
+    }
+
+    pub fn load(path: &Path) -> Result<Self, EvalError> {
+        let mut params = Self::default();
+        for (n, line) in fs::read_to_string(path).map_err(EvalError::Io)?.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let syntax = || EvalError::Syntax(n + 1, line.to_owned());
+            let (name, value) = line.split_once(char::is_whitespace).ok_or_else(syntax)?;
+            let value = value.trim().parse::<i32>().map_err(|_| syntax())?;
+            let i = Self::NAMES.iter().position(|p| *p == name).ok_or_else(|| EvalError::UnknownParam(name.to_owned()))?;
+            *params.get_mut(i) = value;
+        }
+        Ok(params)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), EvalError> {
+        let mut text = String::new();
+        for (i, name) in Self::NAMES.iter().enumerate() {
+            text += &format!("{} {}\n", name, self.get(i));
+        }
+        fs::write(path, text).map_err(EvalError::Io)
+    }
+}
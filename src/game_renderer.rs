@@ -1,12 +1,18 @@
 use sdl2::{rect::{Rect, Point}, pixels::Color, render};
 use vecm::vec::{Vec2u, Vec2i};
 
-use crate::{color_themes::ColorTheme, pieces::{Side, PieceType}, renderer::{Renderer, self}, pos, gameb::GameB, boardb::{BoardB, Piece, Pos, PosTrait}};
+use crate::{color_themes::ColorTheme, pieces::{Side, PieceType}, renderer::{Renderer, self}, pos, gameb::GameB, review::Review, boardb::{BoardB, Piece, Pos, PosTrait}};
 
 
 
 
 
+//side panel right of the board, only shown while analysing or reviewing
+pub const ANALYSIS_WIDTH: u32 = 360;
+const PANEL_ROW: i32 = 24;
+//x offsets of move number, white's and black's move in the move list
+const MOVE_LIST_COLUMNS: [i32; 3] = [12, 72, 192];
+
 pub  struct GameRenderer {
     board_ground: Vec<(Rect, Color)>,
     //hovering: Option<u8>,
@@ -17,6 +23,8 @@ pub  struct GameRenderer {
     pub ai_progess: (Option<f32>, Option<f32>),
     mouse_pos: Vec2u,
     last_move: Option<(u8, u8)>,
+    //moves of the review panel as last drawn, with the ply after each
+    move_list: Vec<(Rect, usize)>,
     //animation
     valid_mvs_tick: f32,
     last_move_tick: f32,
@@ -54,6 +62,7 @@ impl GameRenderer {
             ai_progess: (None, None),
             animation_increment,
             last_move: None,
+            move_list: Vec::new(),
             mouse_pos: Vec2u::zero(), 
             s_tick: 0.0,
             p_tick: 0.0
@@ -91,8 +100,14 @@ impl GameRenderer {
                 let rect = Rect::from_center(Point::new(r_center.x, r_center.y), r_size.x, r_size.y);
                 renderer.draw_rect(rect, color, 3)
             };
-            draw_move(last_move.0, self.color_theme().last_move_primary);
-            draw_move(last_move.1, self.color_theme().last_move_secondary);
+            let theme = self.color_theme();
+            let (primary, secondary) = if game.last_move_from_book() {
+                (theme.book_primary, theme.book_secondary)
+            } else {
+                (theme.last_move_primary, theme.last_move_secondary)
+            };
+            draw_move(last_move.0, primary);
+            draw_move(last_move.1, secondary);
         }
         if game.last_move != self.last_move {
             self.last_move_tick = 0.0;
@@ -230,6 +245,60 @@ impl GameRenderer {
     }
 
 
+    pub fn draw_analysis(&mut self, rows: &[String], renderer: &mut Renderer) {
+        let left = self.field_size as i32 * 8;
+        let theme = self.color_theme();
+        renderer.draw_rect(Rect::new(left, 0, ANALYSIS_WIDTH, self.field_size * 8), theme.board_secondary, 0);
+        for (n, row) in rows.iter().enumerate() {
+            renderer.draw_text(row, theme.board_primary, Point::new(left + 12, 12 + n as i32 * PANEL_ROW));
+        }
+    }
+
+    //the header and one row per move number below the first `top` rows of the panel, scrolled so
+    //the shown move is in view
+    pub fn draw_move_list(&mut self, review: &Review, top: usize, renderer: &mut Renderer) {
+        let left = self.field_size as i32 * 8;
+        let theme = self.color_theme();
+        if top == 0 {
+            renderer.draw_rect(Rect::new(left, 0, ANALYSIS_WIDTH, self.field_size * 8), theme.board_secondary, 0);
+        }
+        let y = |row: usize| 12 + (top + row) as i32 * PANEL_ROW;
+        renderer.draw_text(&review.header(), theme.board_primary, Point::new(left + MOVE_LIST_COLUMNS[0], y(0)));
+
+        let black_first = review.first_side() == Side::Black;
+        let rows = (review.len() + black_first as usize).div_ceil(2);
+        let fit = ((self.field_size * 8) as usize / PANEL_ROW as usize).saturating_sub(top + 2);
+        let current_row = (review.ply() + black_first as usize).saturating_sub(1) / 2;
+        let first_row = current_row.saturating_sub(fit / 2).min(rows.saturating_sub(fit));
+        self.move_list.clear();
+        for row in first_row..rows.min(first_row + fit) {
+            let text_y = y(row - first_row + 1);
+            let dots = if row == 0 && black_first {"..."} else {"."};
+            renderer.draw_text(&format!("{}{}", review.first_number() + row, dots), theme.board_primary, Point::new(left + MOVE_LIST_COLUMNS[0], text_y));
+            for column in 0..2 {
+                let Some(index) = (row * 2 + column).checked_sub(black_first as usize) else {
+                    continue;
+                };
+                let Some(san) = review.sans().get(index) else {
+                    continue;
+                };
+                let x = left + MOVE_LIST_COLUMNS[column + 1];
+                let rect = Rect::new(x - 6, text_y - 2, (MOVE_LIST_COLUMNS[2] - MOVE_LIST_COLUMNS[1]) as u32 - 12, PANEL_ROW as u32);
+                if index + 1 == review.ply() {
+                    renderer.draw_rect(rect, theme.last_move_primary, 1);
+                }
+                renderer.draw_text(san, theme.board_primary, Point::new(x, text_y));
+                self.move_list.push((rect, index + 1));
+            }
+        }
+    }
+
+    //ply after the move of the move list under the mouse
+    pub fn move_list_ply(&self) -> Option<usize> {
+        let point = Point::new(self.mouse_pos.x as i32, self.mouse_pos.y as i32);
+        self.move_list.iter().find(|(rect, _)| rect.contains_point(point)).map(|(_, ply)| *ply)
+    }
+
     pub fn unselect(&mut self) {
         self.selected = None
     }
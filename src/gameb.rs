@@ -1,8 +1,8 @@
-use std::{collections::HashMap, net::TcpStream, sync::mpsc::Receiver};
+use std::{collections::HashMap, net::TcpStream, sync::{mpsc::Receiver, atomic::{AtomicU64, Ordering}}, time::{Duration, Instant}};
 
 use vecm::vec::{PolyVec2, Vec2i};
 
-use crate::{pieces::Side, boardb::{BoardB, Piece, Pos, PosTrait}, dtos::{self, Move}, boardb::FenError};
+use crate::{pieces::{Side, PieceType}, boardb::{BoardB, Piece, Pos, PosTrait}, dtos::{self, GameMessage, Move, Takeback}, boardb::FenError, uci_client::UciPlayer, mcts::MctsConfig};
 
 
 
@@ -18,6 +18,38 @@ pub struct GameB {
     pub check: (bool, bool),
     flipped: bool,
     pub last_move: Option<(u8, u8)>,
+    //half moves played since the game was created
+    pub ply: usize,
+    //plies that were played from the opening book
+    pub book_moves: Vec<usize>,
+    //plies since the last capture or pawn move, for the 50 move rule
+    pub halfmove_clock: usize,
+    //hash of every position since the last capture or pawn move, for threefold repetition
+    history: Vec<u64>,
+    //every move since the start position
+    pub moves: Vec<(u8, u8)>,
+    //thinking time and evaluation of every move, for the pgn comments
+    pub annotations: Vec<Annotation>,
+    //what each of the moves changed, to take it back
+    undo_stack: Vec<Undo>,
+    //moves that were taken back, if they came from the book and their annotation, newest last
+    redo_moves: Vec<((u8, u8), bool, Annotation)>,
+    //position the game started from, for restarts
+    start: (BoardB, Side),
+    //fen of the start position with its move counters
+    start_fen: String,
+    //halfmove clock and fullmove number of the start position
+    start_counters: (usize, usize),
+    //the side to move started thinking here
+    last_move_at: Instant,
+    //changes with every move and every new game, background searches compare it to spot stale results
+    generation: u64,
+}
+
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn next_generation() -> u64 {
+    GENERATION.fetch_add(1, Ordering::Relaxed) + 1
 }
 
 impl GameB {
@@ -36,7 +68,20 @@ impl GameB {
             black,
             check,
             last_move: None,
-            flipped
+            flipped,
+            ply: 0,
+            book_moves: Vec::new(),
+            halfmove_clock: 0,
+            history: vec![board.hash(Side::White)],
+            moves: Vec::new(),
+            annotations: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_moves: Vec::new(),
+            start: (board, Side::White),
+            start_fen: board.to_fen(Side::White, 0, 1),
+            start_counters: (0, 1),
+            last_move_at: Instant::now(),
+            generation: next_generation(),
         }
     }
 
@@ -66,11 +111,22 @@ impl GameB {
 
     pub fn from_fen(white: PlayerType, black: PlayerType, fen: &str, flipped: bool) -> Result<Self, FenError> {
         let (board, turn) = BoardB::from_fen(fen)?;
+        let mut counters = fen.split_whitespace().skip(4);
+        let halfmove = counters.next().and_then(|n| n.parse().ok()).ok_or(FenError::HalfMoves)?;
+        let fullmove = counters.next().and_then(|n| n.parse::<usize>().ok()).ok_or(FenError::FullMoves)?.max(1);
+        let mut game = Self::from_board(white, black, board, turn, flipped);
+        game.start_fen = fen.trim().to_owned();
+        game.start_counters = (halfmove, fullmove);
+        game.halfmove_clock = halfmove;
+        Ok(game)
+    }
+
+    pub fn from_board(white: PlayerType, black: PlayerType, board: BoardB, turn: Side, flipped: bool) -> Self {
         let mut mvs = HashMap::with_capacity(16);
         board.valid_moves(turn, &mut mvs);
-        let white_check = board.is_check_from_hm(&mvs, Side::White);
-        let black_check = board.is_check_from_hm(&mvs, Side::Black);
-        Ok(Self {
+        let white_check = board.checkers(Side::White) > 0;
+        let black_check = board.checkers(Side::Black) > 0;
+        Self {
             captured_pieces: Vec::new(),
             board,
             state: GameState::Running,
@@ -81,7 +137,49 @@ impl GameB {
             turn,
             flipped,
             last_move: None,
-        })  
+            ply: 0,
+            book_moves: Vec::new(),
+            halfmove_clock: 0,
+            history: vec![board.hash(turn)],
+            moves: Vec::new(),
+            annotations: Vec::new(),
+            undo_stack: Vec::new(),
+            redo_moves: Vec::new(),
+            start: (board, turn),
+            start_fen: board.to_fen(turn, 0, 1),
+            start_counters: (0, 1),
+            last_move_at: Instant::now(),
+            generation: next_generation(),
+        }
+    }
+
+    //same players, same start position
+    pub fn restart(&mut self) {
+        let mut white = std::mem::replace(&mut self.white, PlayerType::Me);
+        let mut black = std::mem::replace(&mut self.black, PlayerType::Me);
+        for player in [&mut white, &mut black] {
+            if let PlayerType::Uci(engine) = player {
+                engine.new_game();
+            }
+        }
+        let start_fen = std::mem::take(&mut self.start_fen);
+        let start_counters = self.start_counters;
+        *self = Self::from_board(white, black, self.start.0, self.start.1, self.flipped);
+        self.start_fen = start_fen;
+        self.start_counters = start_counters;
+        self.halfmove_clock = start_counters.0;
+    }
+
+    pub fn start_position(&self) -> (BoardB, Side) {
+        self.start
+    }
+
+    pub fn start_fen(&self) -> &str {
+        &self.start_fen
+    }
+
+    pub fn generation(&self) -> u64 {
+        self.generation
     }
 
     pub fn change_turn(&mut self) {
@@ -105,23 +203,64 @@ impl GameB {
     }
 
     pub fn make_move(&mut self, from: u8, to: u8) -> bool {
+        if !self.play(from, to) {
+            return false;
+        }
+        //the move that was taken back keeps the rest of the line, any other move starts a new one
+        if self.redo_moves.last().is_some_and(|(mv, _, _)| *mv == (from, to)) {
+            self.redo_moves.pop();
+        } else {
+            self.redo_moves.clear();
+        }
+        true
+    }
+
+    fn play(&mut self, from: u8, to: u8) -> bool {
         let Some(moves_for_pieces) = self.possible_moves.get(&from) else {
             return false
         };
         if moves_for_pieces & (1 << to) != 0 {
-            if let Some(captured_piece) = self.board.make_move(from, to) {
+            let mut undo = Undo {
+                board: self.board,
+                turn: self.turn,
+                state: self.state,
+                check: self.check,
+                last_move: self.last_move,
+                halfmove_clock: self.halfmove_clock,
+                captured: false,
+                history: None,
+            };
+            let pawn_move = self.board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::Pawn);
+            let captured = self.board.make_move(from, to);
+            if pawn_move || captured.is_some() {
+                self.halfmove_clock = 0;
+                undo.history = Some(std::mem::take(&mut self.history));
+            } else {
+                self.halfmove_clock += 1;
+            }
+            undo.captured = captured.is_some();
+            self.undo_stack.push(undo);
+            if let Some(captured_piece) = captured {
                 self.captured_pieces.push(captured_piece);
             }
             self.last_move = Some((from, to));
+            self.moves.push((from, to));
+            self.annotations.push(Annotation { think: self.last_move_at.elapsed(), eval: None });
+            self.last_move_at = Instant::now();
+            self.ply += 1;
+            self.generation = next_generation();
             if let PlayerType::Remote(remote) = &mut self.turn_mut() {
                 let f = Pos::from_i(from);
                 let t = Pos::from_i(to);
-                dtos::send(&mut remote.socket, Move {x1: f.x as i8, y1: f.y as i8, x2: t.x as i8, y2: t.y as i8})
+                dtos::send(&mut remote.socket, GameMessage::Move(Move {x1: f.x as i8, y1: f.y as i8, x2: t.x as i8, y2: t.y as i8}))
                     .expect("Failed to send move")
             };
             self.change_turn();
             self.board.valid_moves(self.turn, &mut self.possible_moves);
 
+            self.check.1 = self.board.checkers(Side::Black) > 0;
+            self.check.0 = self.board.checkers(Side::White) > 0;
+
             let check = match self.turn {Side::White => self.check.0, Side::Black => self.check.1};
             if self.possible_moves.iter().filter(|(_, v)| **v > 0).count() == 0 {
                 if check {
@@ -131,18 +270,140 @@ impl GameB {
                 }
             } 
 
-            self.check.1 = self.board.is_check(&self.board.valid_moves_as_array(Side::White, false, false), Side::Black);
-            self.check.0 =  self.board.is_check(&self.board.valid_moves_as_array(Side::Black, false, false), Side::White);
-            
+            let hash = self.board.hash(self.turn);
+            self.history.push(hash);
+            if self.state == GameState::Running && (
+                self.halfmove_clock >= 100 ||
+                self.history.iter().filter(|h| **h == hash).count() >= 3 ||
+                self.board.insufficient_material()
+            ) {
+                self.state = GameState::Draw;
+            }
+
         } else {
             return false
         }
         true
     }
+
+    //takes back the last move, the board, clocks and game state are as they were before it
+    pub fn undo(&mut self) -> bool {
+        let (Some(undo), Some(mv), Some(annotation)) = (self.undo_stack.pop(), self.moves.pop(), self.annotations.pop()) else {
+            return false;
+        };
+        self.board = undo.board;
+        self.turn = undo.turn;
+        self.state = undo.state;
+        self.check = undo.check;
+        self.last_move = undo.last_move;
+        self.halfmove_clock = undo.halfmove_clock;
+        if undo.captured {
+            self.captured_pieces.pop();
+        }
+        self.history.pop();
+        if let Some(history) = undo.history {
+            self.history = history;
+        }
+        let book = self.last_move_from_book();
+        self.ply -= 1;
+        self.book_moves.retain(|ply| *ply < self.ply);
+        self.board.valid_moves(self.turn, &mut self.possible_moves);
+        self.redo_moves.push((mv, book, annotation));
+        self.generation = next_generation();
+        self.last_move_at = Instant::now();
+        true
+    }
+
+    //plays the last move that was taken back again
+    pub fn redo(&mut self) -> bool {
+        let Some(((from, to), book, annotation)) = self.redo_moves.pop() else {
+            return false;
+        };
+        let played = self.play(from, to);
+        if played {
+            *self.annotations.last_mut().expect("Move was just played") = annotation;
+            if book {
+                self.book_moves.push(self.ply - 1);
+            }
+        }
+        played
+    }
+
+    //score from the mover's view and depth of the search that found the last move
+    pub fn annotate_eval(&mut self, score: i32, depth: usize) {
+        if let Some(annotation) = self.annotations.last_mut() {
+            annotation.eval = Some((score, depth));
+        }
+    }
+
+    //result token for pgn, * while the game goes on
+    pub fn result(&self) -> &'static str {
+        match self.state {
+            GameState::Running => "*",
+            GameState::Winner(Side::White) => "1-0",
+            GameState::Winner(Side::Black) => "0-1",
+            GameState::Draw => "1/2-1/2",
+        }
+    }
+
+    pub fn can_undo(&self) -> bool {
+        !self.undo_stack.is_empty()
+    }
+
+    pub fn can_redo(&self) -> bool {
+        !self.redo_moves.is_empty()
+    }
+
+    pub fn remote_mut(&mut self) -> Option<&mut Remote> {
+        match (&mut self.white, &mut self.black) {
+            (PlayerType::Remote(remote), _) | (_, PlayerType::Remote(remote)) => Some(remote),
+            _ => None,
+        }
+    }
+
+    pub fn make_book_move(&mut self, from: u8, to: u8) -> bool {
+        let accepted = self.make_move(from, to);
+        if accepted {
+            self.book_moves.push(self.ply - 1);
+        }
+        accepted
+    }
+
+    pub fn to_fen(&self) -> String {
+        //a game that starts with black to move reaches the next fullmove after one ply
+        let black_started = self.start.1 == Side::Black;
+        self.board.to_fen(self.turn, self.halfmove_clock, self.start_counters.1 + (self.ply + black_started as usize) / 2)
+    }
+
+    pub fn last_move_from_book(&self) -> bool {
+        self.ply > 0 && self.book_moves.last() == Some(&(self.ply - 1))
+    }
 }
 
 
 
+#[derive(Clone, Copy, Default)]
+pub struct Annotation {
+    //from the previous move, or the start of the game, until this one
+    pub think: Duration,
+    //(score from the mover's view, depth) if a search played the move
+    pub eval: Option<(i32, usize)>,
+}
+
+//state before a move that the move itself can't tell
+struct Undo {
+    board: BoardB,
+    turn: Side,
+    state: GameState,
+    check: (bool, bool),
+    last_move: Option<(u8, u8)>,
+    halfmove_clock: usize,
+    //the move added to captured_pieces
+    captured: bool,
+    //repetition history an irreversible move cleared
+    history: Option<Vec<u64>>,
+}
+
 #[derive(Clone, Copy, PartialEq)]
 pub enum GameState {
     Running,
@@ -156,16 +417,33 @@ pub enum PlayerType {
     Cpu {
         depth: usize,
         //computation: Option<JoinHandle<(Vec2i, Vec2i)>>,
-    }
+    },
+    //external engine process
+    Uci(UciPlayer),
+    //cpu with monte carlo tree search instead of alpha-beta
+    Mcts(MctsConfig),
 }
 
 
 impl PlayerType {
+    //for the pgn tags
+    pub fn name(&self) -> String {
+        match self {
+            PlayerType::Me => "Player".to_owned(),
+            PlayerType::Remote(_) => "Remote player".to_owned(),
+            PlayerType::Cpu { depth } => format!("chess-cp depth {}", depth),
+            PlayerType::Uci(engine) => engine.name.clone(),
+            PlayerType::Mcts(config) => format!("chess-cp mcts {}", config.iterations),
+        }
+    }
+
     pub fn is_me(&self) -> bool{
         match self {
             PlayerType::Me => true,
             PlayerType::Remote(_) => false,
             PlayerType::Cpu { depth } => false,
+            PlayerType::Uci(_) => false,
+            PlayerType::Mcts(_) => false,
         }
     }
 
@@ -174,6 +452,8 @@ impl PlayerType {
             PlayerType::Me => false,
             PlayerType::Remote(_) => true,
             PlayerType::Cpu { depth } => false,
+            PlayerType::Uci(_) => false,
+            PlayerType::Mcts(_) => false,
         }
     }
 
@@ -182,6 +462,8 @@ impl PlayerType {
             PlayerType::Me => false,
             PlayerType::Remote(_) => false,
             PlayerType::Cpu { depth } => true,
+            PlayerType::Uci(_) => true,
+            PlayerType::Mcts(_) => true,
         }
     }
 }
@@ -189,23 +471,149 @@ impl PlayerType {
 
 pub struct Remote {
     pub socket: TcpStream,
-    pub rx: Receiver<Move>,
+    pub rx: Receiver<GameMessage>,
 }
 
 impl Remote {
-    pub fn new(socket: TcpStream, rx: Receiver<Move>) -> Self {
+    pub fn new(socket: TcpStream, rx: Receiver<GameMessage>) -> Self {
         Self {socket, rx }
     }
 
+    pub fn send_takeback(&mut self, takeback: Takeback) {
+        dtos::send(&mut self.socket, GameMessage::Takeback(takeback)).expect("Could not send takeback");
+    }
+
     pub fn send_move(&mut self, from: Vec2i, to: Vec2i) {
         dtos::send(
             &mut self.socket, 
-            Move {
+            GameMessage::Move(Move {
                 x1: from.x as i8,
                 y1: 7 - from.y as i8,
                 x2:  to.x as i8,
                 y2: 7 - to.y as i8
-            }
+            })
         ).expect("Could not send move");
     }
-}
\ No newline at end of file
+}
+
+
+#[cfg(test)]
+mod tests {
+    use crate::san::square_from_str;
+    use super::*;
+
+
+
+    //everything a move changes that undo has to bring back
+    #[derive(PartialEq)]
+    struct Snapshot {
+        fen: String,
+        turn: Side,
+        possible_moves: Vec<(u8, u64)>,
+        check: (bool, bool),
+        state: GameState,
+        captured_pieces: Vec<Piece>,
+        halfmove_clock: usize,
+        history: Vec<u64>,
+        book_moves: Vec<usize>,
+        moves: Vec<(u8, u8)>,
+    }
+
+    fn snapshot(game: &GameB) -> Snapshot {
+        let mut possible_moves: Vec<_> = game.possible_moves.iter().map(|(from, tos)| (*from, *tos)).collect();
+        possible_moves.sort_unstable();
+        Snapshot {
+            fen: game.to_fen(),
+            turn: game.turn,
+            possible_moves,
+            check: game.check,
+            state: game.state,
+            captured_pieces: game.captured_pieces.clone(),
+            halfmove_clock: game.halfmove_clock,
+            history: game.history.clone(),
+            book_moves: game.book_moves.clone(),
+            moves: game.moves.clone(),
+        }
+    }
+
+    fn mv(s: &str) -> (u8, u8) {
+        (square_from_str(&s[..2]).unwrap(), square_from_str(&s[2..]).unwrap())
+    }
+
+    //plays the moves, the first book ones from the book, and keeps the state before and after each
+    fn play(game: &mut GameB, book: usize, moves: &str) -> Vec<Snapshot> {
+        let mut snapshots = vec![snapshot(game)];
+        for (n, m) in moves.split_whitespace().enumerate() {
+            let (from, to) = mv(m);
+            let played = if n < book {game.make_book_move(from, to)} else {game.make_move(from, to)};
+            assert!(played, "{}", m);
+            snapshots.push(snapshot(game));
+        }
+        snapshots
+    }
+
+    fn undo_and_redo(game: &mut GameB, snapshots: &[Snapshot]) {
+        let plies = snapshots.len() - 1;
+        for n in (0..plies).rev() {
+            assert!(game.undo());
+            assert!(snapshot(game) == snapshots[n], "undo to ply {}", n);
+        }
+        assert!(!game.undo());
+        for (n, expected) in snapshots.iter().enumerate().skip(1) {
+            assert!(game.redo());
+            assert!(snapshot(game) == *expected, "redo to ply {}", n);
+        }
+        assert!(!game.redo());
+    }
+
+    #[test]
+    fn undo_redo_restores_everything() {
+        //book moves, captures, castling and a threefold repetition at the end
+        let mut game = GameB::new(PlayerType::Me, PlayerType::Me, false);
+        let snapshots = play(&mut game, 2, "e2e4 d7d5 e4d5 d8d5 b1c3 d5a5 g1f3 g8f6 f1c4 c8g4 e1g1 b8c6 \
+            f3e1 a5b6 e1f3 b6a5 f3e1 a5b6 e1f3 b6a5");
+        assert!(game.state == GameState::Draw);
+        assert_eq!(game.captured_pieces.len(), 2);
+        assert_eq!(game.book_moves, [0, 1]);
+        undo_and_redo(&mut game, &snapshots);
+        assert!(game.state == GameState::Draw);
+    }
+
+    #[test]
+    fn undo_redo_mate() {
+        let mut game = GameB::new(PlayerType::Me, PlayerType::Me, false);
+        let snapshots = play(&mut game, 0, "f2f3 e7e5 g2g4 d8h4");
+        assert!(game.state == GameState::Winner(Side::Black));
+        assert_eq!(game.check, (true, false));
+        undo_and_redo(&mut game, &snapshots);
+    }
+
+    #[test]
+    fn new_move_clears_redo() {
+        let mut game = GameB::new(PlayerType::Me, PlayerType::Me, false);
+        play(&mut game, 0, "e2e4 e7e5 g1f3");
+        assert!(game.undo() && game.undo());
+        //the move that was taken back keeps the rest of the line
+        let (from, to) = mv("e7e5");
+        assert!(game.make_move(from, to));
+        assert!(game.can_redo());
+        assert!(game.undo());
+        let (from, to) = mv("c7c5");
+        assert!(game.make_move(from, to));
+        assert!(!game.can_redo() && !game.redo());
+        assert_eq!(game.moves, [mv("e2e4"), mv("c7c5")]);
+    }
+
+    #[test]
+    fn fen_keeps_start_counters() {
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 7 20";
+        let mut game = GameB::from_fen(PlayerType::Me, PlayerType::Me, fen, false).unwrap();
+        assert_eq!(game.to_fen(), fen);
+        play(&mut game, 0, "b8c6");
+        assert_eq!(game.to_fen(), "r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 8 21");
+        play(&mut game, 0, "f1c4 g8f6 f3e5");
+        assert_eq!(game.to_fen(), "r1bqkb1r/pppp1ppp/2n2n2/4N3/2B1P3/8/PPPP1PPP/RNBQK2R b KQkq - 0 22");
+        game.restart();
+        assert_eq!(game.to_fen(), fen);
+    }
+}
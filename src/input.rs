@@ -62,7 +62,18 @@ key_mappings! {
     Right => D,
     Escape => Escape,
     Color => C,
-    Pieces => P
+    Pieces => P,
+    Analysis => Tab,
+    NewGame => N,
+    Undo => Left,
+    Redo => Right,
+    Accept => Y,
+    Export => E,
+    Review => R,
+    First => Home,
+    Last => End,
+    Autoplay => Space,
+    Branch => B
   press:
     ZoomIn => Plus,
     ZoomOut => Minus
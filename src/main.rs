@@ -10,11 +10,38 @@ pub mod game_renderer;
 pub mod boardb;
 pub mod gameb;
 pub mod castle;
+pub mod zobrist;
+pub mod tt;
+pub mod search;
+pub mod bench;
+pub mod book;
+pub mod san;
+pub mod pgn;
+pub mod pgn_tree;
+pub mod review;
+pub mod book_builder;
+pub mod tablebase;
+pub mod syzygy;
+pub mod retrograde;
+pub mod trainer;
+pub mod nnue;
+pub mod datagen;
+pub mod eval;
+pub mod tuner;
+pub mod skill;
+pub mod uci;
+pub mod analysis;
+pub mod uci_client;
+pub mod match_runner;
+pub mod epd;
+pub mod seed;
+pub mod mate;
+pub mod mcts;
 
 use atlas::TextureAtlas;
 use binverse::error::BinverseError;
 use game_renderer::GameRenderer;
-use dtos::{PlayerInfo, Move, GameInfo};
+use dtos::{PlayerInfo, GameMessage, GameInfo, Takeback};
 use gameb::PlayerType;
 use pieces::Side;
 use input::InputHandler;
@@ -23,24 +50,46 @@ use sdl2::image::{LoadTexture, InitFlag};
 use sdl2::{pixels::Color, render::Canvas};
 use vecm::vec::Vec2u;
 
-use std::collections::HashMap;
 use std::env::Args;
 use std::net::TcpStream;
+use std::fs::File;
+use std::io::BufReader;
 use std::path::Path;
-use std::sync::mpsc::{self, TryRecvError, Sender};
-use std::thread::JoinHandle;
+use std::sync::Arc;
+use std::sync::mpsc::{self, TryRecvError};
 //use world::celo::Celo;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use rand::Rng;
 
 mod input; 
 
 
-use crate::boardb::{Pos, PosTrait, BoardB, BitMap};
+use crate::boardb::{BoardB, Pos, PosTrait};
+use crate::search::{SearchConfig, SearchHandle, CpuMove, Ponder};
+use crate::tt::TranspositionTable;
+use crate::book::OpeningBook;
+use crate::book_builder::BookFilter;
+use crate::tablebase::Tablebases;
+use crate::syzygy::Syzygy;
+use crate::retrograde::DtmTables;
+use crate::trainer::Trainer;
+use crate::nnue::Network;
+use crate::datagen::DatagenConfig;
+use crate::epd::EpdConfig;
+use crate::mate::{MateProblem, Stipulation};
+use crate::mcts::{MctsConfig, Playout};
+use crate::pgn::{ExportOptions, PgnError};
+use crate::pgn_tree::PgnTreeReader;
+use crate::review::Review;
+use crate::eval::EvalParams;
+use crate::skill::Skill;
+use crate::analysis::Analysis;
+use crate::match_runner::{EngineSpec, MatchConfig, Sprt, TimeControl};
+use crate::uci_client::{GoResult, Limits, UciPlayer};
 use crate::gameb::{GameB, Remote, GameState};
 use crate::input::Control;
 
-fn receive_mvs(mut tcp_stream: TcpStream, moves: mpsc::Sender<Move>) -> Result<(), BinverseError> {
+fn receive_mvs(mut tcp_stream: TcpStream, moves: mpsc::Sender<GameMessage>) -> Result<(), BinverseError> {
     loop {
         moves.send(dtos::recv(&mut tcp_stream)?).unwrap();
     }
@@ -48,39 +97,236 @@ fn receive_mvs(mut tcp_stream: TcpStream, moves: mpsc::Sender<Move>) -> Result<(
 
 struct MultiplayerUtils {
     tcp_stream: TcpStream,
-    moves_rx: mpsc::Receiver<Move>,
+    moves_rx: mpsc::Receiver<GameMessage>,
     my_side: Side,
 }
 
-fn parse_args(args: &mut Args) -> (bool, bool, Option<usize>, Option<usize>, Option<String>, Option<String>){
-    args.skip(1);
-    let mut versus = true;
-    let mut server = false;
-    let mut ai = None;
-    let mut ip = None;
-    let mut fen = None;
-    let mut vai = None;
+struct Options {
+    ai: Option<usize>,
+    vai: Option<usize>,
+    ip: Option<String>,
+    fen: Option<String>,
+    threads: usize,
+    hash_mb: usize,
+    //(depth, max threads)
+    smp_bench: Option<(usize, usize)>,
+    //depth of the fixed node count benchmark
+    bench: Option<usize>,
+    book: Option<String>,
+    book_best: bool,
+    //(pgn directory, output file)
+    build_book: Option<(String, String)>,
+    book_filter: BookFilter,
+    syzygy_path: Option<String>,
+    //directory with generated .dtm tables
+    tb_path: Option<String>,
+    //(output directory, comma separated materials or all)
+    generate_tb: Option<(String, String)>,
+    trainer: Option<String>,
+    //network file, replaces the material evaluation
+    nnue: Option<String>,
+    //self-play output file
+    datagen: Option<String>,
+    datagen_config: DatagenConfig,
+    eval_params: Option<String>,
+    //(epd file, output parameter file)
+    tune: Option<(String, String)>,
+    //strength of every cpu player
+    skill: Option<Skill>,
+    //speak uci on stdin/stdout instead of opening a window
+    uci: bool,
+    //lines of the analysis panel and the uci default
+    multi_pv: Option<usize>,
+    //cpu keeps searching on the expected reply while the opponent thinks
+    ponder: bool,
+    //(engine a, engine b) of a headless match
+    match_engines: Option<(String, String)>,
+    match_config: MatchConfig,
+    //external uci engine as the opponent
+    engine: Option<String>,
+    engine_options: Vec<(String, String)>,
+    engine_limits: Limits,
+    //test suite file
+    epd: Option<String>,
+    //random unless given, printed so a run can be repeated
+    seed: Option<u64>,
+    //solve the --fen position instead of playing it
+    mate: Option<MateProblem>,
+    //cpu players search with mcts
+    mcts: Option<MctsConfig>,
+    //finished games are saved here
+    pgn_dir: Option<String>,
+    pgn_export: ExportOptions,
+    //pgn file to read and validate
+    pgn_check: Option<String>,
+    //pgn file to step through, which game of it and the autoplay milliseconds per move
+    review: Option<String>,
+    review_game: usize,
+    review_speed: u64,
+    epd_config: EpdConfig,
+}
+
+fn parse_args(args: &mut Args) -> Options {
+    args.next();
+    let mut options = Options {
+        ai: None,
+        vai: None,
+        ip: None,
+        fen: None,
+        threads: 1,
+        hash_mb: 64,
+        smp_bench: None,
+        bench: None,
+        book: None,
+        book_best: false,
+        build_book: None,
+        book_filter: BookFilter::default(),
+        syzygy_path: None,
+        tb_path: None,
+        generate_tb: None,
+        trainer: None,
+        nnue: None,
+        datagen: None,
+        datagen_config: DatagenConfig::default(),
+        eval_params: None,
+        tune: None,
+        skill: None,
+        uci: false,
+        multi_pv: None,
+        ponder: false,
+        match_engines: None,
+        match_config: MatchConfig::default(),
+        engine: None,
+        engine_options: Vec::new(),
+        engine_limits: Limits { movetime: Some(1000), ..Default::default() },
+        epd: None,
+        seed: None,
+        mate: None,
+        mcts: None,
+        pgn_dir: None,
+        pgn_export: ExportOptions::default(),
+        pgn_check: None,
+        review: None,
+        review_game: 1,
+        review_speed: 1000,
+        epd_config: EpdConfig::default(),
+    };
+    fn number(arg: Option<String>, expected: &str) -> usize {
+        arg.expect(expected)
+            .parse::<usize>()
+            .expect("has to be a positive integer")
+    }
+    fn float(arg: Option<String>, expected: &str) -> f64 {
+        arg.expect(expected)
+            .parse::<f64>()
+            .expect("has to be a number")
+    }
     while let Some(arg) = args.next() {
         match arg.as_str() {
             "-s" | "--server" => panic!("Not available at the moment"),//server = true,
-            "-a" | "--ai" => ai = Some(
-                args.next()
-                    .expect("give ai depth as argument")
-                    .parse::<usize>()
-                    .expect("depth has to be a positive integer")
-                ),
-            "-v" | "--vai" => vai = Some(
-                args.next()
-                    .expect("give ai depth as argument")
-                    .parse::<usize>()
-                    .expect("depth has to be a positive integer")
-                ),
-            "-f" | "--fen" => fen = Some(args.next().expect("fen expected after -f/--fen")),
-            "-c" | "--c" => ip = Some(args.next().expect("connect requires ip")), 
+            "-a" | "--ai" => options.ai = Some(number(args.next(), "give ai depth as argument")),
+            "-v" | "--vai" => options.vai = Some(number(args.next(), "give ai depth as argument")),
+            "-f" | "--fen" => options.fen = Some(args.next().expect("fen expected after -f/--fen")),
+            "-c" | "--c" => options.ip = Some(args.next().expect("connect requires ip")), 
+            "-t" | "--threads" => options.threads = number(args.next(), "give number of search threads").max(1),
+            "--hash" => options.hash_mb = number(args.next(), "give hash size in MB"),
+            "--smp-bench" => options.smp_bench = Some((
+                number(args.next(), "give bench depth"),
+                number(args.next(), "give max thread count"),
+            )),
+            //plain "bench" too, like test frameworks call engines
+            "bench" | "--bench" => options.bench = Some(options.bench.unwrap_or(bench::DEFAULT_DEPTH)),
+            "--bench-depth" => options.bench = Some(number(args.next(), "give bench depth")),
+            "-b" | "--book" => options.book = Some(args.next().expect("book path expected after -b/--book")),
+            "--book-best" => options.book_best = true,
+            "--build-book" => options.build_book = Some((
+                args.next().expect("pgn directory expected after --build-book"),
+                args.next().expect("output file expected after --build-book <pgn dir>"),
+            )),
+            "--book-min-ply" => options.book_filter.min_ply = number(args.next(), "give minimum game length in plies"),
+            "--book-max-ply" => options.book_filter.max_ply = number(args.next(), "give book depth in plies"),
+            "--book-min-elo" => options.book_filter.min_elo = number(args.next(), "give minimum rating") as u32,
+            "--book-results" => options.book_filter.results = args.next()
+                .expect("results expected after --book-results, like 1-0,1/2-1/2")
+                .split(',')
+                .map(|r| r.to_owned())
+                .collect(),
+            "--syzygy-path" => options.syzygy_path = Some(args.next().expect("tablebase directory expected after --syzygy-path")),
+            "--tb-path" => options.tb_path = Some(args.next().expect("tablebase directory expected after --tb-path")),
+            "--generate-tb" => options.generate_tb = Some((
+                args.next().expect("output directory expected after --generate-tb"),
+                args.next().expect("materials expected after --generate-tb <dir>, like KQvK,KRvK or all"),
+            )),
+            "--trainer" => options.trainer = Some(args.next().expect("material expected after --trainer, like KQvK")),
+            "--nnue" => options.nnue = Some(args.next().expect("network file expected after --nnue")),
+            "--datagen" => options.datagen = Some(args.next().expect("output file expected after --datagen")),
+            "--datagen-games" => options.datagen_config.games = number(args.next(), "give number of games"),
+            "--datagen-depth" => options.datagen_config.depth = number(args.next(), "give search depth"),
+            "--datagen-nodes" => options.datagen_config.nodes = Some(number(args.next(), "give node limit per move") as u64),
+            "--datagen-random-plies" => options.datagen_config.random_plies = number(args.next(), "give number of random opening plies"),
+            "--eval-params" => options.eval_params = Some(args.next().expect("parameter file expected after --eval-params")),
+            "--tune" => options.tune = Some((
+                args.next().expect("epd file expected after --tune"),
+                args.next().expect("output file expected after --tune <epd>"),
+            )),
+            "--skill" => options.skill = Some(Skill::new(number(args.next(), "give skill level 0-20") as u8)),
+            "--elo" => options.skill = Some(Skill::from_elo(number(args.next(), "give target elo") as u32)),
+            "--uci" => options.uci = true,
+            "--ponder" => options.ponder = true,
+            "--multi-pv" => options.multi_pv = Some(number(args.next(), "give number of lines").max(1)),
+            "--match" => options.match_engines = Some((
+                args.next().expect("engine expected after --match, like cp or uci:/path/to/engine"),
+                args.next().expect("second engine expected after --match <engine>"),
+            )),
+            "--match-games" => options.match_config.games = number(args.next(), "give number of games"),
+            "--match-concurrency" => options.match_config.concurrency = number(args.next(), "give number of games at the same time").max(1),
+            "--match-depth" => options.match_config.depth = Some(number(args.next(), "give search depth")),
+            "--match-nodes" => options.match_config.nodes = Some(number(args.next(), "give node limit per move") as u64),
+            "--match-tc" => options.match_config.time_control = Some(TimeControl::parse(&args.next().expect("time control expected after --match-tc"))
+                .expect("time control has to be seconds+increment, like 10+0.1")),
+            "--match-openings" => options.match_config.openings = Some(args.next().expect("fen or epd file expected after --match-openings").into()),
+            "--match-random-plies" => options.match_config.random_plies = number(args.next(), "give number of random opening plies"),
+            "--match-pgn" => options.match_config.pgn = Some(args.next().expect("output file expected after --match-pgn").into()),
+            "--engine" => options.engine = Some(args.next().expect("engine path expected after --engine")),
+            "--engine-option" => options.engine_options.push(args.next()
+                .and_then(|o| o.split_once('=').map(|(n, v)| (n.to_owned(), v.to_owned())))
+                .expect("option expected after --engine-option, like Hash=64")),
+            "--engine-movetime" => options.engine_limits.movetime = Some(number(args.next(), "give milliseconds per move") as u64),
+            "--engine-depth" => {
+                options.engine_limits.depth = Some(number(args.next(), "give search depth"));
+                options.engine_limits.movetime = None;
+            },
+            "--mate" => options.mate = Some(MateProblem { moves: number(args.next(), "give number of moves"), ..options.mate.unwrap_or_default() }),
+            "--mate-checks" => options.mate = Some(MateProblem { checks_only: true, ..options.mate.unwrap_or_default() }),
+            "--selfmate" => options.mate = Some(MateProblem { stipulation: Stipulation::Selfmate, ..options.mate.unwrap_or_default() }),
+            "--helpmate" => options.mate = Some(MateProblem { stipulation: Stipulation::Helpmate, ..options.mate.unwrap_or_default() }),
+            "--mcts" => options.mcts = Some(MctsConfig { iterations: number(args.next(), "give number of iterations"), ..options.mcts.unwrap_or_default() }),
+            "--mcts-c" => options.mcts = Some(MctsConfig { exploration: float(args.next(), "give exploration constant"), ..options.mcts.unwrap_or_default() }),
+            "--mcts-playout" => options.mcts = Some(MctsConfig {
+                playout: match args.next().as_deref() {
+                    Some("random") => Playout::Random,
+                    Some("eval") => Playout::Eval,
+                    _ => panic!("playout has to be random or eval"),
+                },
+                ..options.mcts.unwrap_or_default()
+            }),
+            "--pgn-dir" => options.pgn_dir = Some(args.next().expect("directory expected after --pgn-dir")),
+            "--pgn-clock" => options.pgn_export.clock = true,
+            "--pgn-eval" => options.pgn_export.eval = true,
+            "--pgn-check" => options.pgn_check = Some(args.next().expect("pgn file expected after --pgn-check")),
+            "--review" => options.review = Some(args.next().expect("pgn file expected after --review")),
+            "--review-game" => options.review_game = number(args.next(), "give game number").max(1),
+            "--review-speed" => options.review_speed = number(args.next(), "give milliseconds per move") as u64,
+            "--seed" => options.seed = Some(number(args.next(), "give seed") as u64),
+            "--epd" => options.epd = Some(args.next().expect("epd file expected after --epd")),
+            "--epd-depth" => options.epd_config.depth = Some(number(args.next(), "give search depth")),
+            "--epd-time" => options.epd_config.movetime = Some(number(args.next(), "give milliseconds per position") as u64),
+            "--epd-out" => options.epd_config.output = Some(args.next().expect("output file expected after --epd-out").into()),
+            "--sprt" => options.match_config.sprt = Some(Sprt::new(float(args.next(), "give elo0 after --sprt"), float(args.next(), "give elo1 after --sprt <elo0>"))),
             _ => eprintln!("unrecognized arg {arg}"),
         }
     }
-    (versus, server, ai, vai, ip, fen)
+    options
 } 
 
 #[derive(Debug)]
@@ -112,29 +358,291 @@ fn connect(ip: String) -> Result<MultiplayerUtils, ConnectionError> {
     Ok(MultiplayerUtils { tcp_stream, moves_rx: rx, my_side})
 }
 
-fn try_apply_remote_move(game: &mut GameB) {
-    if let PlayerType::Remote(remote) = &game.turn() {
-        match remote.rx.try_recv() {
-            Ok(new_move) => {
-                println!("Receiving move {:?} for {:?}", new_move, game.turn);
-                if !game.make_move(Pos::new(new_move.x1, 7 - new_move.y1).to_i(), Pos::new(new_move.x2, 7 - new_move.y2).to_i()) {
+//plies asked to be taken back, by the opponent or by us
+#[derive(Default)]
+struct Takebacks {
+    offered: Option<u32>,
+    asked: Option<u32>,
+}
+
+//moves only come on the opponent's turn, takeback messages at any time
+fn try_apply_remote_move(game: &mut GameB, takebacks: &mut Takebacks) {
+    let Some(remote) = game.remote_mut() else {
+        return;
+    };
+    match remote.rx.try_recv() {
+        Ok(message) => match message {
+            GameMessage::Takeback(Takeback::Request(plies)) => {
+                println!("Opponent wants to take back {} plies, press Y to accept", plies);
+                takebacks.offered = Some(plies);
+            },
+            GameMessage::Takeback(Takeback::Accept(plies)) => if takebacks.asked.take() == Some(plies) {
+                for _ in 0..plies {
+                    game.undo();
+                }
+                println!("Opponent accepted the takeback");
+            },
+            GameMessage::Move(message) => {
+                println!("Receiving move {:?} for {:?}", message, game.turn);
+                if !game.make_move(Pos::new(message.x1, 7 - message.y1).to_i(), Pos::new(message.x2, 7 - message.y2).to_i()) {
                     panic!("Opponent move not accepted");
                 }
                 game.change_turn();
+                //a move answers any open request
+                *takebacks = Takebacks::default();
             },
-            Err(TryRecvError::Empty) => {},
-            Err(TryRecvError::Disconnected) => panic!("Disconnected"),
-        }
+        },
+        Err(TryRecvError::Empty) => {},
+        Err(TryRecvError::Disconnected) => panic!("Disconnected"),
     }
 }
 
+fn try_apply_engine_move(game: &mut GameB) {
+    let generation = game.generation();
+    if !matches!(game.turn(), PlayerType::Uci(engine) if engine.asked(generation)) {
+        let (start, moves) = (game.start_position(), game.moves.clone());
+        if let PlayerType::Uci(engine) = game.turn_mut() {
+            engine.go(start, moves, generation);
+        }
+    }
+    let PlayerType::Uci(engine) = game.turn_mut() else {
+        return;
+    };
+    let name = engine.name.clone();
+    let error = match engine.try_answer(generation) {
+        Some(Ok(GoResult { mv: (from, to, _), score, depth })) => {
+            if game.make_move(from, to) {
+                if let Some(score) = score {
+                    game.annotate_eval(score, depth);
+                }
+                None
+            } else {
+                Some(format!("move {} not accepted", uci::move_to_uci(&game.board, from, to)))
+            }
+        },
+        Some(Err(err)) => Some(format!("{:?}", err)),
+        None => None,
+    };
+    //a broken engine loses the game instead of taking the gui down with it
+    if let Some(error) = error {
+        eprintln!("Engine error ({}): {}", name, error);
+        game.state = GameState::Winner(!game.turn);
+    }
+}
 
 fn main() -> Result<(), String> {
     let mut args = std::env::args();
-    let (versus, server, ai, vai, ip, fen) = parse_args(&mut args);
+    let Options { ai, vai, ip, fen, threads, hash_mb, smp_bench, bench, book, book_best, build_book, book_filter, syzygy_path, tb_path, generate_tb, trainer, nnue, datagen, mut datagen_config, eval_params, tune, skill, uci, multi_pv, ponder, match_engines, mut match_config, engine, engine_options, engine_limits, epd, mut epd_config, seed, mate, mcts, pgn_dir, pgn_export, pgn_check, review: review_file, review_game, review_speed } = parse_args(&mut args);
+    if let Some(seed) = seed {
+        seed::init(seed);
+    }
     let mut mp = false;
 
+    let params = match eval_params {
+        Some(path) => match EvalParams::load(Path::new(&path)) {
+            Ok(params) => {println!("Loaded evaluation parameters {}", path); params},
+            Err(err) => panic!("Evaluation parameter error: {:?}", err),
+        },
+        None => EvalParams::default(),
+    };
+
+    if let Some((epd, out)) = tune {
+        match tuner::tune(Path::new(&epd), params).map(|params| params.save(Path::new(&out))) {
+            Ok(Ok(())) => println!("Tuned parameters written to {}", out),
+            Ok(Err(err)) => panic!("Evaluation parameter error: {:?}", err),
+            Err(err) => panic!("Tuner error: {:?}", err),
+        }
+        return Ok(());
+    }
+    eval::init(params);
+
+    if let Some(path) = nnue {
+        match Network::load(Path::new(&path)) {
+            Ok(network) => {println!("Loaded network {}", path); nnue::init(network)},
+            Err(err) => panic!("Network error: {:?}", err),
+        }
+    }
+
+    let syzygy = syzygy_path.map(|path| match Syzygy::open(Path::new(&path)) {
+        Ok(syzygy) => {println!("Found {} syzygy tables up to {} pieces in {}", syzygy.table_count(), syzygy.max_pieces, path); syzygy},
+        Err(err) => panic!("Tablebase error: {:?}", err),
+    });
+    let mut dtm = match tb_path {
+        Some(path) => match DtmTables::load(Path::new(&path)) {
+            Ok(dtm) => {println!("Loaded {} distance to mate tables from {}", dtm.len(), path); dtm},
+            Err(err) => panic!("Tablebase error: {:?}", err),
+        },
+        None => DtmTables::default(),
+    };
+
+    if let Some((dir, materials)) = generate_tb {
+        let materials: Vec<&str> = if materials == "all" {retrograde::SUPPORTED.to_vec()} else {materials.split(',').collect()};
+        for material in materials {
+            if let Err(err) = dtm.generate(material) {
+                panic!("Tablebase error: {:?}", err);
+            }
+        }
+        for table in dtm.tables() {
+            match table.save(Path::new(&dir)) {
+                Ok(path) => println!("Wrote {}", path.display()),
+                Err(err) => panic!("Tablebase error: {:?}", err),
+            }
+        }
+        return Ok(());
+    }
+
+    //small tables are quick to generate, so the trainer does not need them on disk
+    if let Some(material) = &trainer && dtm.table(material).is_none() {
+        println!("Generating {}", material);
+        if let Err(err) = dtm.generate(material) {
+            panic!("Tablebase error: {:?}", err);
+        }
+    }
+    tablebase::init(Tablebases { syzygy, dtm });
+
+    if uci {
+        uci::run(threads, hash_mb, multi_pv.unwrap_or(1));
+        return Ok(());
+    }
+
+    if let Some(depth) = bench {
+        bench::bench(depth);
+        return Ok(());
+    }
+
+    if let Some((depth, max_threads)) = smp_bench {
+        bench::smp_speedup(depth, max_threads, hash_mb);
+        return Ok(());
+    }
+
+    if let Some(problem) = mate {
+        let (board, turn) = match &fen {
+            Some(fen) => match BoardB::from_fen(fen) {
+                Ok(position) => position,
+                Err(err) => panic!("Fen error: {:?}", err),
+            },
+            None => (BoardB::with_starting_pos(), Side::White),
+        };
+        let start = Instant::now();
+        let solution = mate::solve(&board, turn, problem);
+        for line in solution.lines() {
+            println!("{}", line);
+        }
+        let goal = match problem.stipulation {
+            Stipulation::Direct => "mate",
+            Stipulation::Selfmate => "selfmate",
+            Stipulation::Helpmate => "helpmate",
+        };
+        if !solution.solved() {
+            println!("no {} in {}", goal, problem.moves);
+        } else if solution.cooked() {
+            println!("{} in {} cooked, {} solutions", goal, problem.moves, solution.solutions());
+        } else {
+            println!("{} in {} sound", goal, problem.moves);
+        }
+        println!("{} positions in {:.2}s", solution.nodes, start.elapsed().as_secs_f64());
+        return Ok(());
+    }
+
+    //everything below uses randomness
+    println!("seed {}", seed::get());
+
+    if let Some(out) = datagen {
+        datagen_config.threads = threads;
+        match datagen::run(Path::new(&out), &datagen_config) {
+            Ok(stats) => println!(
+                "{} games played, {} positions written to {}, {} duplicates skipped",
+                stats.games, stats.positions, out, stats.duplicates
+            ),
+            Err(err) => panic!("Datagen error: {:?}", err),
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = epd {
+        epd_config.threads = threads;
+        epd_config.hash_mb = hash_mb;
+        if epd_config.depth.is_none() && epd_config.movetime.is_none() {
+            epd_config.movetime = Some(1000);
+        }
+        match epd::run(Path::new(&path), &epd_config) {
+            Ok(stats) => println!(
+                "{} of {} solved ({:.1}%), {} nodes in {:.1}s",
+                stats.solved, stats.positions, stats.solved as f64 * 100.0 / stats.positions as f64, stats.nodes, stats.ms as f64 / 1000.0
+            ),
+            Err(err) => panic!("Epd error: {:?}", err),
+        }
+        return Ok(());
+    }
+
+    if let Some((a, b)) = match_engines {
+        let (a, b) = match (EngineSpec::parse(&a), EngineSpec::parse(&b)) {
+            (Ok(a), Ok(b)) => (a, b),
+            (Err(err), _) | (_, Err(err)) => panic!("Match error: {:?}", err),
+        };
+        //fixed depth unless the match is limited otherwise
+        if match_config.nodes.is_none() && match_config.time_control.is_none() && match_config.depth.is_none() {
+            match_config.depth = Some(4);
+        }
+        match match_runner::run(&a, &b, &match_config) {
+            Ok(stats) => {
+                println!("{} games: +{} ={} -{}, score {:.1}%", stats.games(), stats.wins, stats.draws, stats.losses, stats.score() * 100.0);
+                match stats.elo() {
+                    Some((elo, margin)) => println!("elo difference {:+.1} +/- {:.1}", elo, margin),
+                    None => println!("elo difference can't be estimated yet"),
+                }
+                if let Some(sprt) = &match_config.sprt {
+                    let (lower, upper) = sprt.bounds();
+                    let verdict = match sprt.verdict(&stats) {
+                        Some(true) => "H1 accepted",
+                        Some(false) => "H0 accepted",
+                        None => "inconclusive",
+                    };
+                    println!("sprt [{}, {}]: llr {:.2} ({:.2}, {:.2}), {}", sprt.elo0, sprt.elo1, stats.llr(sprt), lower, upper, verdict);
+                }
+            },
+            Err(err) => panic!("Match error: {:?}", err),
+        }
+        return Ok(());
+    }
+
+    if let Some((pgn_dir, out)) = build_book {
+        match book_builder::build(Path::new(&pgn_dir), Path::new(&out), &book_filter) {
+            Ok(stats) => println!(
+                "{} games read, {} used, {} invalid, {} book entries written to {}",
+                stats.games, stats.used, stats.invalid, stats.entries, out
+            ),
+            Err(err) => panic!("Book builder error: {:?}", err),
+        }
+        return Ok(());
+    }
+
+    if let Some(path) = pgn_check {
+        let file = File::open(&path).unwrap_or_else(|err| panic!("Pgn error: {:?}", err));
+        let (mut games, mut moves, mut errors) = (0, 0, 0);
+        for game in PgnTreeReader::new(BufReader::new(file)) {
+            match game {
+                Ok(game) => {
+                    games += 1;
+                    moves += game.move_count();
+                },
+                Err(PgnError::Syntax(line, column, reason)) => {
+                    errors += 1;
+                    println!("{}:{}:{}: {}", path, line, column, reason);
+                },
+                Err(PgnError::IllegalMove(line, column, san, err)) => {
+                    errors += 1;
+                    println!("{}:{}:{}: {} {:?}", path, line, column, san, err);
+                },
+                Err(err) => panic!("Pgn error: {:?}", err),
+            }
+        }
+        println!("{} games with {} moves including variations, {} invalid", games, moves, errors);
+        return Ok(());
+    }
+
     let mut gameb = GameB::versus();
+    let mut rng = seed::rng(0);
 
 
     if let Some(ip)  = ip {
@@ -152,7 +660,6 @@ fn main() -> Result<(), String> {
     }
 
     if let Some(depth) = ai {
-        let mut rng = rand::thread_rng();
         let is_white: bool = rng.gen();
         gameb = GameB::cpu(depth, is_white)
     }
@@ -161,6 +668,22 @@ fn main() -> Result<(), String> {
         gameb = GameB::vcpu(depth)
     }
 
+    if let Some(path) = engine {
+        let player = match UciPlayer::start(Path::new(&path), &engine_options, engine_limits) {
+            Ok(player) => player,
+            Err(err) => panic!("Engine error: {:?}", err),
+        };
+        println!("Playing against {}", player.name);
+        //with --ai the engine plays our computer instead of you
+        let opponent = match ai {
+            Some(depth) => PlayerType::Cpu { depth },
+            None => PlayerType::Me,
+        };
+        let engine_white: bool = rng.gen();
+        let (white, black) = if engine_white {(PlayerType::Uci(player), opponent)} else {(opponent, PlayerType::Uci(player))};
+        gameb = GameB::new(white, black, engine_white);
+    }
+
     if let Some(fen) = fen {
        gameb = match GameB::from_fen(gameb.white, gameb.black, &fen, false) {
         Ok(g) => g,
@@ -168,6 +691,40 @@ fn main() -> Result<(), String> {
         }
     }
 
+    let dtm_tables = &tablebase::get().expect("Tablebases are initialized above").dtm;
+    let mut trainer = trainer.map(|material| {
+        //at least 10 plies, so the position is not solved at a glance
+        let (trainer, board) = Trainer::start(&material, dtm_tables, 10, &mut rng)
+            .expect("no won position for white in this table");
+        gameb = GameB::from_board(PlayerType::Me, PlayerType::Cpu { depth: ai.unwrap_or(1) }, board, Side::White, false);
+        trainer
+    });
+
+    let review_speed = Duration::from_millis(review_speed);
+    let mut review = review_file.map(|path| {
+        let file = File::open(&path).unwrap_or_else(|err| panic!("Pgn error: {:?}", err));
+        let tree = match PgnTreeReader::new(BufReader::new(file)).nth(review_game - 1) {
+            Some(Ok(tree)) => tree,
+            Some(Err(err)) => panic!("Pgn error: {:?}", err),
+            None => panic!("{} has less than {} games", path, review_game),
+        };
+        //leaving the review goes on from the end of the loaded game
+        gameb = match review::game_from_pgn(&tree) {
+            Ok(game) => game,
+            Err(err) => panic!("Pgn error: {:?}", err),
+        };
+        Review::new(&gameb, review_speed).expect("Game was replayed before")
+    });
+
+    //--mcts swaps the search of every cpu player
+    if let Some(config) = mcts {
+        for player in [&mut gameb.white, &mut gameb.black] {
+            if let PlayerType::Cpu { .. } = player {
+                *player = PlayerType::Mcts(config);
+            }
+        }
+    }
+
 
 
     let font_path = &Path::new("../../res/IBMPlexSerif-Medium.ttf");
@@ -177,6 +734,7 @@ fn main() -> Result<(), String> {
     let ttf_context = sdl2::ttf::init().map_err(|e| e.to_string())?;
     let mut font = ttf_context.load_font(font_path, 128)?;
     font.set_style(sdl2::ttf::FontStyle::BOLD);
+    let panel_font = ttf_context.load_font(font_path, 18)?;
 
 
     let window = video_subsystem.window("Chess", 720, 720)
@@ -206,9 +764,36 @@ fn main() -> Result<(), String> {
     let mut color_lifted = true;
     let mut pieces_lifted = true;
 
-    let mut renderer = Renderer::new(&tex_atlas, &mut canvas);
+    let mut renderer = Renderer::new(&tex_atlas, &mut canvas, &panel_font);
     let mut game_renderer = GameRenderer::new(field_size, board_size, 200.0);
     let (progress_sender, progress_rx) = mpsc::channel();
+    let tt = Arc::new(TranspositionTable::new(hash_mb));
+    let book = book.map(|path| match OpeningBook::load(Path::new(&path)) {
+        Ok(book) => {println!("Loaded {} book entries from {}", book.len(), path); book},
+        Err(err) => panic!("Book error: {:?}", err),
+    });
+    let mut analysis: Option<Analysis> = None;
+    let mut analysis_lifted = true;
+    //the side panel shows the analysis, the moves of the review or both
+    let mut panel_open = false;
+    let mut review_lifted = true;
+    let mut first_lifted = true;
+    let mut last_lifted = true;
+    let mut autoplay_lifted = true;
+    let mut faster_lifted = true;
+    let mut slower_lifted = true;
+    let mut branch_lifted = true;
+    let mut new_game_lifted = true;
+    let mut undo_lifted = true;
+    let mut redo_lifted = true;
+    let mut accept_lifted = true;
+    let mut takebacks = Takebacks::default();
+    let mut export_lifted = true;
+    //generation of the finished game that was saved last
+    let mut saved_generation = None;
+    //without --pgn-dir games are only saved on request, to the working directory
+    let autosave = pgn_dir.is_some();
+    let pgn_dir = Path::new(pgn_dir.as_deref().unwrap_or("."));
 
 
 
@@ -216,69 +801,8 @@ fn main() -> Result<(), String> {
 
 
 
-    fn spawn_move_computer(board: BoardB, depth: usize, turn: Side, progress_sender: Sender<f32>) -> JoinHandle<(u8, u8)> { 
-        std::thread::spawn(move || {
-            let mut next_moves_by_piece = HashMap::with_capacity(16);
-            board.valid_moves(turn, &mut next_moves_by_piece);
-            let mut best_move = ((0,0), i32::MIN);
-            let mvs = next_moves_by_piece.iter().flat_map(|(from, tos)| tos.ones().iter().map(|to| (*from, *to)).collect::<Vec<_>>()).collect::<Vec<(u8, u8)>>();
-            let total = mvs.len();
-            let mut progress = 0;
-            for (from, to) in mvs {
-                progress += 1;
-                progress_sender.send(progress as f32 / total as f32).unwrap();
-                let mut b = board;
-                b.make_move(from, to);
-                let eval = compute_best_move(b, depth - 1, !turn, turn, i32::MIN, i32::MAX);
-                if eval > best_move.1 {
-                    best_move = ((from, to), eval);
-                }
-                println!("from: {} to: {} val: {}", Pos::from_i(from), Pos::from_i(to), eval);
-            }
-            println!("done");
-            best_move.0
-        })
-    }
-
-
-
-
-    fn compute_best_move(board: BoardB, depth: usize, turn: Side, max: Side, mut alpha: i32, mut beta: i32) -> i32 { 
-        let mut next_moves_by_piece = HashMap::with_capacity(16);
-        board.valid_moves(turn, &mut next_moves_by_piece);
-        /*if next_moves_by_piece.len() == 0 {
-            if board.is_check(&board.valid_moves_as_array(!turn, false, true), turn) {
-                return if turn == max {i32::MIN} else {i32::MAX}
-            }
-            return i32::MIN
-        }*/
-        if depth == 0 || next_moves_by_piece.len() == 0 {
-            return board.evaluate(max);
-        }
-        let mut t_eval = if turn == max {i32::MIN} else {i32::MAX};
-        let mvs = next_moves_by_piece.iter().flat_map(|(from, tos)| tos.ones().iter().map(|to| (*from, *to)).collect::<Vec<_>>()).collect::<Vec<(u8, u8)>>();
-        for (from, to) in mvs {
-            let mut b = board;
-            b.make_move(from, to);
-            let eval = compute_best_move(b, depth - 1, !turn, max, alpha, beta);
-            if turn == max {
-                t_eval = t_eval.max(eval);
-                alpha = alpha.max(eval);
-                if beta <= alpha {
-                    break;
-                }
-            } else {
-                t_eval = t_eval.min(eval);
-                beta = beta.min(eval);
-                if beta <= alpha {
-                    break;
-                }
-            }
-        }  
-        return t_eval;
-    }
-
-    let mut next_move_option: Option<JoinHandle<(u8, u8)>> = None;
+    let mut next_move_option: Option<SearchHandle> = None;
+    let mut pondering: Option<Ponder> = None;
 
     'running: loop {
         let current_frame_time = Instant::now();
@@ -304,32 +828,183 @@ fn main() -> Result<(), String> {
         if inputs.pressed(Control::Escape) {
             game_renderer.unselect();
         }
+        if inputs.pressed(Control::Analysis) && analysis_lifted {
+            match analysis.take() {
+                Some(mut a) => a.stop(),
+                None => analysis = Some(Analysis::new(multi_pv.unwrap_or(3), threads, tt.clone())),
+            }
+        }
+        if inputs.pressed(Control::Review) && review_lifted {
+            review = match review.take() {
+                Some(_) => None,
+                None => Some(Review::new(&gameb, review_speed).expect("Start position of the game is valid")),
+            };
+            game_renderer.unselect();
+        }
+        //while reviewing the arrows step through the moves instead of taking them back
+        if let Some(review) = &mut review {
+            if inputs.pressed(Control::Undo) && undo_lifted {
+                review.prev();
+            }
+            if inputs.pressed(Control::Redo) && redo_lifted {
+                review.next();
+            }
+            if inputs.pressed(Control::First) && first_lifted {
+                review.first();
+            }
+            if inputs.pressed(Control::Last) && last_lifted {
+                review.last();
+            }
+            if inputs.pressed(Control::Autoplay) && autoplay_lifted {
+                review.toggle_autoplay();
+            }
+            if inputs.pressed(Control::ZoomIn) && faster_lifted {
+                review.faster();
+            }
+            if inputs.pressed(Control::ZoomOut) && slower_lifted {
+                review.slower();
+            }
+            if inputs.pressed(Control::Branch) && branch_lifted {
+                review.branch();
+            }
+            review.update();
+        }
+        if (analysis.is_some() || review.is_some()) != panel_open {
+            panel_open = !panel_open;
+            renderer.set_window_width(screen_size.x + if panel_open {game_renderer::ANALYSIS_WIDTH} else {0});
+        }
+        if inputs.pressed(Control::NewGame) && new_game_lifted && review.is_none() {
+            if gameb.white.is_remote() || gameb.black.is_remote() {
+                println!("network games can't be restarted");
+            } else {
+                //dropping the handles stops the searches, their moves would be stale anyway
+                next_move_option = None;
+                pondering = None;
+                gameb.restart();
+                game_renderer.unselect();
+                game_renderer.ai_progess = (None, None);
+                while progress_rx.try_recv().is_ok() {}
+                if let Some(trainer) = &mut trainer {
+                    trainer.retry();
+                }
+                println!("new game");
+            }
+        }
+        if inputs.pressed(Control::Undo) && undo_lifted && review.is_none() {
+            if gameb.white.is_remote() || gameb.black.is_remote() {
+                //our last move, and the opponent's answer if there is one
+                let plies = if gameb.turn().is_me() {2} else {1};
+                if gameb.moves.len() >= plies as usize && let Some(remote) = gameb.remote_mut() {
+                    remote.send_takeback(Takeback::Request(plies));
+                    takebacks.asked = Some(plies);
+                    println!("asked to take back {} plies", plies);
+                }
+            } else if gameb.undo() {
+                next_move_option = None;
+                pondering = None;
+                //against the computer it would just play again, so take back to our own move
+                while gameb.turn().is_ai() && !(gameb.white.is_ai() && gameb.black.is_ai()) && gameb.undo() {}
+                game_renderer.unselect();
+            }
+        }
+        if inputs.pressed(Control::Redo) && redo_lifted && review.is_none() && !gameb.white.is_remote() && !gameb.black.is_remote() && gameb.redo() {
+            next_move_option = None;
+            pondering = None;
+            while gameb.turn().is_ai() && !(gameb.white.is_ai() && gameb.black.is_ai()) && gameb.redo() {}
+            game_renderer.unselect();
+        }
+        if inputs.pressed(Control::Accept) && accept_lifted && let Some(plies) = takebacks.offered.take() {
+            if let Some(remote) = gameb.remote_mut() {
+                remote.send_takeback(Takeback::Accept(plies));
+            }
+            for _ in 0..plies {
+                gameb.undo();
+            }
+            game_renderer.unselect();
+        }
+        if inputs.pressed(Control::Export) && export_lifted {
+            match pgn::save(pgn_dir, &gameb, pgn_export) {
+                Ok(path) => println!("game saved to {}", path.display()),
+                Err(err) => eprintln!("Pgn error: {:?}", err),
+            }
+        }
         color_lifted = !inputs.pressed(Control::Color);
         pieces_lifted = !inputs.pressed(Control::Pieces);
+        analysis_lifted = !inputs.pressed(Control::Analysis);
+        new_game_lifted = !inputs.pressed(Control::NewGame);
+        undo_lifted = !inputs.pressed(Control::Undo);
+        redo_lifted = !inputs.pressed(Control::Redo);
+        accept_lifted = !inputs.pressed(Control::Accept);
+        export_lifted = !inputs.pressed(Control::Export);
+        review_lifted = !inputs.pressed(Control::Review);
+        first_lifted = !inputs.pressed(Control::First);
+        last_lifted = !inputs.pressed(Control::Last);
+        autoplay_lifted = !inputs.pressed(Control::Autoplay);
+        faster_lifted = !inputs.pressed(Control::ZoomIn);
+        slower_lifted = !inputs.pressed(Control::ZoomOut);
+        branch_lifted = !inputs.pressed(Control::Branch);
 
-        if inputs.left_click {
-            if let Some(selected) = game_renderer.selected && gameb.turn().is_me() {
-                gameb.make_move(selected, cursor_field);
+        //clicks on the analysis panel are not on the board, the reviewed game only takes moves after branching off
+        let board_game = match &mut review {
+            Some(review) => review.branched().then_some(&mut review.game),
+            None => Some(&mut gameb),
+        };
+        if inputs.left_click && cursor_field_xy.x < 8 && let Some(game) = board_game {
+            if let Some(selected) = game_renderer.selected && game.turn().is_me() {
+                game.make_move(selected, cursor_field);
                 game_renderer.unselect();
             } else {
-                game_renderer.select(cursor_field, gameb.turn, &gameb.board);
+                game_renderer.select(cursor_field, game.turn, &game.board);
             }
         }
+        if inputs.left_click && let Some(review) = &mut review && let Some(ply) = game_renderer.move_list_ply() {
+            review.goto(ply);
+            game_renderer.unselect();
+        }
 
         game_renderer.update_mouse_pos(inputs.mouse_pos);
-        game_renderer.render(&gameb, &mut renderer, dt);
+        let shown = review.as_ref().map_or(&gameb, |review| &review.game);
+        game_renderer.render(shown, &mut renderer, dt);
+        let mut panel_rows = 0;
+        if let Some(analysis) = &mut analysis {
+            analysis.update(shown);
+            let rows = analysis.rows();
+            game_renderer.draw_analysis(&rows, &mut renderer);
+            panel_rows = rows.len() + 1;
+        }
+        if let Some(review) = &review {
+            game_renderer.draw_move_list(review, panel_rows, &mut renderer);
+        }
         renderer.render();
 
+        if let Some(trainer) = &mut trainer {
+            trainer.update(&gameb, dtm_tables);
+        }
+
         if let GameState::Winner(w) = gameb.state {
             println!("{} won !!", w);
         } else if GameState::Draw == gameb.state {
             println!("draw");
         }
+        if autosave && gameb.state != GameState::Running && saved_generation != Some(gameb.generation()) {
+            saved_generation = Some(gameb.generation());
+            match pgn::save(pgn_dir, &gameb, pgn_export) {
+                Ok(path) => println!("game saved to {}", path.display()),
+                Err(err) => eprintln!("Pgn error: {:?}", err),
+            }
+        }
+
+        //the game moved on without the search, like after a restart
+        if next_move_option.as_ref().is_some_and(|search| search.generation != gameb.generation()) {
+            next_move_option = None;
+        }
 
+        try_apply_remote_move(&mut gameb, &mut takebacks);
         if gameb.state == GameState::Running {
             match gameb.turn() {
-                PlayerType::Remote(_) => try_apply_remote_move(&mut gameb),
+                PlayerType::Uci(_) => try_apply_engine_move(&mut gameb),
                 PlayerType::Cpu { depth } => {
+                    let config = SearchConfig { depth: *depth, threads, ..Default::default() };
                     match progress_rx.try_recv() {
                         Ok(progress) => {
                             match gameb.turn {
@@ -340,13 +1015,41 @@ fn main() -> Result<(), String> {
                         Err(TryRecvError::Empty) => {},
                         Err(TryRecvError::Disconnected) => {},
                     }
+                    //the opponent moved, the ponder search either goes on or is thrown away
+                    if next_move_option.is_none() && let Some(ponder) = pondering.take() {
+                        next_move_option = ponder.resolve(&gameb.board, gameb.turn, gameb.generation());
+                    }
                     if let Some(next_move) = &next_move_option {
                         if next_move.is_finished() {
-                            let mv = next_move_option.take().unwrap().join().expect("Thread couldnt be joined");
-                            gameb.make_move(mv.0, mv.1);
+                            let cpu_move = next_move_option.take().unwrap().join();
+                            if let Some(CpuMove { mv, ponder: reply, eval }) = cpu_move {
+                                if gameb.make_move(mv.0, mv.1) && let Some((score, depth)) = eval {
+                                    gameb.annotate_eval(score, depth);
+                                }
+                                if ponder && gameb.state == GameState::Running && !gameb.turn().is_ai() && let Some(reply) = reply {
+                                    let config = SearchConfig { seed: rng.gen(), ..config };
+                                    pondering = Ponder::start(gameb.board, gameb.turn, reply, config, skill, tt.clone());
+                                }
+                            }
                         }
+                    } else if let Some(book) = &book && let Some(mv) = book.pick(&gameb.board, gameb.turn, book_best, &mut rng) {
+                        println!("book: {} -> {} weight: {}", Pos::from_i(mv.from), Pos::from_i(mv.to), mv.weight);
+                        gameb.make_book_move(mv.from, mv.to);
                     } else {
-                        next_move_option = Some(spawn_move_computer(gameb.board, *depth, gameb.turn, progress_sender.clone()));
+                        let config = SearchConfig { seed: rng.gen(), ..config };
+                        next_move_option = Some(search::spawn_move_computer(gameb.board, gameb.turn, config, skill, tt.clone(), Some(progress_sender.clone()), gameb.generation()));
+                    }
+                },
+                PlayerType::Mcts(config) => {
+                    let config = *config;
+                    if let Some(next_move) = &next_move_option {
+                        if next_move.is_finished() && let Some(CpuMove { mv, eval, .. }) = next_move_option.take().unwrap().join()
+                            && gameb.make_move(mv.0, mv.1) && let Some((score, depth)) = eval
+                        {
+                            gameb.annotate_eval(score, depth);
+                        }
+                    } else {
+                        next_move_option = Some(mcts::spawn_move_computer(gameb.board, gameb.turn, config, rng.gen(), gameb.generation()));
                     }
                 },
                 _ => {}
@@ -359,5 +1062,16 @@ fn main() -> Result<(), String> {
 
     }
 
+    //no search thread outlives the window
+    if let Some(search) = next_move_option {
+        search.cancel();
+    }
+    if let Some(ponder) = pondering {
+        ponder.cancel();
+    }
+    if let Some(analysis) = &mut analysis {
+        analysis.stop();
+    }
+
     Ok(())
 }
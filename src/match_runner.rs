@@ -0,0 +1,609 @@
+use std::{fs::File, io::{BufRead, BufReader, BufWriter, Write}, path::{Path, PathBuf}, sync::{atomic::{AtomicBool, AtomicUsize, Ordering}, Arc, Mutex}, time::Instant};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng};
+
+use crate::{boardb::BoardB, gameb::{GameB, GameState, PlayerType}, pgn, pieces::{PieceType, Side}, san, seed, search::{self, SearchConfig}, skill::Skill, tt::TranspositionTable, uci, uci_client::{Clock, GoResult, Limits, UciEngine, UciError}, mcts::{self, MctsConfig, Playout}};
+
+
+
+#[derive(Debug)]
+pub enum MatchError {
+    Io(std::io::Error),
+    Engine(UciError),
+    //engine description that can't be parsed
+    Spec(String),
+    //(line, content) of the opening file
+    Opening(usize, String),
+}
+
+//One side of the match, either our own search or an external UCI engine.
+//Written as cp[:key=value,...] with depth, skill and hash, mcts[:key=value,...] with iterations,
+//c, playout and plies, or uci:<path>[,Option=value,...]
+#[derive(Clone, Debug)]
+pub enum EngineSpec {
+    Internal {
+        //replaces the depth of the match limits
+        depth: Option<usize>,
+        skill: Option<Skill>,
+        hash_mb: usize,
+    },
+    Mcts {
+        //replaces the nodes of the match limits
+        iterations: Option<usize>,
+        config: MctsConfig,
+    },
+    Uci {
+        path: PathBuf,
+        options: Vec<(String, String)>,
+    },
+}
+
+impl EngineSpec {
+    pub fn parse(spec: &str) -> Result<Self, MatchError> {
+        let err = || MatchError::Spec(spec.to_owned());
+        if let Some(rest) = spec.strip_prefix("uci:") {
+            let mut parts = rest.split(',');
+            let path = PathBuf::from(parts.next().filter(|p| !p.is_empty()).ok_or_else(err)?);
+            let options = parts
+                .map(|o| o.split_once('=').map(|(n, v)| (n.to_owned(), v.to_owned())).ok_or_else(err))
+                .collect::<Result<_, _>>()?;
+            return Ok(EngineSpec::Uci { path, options });
+        }
+        if let Some(rest) = spec.strip_prefix("mcts") {
+            let (mut iterations, mut config) = (None, MctsConfig::default());
+            if let Some(rest) = rest.strip_prefix(':') {
+                for option in rest.split(',') {
+                    let (name, value) = option.split_once('=').ok_or_else(err)?;
+                    match name {
+                        "iterations" => iterations = Some(value.parse().map_err(|_| err())?),
+                        "c" => config.exploration = value.parse().map_err(|_| err())?,
+                        "plies" => config.playout_plies = value.parse().map_err(|_| err())?,
+                        "playout" => config.playout = match value {
+                            "random" => Playout::Random,
+                            "eval" => Playout::Eval,
+                            _ => return Err(err()),
+                        },
+                        _ => return Err(err()),
+                    }
+                }
+            } else if !rest.is_empty() {
+                return Err(err());
+            }
+            return Ok(EngineSpec::Mcts { iterations, config });
+        }
+        let rest = spec.strip_prefix("cp").ok_or_else(err)?;
+        let (mut depth, mut skill, mut hash_mb) = (None, None, 16);
+        if let Some(rest) = rest.strip_prefix(':') {
+            for option in rest.split(',') {
+                let (name, value) = option.split_once('=').ok_or_else(err)?;
+                let value = value.parse::<usize>().map_err(|_| err())?;
+                match name {
+                    "depth" => depth = Some(value),
+                    "skill" => skill = Some(Skill::new(value as u8)),
+                    "hash" => hash_mb = value,
+                    _ => return Err(err()),
+                }
+            }
+        } else if !rest.is_empty() {
+            return Err(err());
+        }
+        Ok(EngineSpec::Internal { depth, skill, hash_mb })
+    }
+}
+
+//base time and increment in milliseconds
+#[derive(Clone, Copy, Debug)]
+pub struct TimeControl {
+    pub base: u64,
+    pub inc: u64,
+}
+
+impl TimeControl {
+    //"10+0.1" in seconds
+    pub fn parse(tc: &str) -> Option<Self> {
+        let (base, inc) = tc.split_once('+').unwrap_or((tc, "0"));
+        let ms = |s: &str| s.parse::<f64>().ok().filter(|v| *v >= 0.0).map(|v| (v * 1000.0) as u64);
+        Some(Self { base: ms(base)?, inc: ms(inc)? })
+    }
+}
+
+//sequential probability ratio test between "A is elo0 stronger" (H0) and "A is elo1 stronger" (H1)
+#[derive(Clone, Copy, Debug)]
+pub struct Sprt {
+    pub elo0: f64,
+    pub elo1: f64,
+    pub alpha: f64,
+    pub beta: f64,
+}
+
+impl Sprt {
+    pub fn new(elo0: f64, elo1: f64) -> Self {
+        Self { elo0, elo1, alpha: 0.05, beta: 0.05 }
+    }
+
+    //(lower, upper) log likelihood ratio bounds
+    pub fn bounds(&self) -> (f64, f64) {
+        ((self.beta / (1.0 - self.alpha)).ln(), ((1.0 - self.beta) / self.alpha).ln())
+    }
+
+    //Some(true) once H1 is accepted, Some(false) for H0
+    pub fn verdict(&self, stats: &MatchStats) -> Option<bool> {
+        let llr = stats.llr(self);
+        let (lower, upper) = self.bounds();
+        if llr >= upper {
+            Some(true)
+        } else if llr <= lower {
+            Some(false)
+        } else {
+            None
+        }
+    }
+}
+
+pub struct MatchConfig {
+    pub games: usize,
+    //games played at the same time, every one with its own engine instances
+    pub concurrency: usize,
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub time_control: Option<TimeControl>,
+    //fen or epd file, every opening is played twice with colors swapped
+    pub openings: Option<PathBuf>,
+    //random moves as opening when there is no opening file
+    pub random_plies: usize,
+    //games still running after this many plies are drawn
+    pub max_plies: usize,
+    pub pgn: Option<PathBuf>,
+    //stops the match early once it has a verdict
+    pub sprt: Option<Sprt>,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        Self { games: 100, concurrency: 1, depth: None, nodes: None, time_control: None, openings: None, random_plies: 6, max_plies: 400, pgn: None, sprt: None }
+    }
+}
+
+//results from the view of engine A
+#[derive(Default, Debug, Clone, Copy)]
+pub struct MatchStats {
+    pub wins: usize,
+    pub draws: usize,
+    pub losses: usize,
+}
+
+impl MatchStats {
+    pub fn games(&self) -> usize {
+        self.wins + self.draws + self.losses
+    }
+
+    pub fn score(&self) -> f64 {
+        (self.wins as f64 + self.draws as f64 / 2.0) / self.games().max(1) as f64
+    }
+
+    //(elo difference, 95% error margin), None while one side has all the points
+    pub fn elo(&self) -> Option<(f64, f64)> {
+        let n = self.games() as f64;
+        let s = self.score();
+        if s <= 0.0 || s >= 1.0 {
+            return None;
+        }
+        let variance = (self.wins as f64 * (1.0 - s).powi(2) + self.draws as f64 * (0.5 - s).powi(2) + self.losses as f64 * s.powi(2)) / n;
+        let margin = 1.96 * (variance / n).sqrt();
+        let (low, high) = ((s - margin).max(1e-6), (s + margin).min(1.0 - 1e-6));
+        Some((elo(s), (elo(high) - elo(low)) / 2.0))
+    }
+
+    //Approximate log likelihood ratio of the trinomial results. Results that are all the same
+    //have no spread, one made up win and loss give them some so a clean sweep still ends.
+    pub fn llr(&self, sprt: &Sprt) -> f64 {
+        if self.games() == 0 {
+            return 0.0;
+        }
+        let (mut wins, draws, mut losses) = (self.wins as f64, self.draws as f64, self.losses as f64);
+        if [self.wins, self.draws, self.losses].contains(&self.games()) {
+            wins += 1.0;
+            losses += 1.0;
+        }
+        let n = wins + draws + losses;
+        let (w, d) = (wins / n, draws / n);
+        let s = w + d / 2.0;
+        let variance = (w + d / 4.0 - s * s) / n;
+        let (s0, s1) = (score(sprt.elo0), score(sprt.elo1));
+        (s1 - s0) * (2.0 * s - s0 - s1) / (2.0 * variance)
+    }
+}
+
+fn elo(score: f64) -> f64 {
+    -400.0 * (1.0 / score - 1.0).log10()
+}
+
+fn score(elo: f64) -> f64 {
+    1.0 / (1.0 + 10_f64.powf(-elo / 400.0))
+}
+
+struct Opening {
+    board: BoardB,
+    turn: Side,
+    fen: String,
+    fullmove: usize,
+}
+
+impl Opening {
+    fn from_fen(fen: &str) -> Option<Self> {
+        let (board, turn) = BoardB::from_fen(fen).ok()?;
+        let fullmove = fen.split_whitespace().nth(5)?.parse().ok()?;
+        Some(Self { board, turn, fen: fen.to_owned(), fullmove })
+    }
+}
+
+enum Player {
+    Internal {
+        name: String,
+        depth: Option<usize>,
+        skill: Option<Skill>,
+        tt: TranspositionTable,
+    },
+    Mcts {
+        name: String,
+        iterations: Option<usize>,
+        config: MctsConfig,
+    },
+    External(UciEngine),
+}
+
+impl Player {
+    fn start(spec: &EngineSpec) -> Result<Self, MatchError> {
+        Ok(match spec {
+            EngineSpec::Internal { depth, skill, hash_mb } => {
+                let mut name = "chess-cp".to_owned();
+                if let Some(depth) = depth {
+                    name += &format!(" depth {}", depth);
+                }
+                if let Some(skill) = skill {
+                    name += &format!(" skill {}", skill.level);
+                }
+                Player::Internal { name, depth: *depth, skill: *skill, tt: TranspositionTable::new(*hash_mb) }
+            },
+            EngineSpec::Mcts { iterations, config } => {
+                let mut name = "chess-cp mcts".to_owned();
+                if let Some(iterations) = iterations {
+                    name += &format!(" iterations {}", iterations);
+                }
+                Player::Mcts { name, iterations: *iterations, config: *config }
+            },
+            EngineSpec::Uci { path, options } => Player::External(UciEngine::start(path, options).map_err(MatchError::Engine)?),
+        })
+    }
+
+    fn name(&self) -> &str {
+        match self {
+            Player::Internal { name, .. } => name,
+            Player::Mcts { name, .. } => name,
+            Player::External(engine) => &engine.name,
+        }
+    }
+
+    fn new_game(&mut self) -> Result<(), MatchError> {
+        match self {
+            Player::Internal { tt, .. } => tt.clear(),
+            //the tree is built fresh every move
+            Player::Mcts { .. } => {},
+            Player::External(engine) => engine.new_game().map_err(MatchError::Engine)?,
+        }
+        Ok(())
+    }
+
+    //None for an illegal move of an external engine
+    fn go(&mut self, opening: &Opening, moves: &[String], game: &GameB, limits: &Limits, rng: &mut StdRng) -> Result<Option<GoResult>, MatchError> {
+        match self {
+            Player::Internal { depth, skill, tt, .. } => {
+                let timed = limits.nodes.is_some() || limits.time(game.turn).is_some();
+                let config = SearchConfig {
+                    depth: depth.or(limits.depth).unwrap_or(if timed {search::MAX_DEPTH} else {SearchConfig::default().depth}),
+                    nodes: limits.nodes,
+                    seed: rng.gen(),
+                    ..Default::default()
+                };
+                let config = skill.map_or(config, |skill| skill.apply(config));
+                let stop = Arc::new(AtomicBool::new(false));
+                if let Some(clock) = limits.clock {
+                    let (time, inc) = match game.turn {
+                        Side::White => (clock.wtime, clock.winc),
+                        Side::Black => (clock.btime, clock.binc),
+                    };
+                    uci::stop_after(stop.clone(), uci::time_budget(time, inc, None));
+                }
+                let result = search::analyse(game.board, game.turn, config, tt, &stop, &mut |_| {});
+                let Some(mut mv) = result.mv else {
+                    return Ok(None);
+                };
+                if let Some(skill) = skill {
+                    mv = skill.pick(&result.lines, rng);
+                }
+                Ok(Some(GoResult { mv: (mv.0, mv.1, PieceType::Queen), score: Some(result.score), depth: result.depth }))
+            },
+            Player::Mcts { iterations, config, .. } => {
+                let timed = limits.time(game.turn).is_some();
+                let iterations = iterations.or(limits.nodes.map(|n| n as usize)).unwrap_or(if timed {usize::MAX} else {config.iterations});
+                let stop = Arc::new(AtomicBool::new(false));
+                if let Some(clock) = limits.clock {
+                    let (time, inc) = match game.turn {
+                        Side::White => (clock.wtime, clock.winc),
+                        Side::Black => (clock.btime, clock.binc),
+                    };
+                    uci::stop_after(stop.clone(), uci::time_budget(time, inc, None));
+                }
+                let result = mcts::search(game.board, game.turn, MctsConfig { iterations, ..*config }, &stop, rng);
+                Ok(result.mv.map(|mv| GoResult { mv: (mv.0, mv.1, PieceType::Queen), score: Some(result.score()), depth: result.pv.len() }))
+            },
+            Player::External(engine) => {
+                let start_fen = (opening.fen != pgn::START_FEN).then_some(opening.fen.as_str());
+                match engine.go(start_fen, moves, &game.board, game.turn, limits) {
+                    Ok(result) => Ok(Some(result)),
+                    Err(UciError::IllegalMove(mv)) => {
+                        eprintln!("{} played the illegal move {}", engine.name, mv);
+                        Ok(None)
+                    },
+                    Err(err) => Err(MatchError::Engine(err)),
+                }
+            },
+        }
+    }
+}
+
+struct GameRecord {
+    moves: Vec<(String, Option<String>)>,
+    result: &'static str,
+    termination: Option<&'static str>,
+}
+
+struct Output {
+    pgn: Option<BufWriter<File>>,
+    stats: MatchStats,
+}
+
+//Plays engine a against engine b and returns the results from a's view. Game 2n and 2n+1 start
+//from the same opening, a has white in the even ones.
+pub fn run(a: &EngineSpec, b: &EngineSpec, config: &MatchConfig) -> Result<MatchStats, MatchError> {
+    let pairs = config.games.div_ceil(2);
+    let openings = match &config.openings {
+        Some(path) => load_openings(path)?,
+        None => random_openings(pairs, config.random_plies),
+    };
+    let pgn = match &config.pgn {
+        Some(path) => Some(BufWriter::new(File::create(path).map_err(MatchError::Io)?)),
+        None => None,
+    };
+    let output = Mutex::new(Output { pgn, stats: MatchStats::default() });
+    let next_game = AtomicUsize::new(0);
+    let finished = AtomicBool::new(false);
+
+    std::thread::scope(|s| {
+        let handles: Vec<_> = (0..config.concurrency.max(1)).map(|_| s.spawn(|| -> Result<(), MatchError> {
+            let mut players = [Player::start(a)?, Player::start(b)?];
+            loop {
+                let n = next_game.fetch_add(1, Ordering::Relaxed);
+                if n >= config.games || finished.load(Ordering::Relaxed) {
+                    return Ok(());
+                }
+                let opening = &openings[n / 2 % openings.len()];
+                let a_white = n % 2 == 0;
+                let [pa, pb] = &mut players;
+                let (white, black) = if a_white {(pa, pb)} else {(pb, pa)};
+                let names = (white.name().to_owned(), black.name().to_owned());
+                //seeded by game number, which thread plays it doesn't matter
+                let record = play_game(white, black, opening, config, &mut seed::rng(n as u64 + 1))?;
+
+                let mut output = output.lock().expect("Match output poisoned");
+                let output = &mut *output;
+                match (record.result, a_white) {
+                    ("1-0", true) | ("0-1", false) => output.stats.wins += 1,
+                    ("1-0", false) | ("0-1", true) => output.stats.losses += 1,
+                    _ => output.stats.draws += 1,
+                }
+                if let Some(pgn) = &mut output.pgn {
+                    write_pgn(pgn, n + 1, &names, opening, &record, config).map_err(MatchError::Io)?;
+                }
+                let stats = output.stats;
+                print!("game {}: {} - {} {}", n + 1, names.0, names.1, record.result);
+                if let Some(termination) = record.termination {
+                    print!(" ({})", termination);
+                }
+                println!("   +{} ={} -{}", stats.wins, stats.draws, stats.losses);
+                if let Some(sprt) = &config.sprt && sprt.verdict(&stats).is_some() {
+                    finished.store(true, Ordering::Relaxed);
+                }
+            }
+        })).collect();
+        handles.into_iter().try_for_each(|h| h.join().expect("Match thread panicked"))
+    })?;
+
+    let mut output = output.into_inner().expect("Match output poisoned");
+    if let Some(pgn) = &mut output.pgn {
+        pgn.flush().map_err(MatchError::Io)?;
+    }
+    Ok(output.stats)
+}
+
+fn play_game(white: &mut Player, black: &mut Player, opening: &Opening, config: &MatchConfig, rng: &mut StdRng) -> Result<GameRecord, MatchError> {
+    white.new_game()?;
+    black.new_game()?;
+    let mut game = GameB::from_board(PlayerType::Cpu { depth: 0 }, PlayerType::Cpu { depth: 0 }, opening.board, opening.turn, false);
+    let mut clock = config.time_control.map(|tc| Clock { wtime: tc.base, btime: tc.base, winc: tc.inc, binc: tc.inc });
+    let mut moves = Vec::new();
+    let mut uci_moves = Vec::new();
+    while game.state == GameState::Running {
+        if game.ply >= config.max_plies {
+            return Ok(GameRecord { moves, result: "1/2-1/2", termination: Some("adjudication") });
+        }
+        let limits = Limits { depth: config.depth, nodes: config.nodes, movetime: None, clock };
+        let player = match game.turn {
+            Side::White => &mut *white,
+            Side::Black => &mut *black,
+        };
+        let start = Instant::now();
+        let answer = player.go(opening, &uci_moves, &game, &limits, rng)?;
+        let elapsed = start.elapsed().as_millis() as u64;
+        let loss = if game.turn == Side::White {"0-1"} else {"1-0"};
+        let Some(GoResult { mv: (from, to, _), score, depth }) = answer else {
+            return Ok(GameRecord { moves, result: loss, termination: Some("rules infraction") });
+        };
+        if let Some(clock) = &mut clock {
+            let (time, inc) = match game.turn {
+                Side::White => (&mut clock.wtime, clock.winc),
+                Side::Black => (&mut clock.btime, clock.binc),
+            };
+            if elapsed > *time {
+                return Ok(GameRecord { moves, result: loss, termination: Some("time forfeit") });
+            }
+            *time = *time - elapsed + inc;
+        }
+        let san = san::to_san(&game.board, game.turn, from, to);
+        let uci_move = uci::move_to_uci(&game.board, from, to);
+        if !game.make_move(from, to) {
+            return Ok(GameRecord { moves, result: loss, termination: Some("rules infraction") });
+        }
+        //score from the mover's view and thinking time, like other match tools write it
+        let score = match score.map(search::mate_in) {
+            Some(Some(mate)) => format!("{}M{}/{} ", if mate > 0 {"+"} else {"-"}, mate.abs(), depth),
+            Some(None) => format!("{:+.2}/{} ", score.unwrap_or(0) as f64 / 100.0, depth),
+            None => String::new(),
+        };
+        moves.push((san, Some(format!("{}{:.2}s", score, elapsed as f64 / 1000.0))));
+        uci_moves.push(uci_move);
+    }
+    let result = match game.state {
+        GameState::Winner(Side::White) => "1-0",
+        GameState::Winner(Side::Black) => "0-1",
+        _ => "1/2-1/2",
+    };
+    Ok(GameRecord { moves, result, termination: None })
+}
+
+fn write_pgn<W: Write>(out: &mut W, round: usize, names: &(String, String), opening: &Opening, record: &GameRecord, config: &MatchConfig) -> std::io::Result<()> {
+    let mut tags = vec![
+        ("Event".to_owned(), "chess-cp match".to_owned()),
+        ("Site".to_owned(), "?".to_owned()),
+        ("Date".to_owned(), pgn::today()),
+        ("Round".to_owned(), round.to_string()),
+        ("White".to_owned(), names.0.clone()),
+        ("Black".to_owned(), names.1.clone()),
+        ("Result".to_owned(), record.result.to_owned()),
+    ];
+    if opening.fen != pgn::START_FEN {
+        tags.push(("SetUp".to_owned(), "1".to_owned()));
+        tags.push(("FEN".to_owned(), opening.fen.clone()));
+    }
+    if let Some(tc) = config.time_control {
+        tags.push(("TimeControl".to_owned(), format!("{}+{}", tc.base as f64 / 1000.0, tc.inc as f64 / 1000.0)));
+    }
+    if let Some(termination) = record.termination {
+        tags.push(("Termination".to_owned(), termination.to_owned()));
+    }
+    pgn::write_game(out, &tags, (opening.fullmove, opening.turn), &record.moves, record.result)
+}
+
+//one fen or epd per line, epd operations after the four position fields are ignored
+fn load_openings(path: &Path) -> Result<Vec<Opening>, MatchError> {
+    let reader = BufReader::new(File::open(path).map_err(MatchError::Io)?);
+    let mut openings = Vec::new();
+    for (n, line) in reader.lines().enumerate() {
+        let line = line.map_err(MatchError::Io)?;
+        let fields: Vec<&str> = line.split_whitespace().collect();
+        if fields.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let counters = fields.len() >= 6 && fields[4..6].iter().all(|f| f.parse::<usize>().is_ok());
+        let fen = if counters {fields[..6].join(" ")} else {format!("{} 0 1", fields[..fields.len().min(4)].join(" "))};
+        openings.push(Opening::from_fen(&fen).ok_or_else(|| MatchError::Opening(n + 1, line.clone()))?);
+    }
+    if openings.is_empty() {
+        return Err(MatchError::Opening(0, "no openings".to_owned()));
+    }
+    Ok(openings)
+}
+
+fn random_openings(count: usize, plies: usize) -> Vec<Opening> {
+    //stream 0, the games use the ones after it
+    let mut rng = seed::rng(0);
+    let mut openings = Vec::with_capacity(count.max(1));
+    while openings.len() < count.max(1) {
+        let (mut board, mut turn) = (BoardB::with_starting_pos(), Side::White);
+        let mut ended = false;
+        for _ in 0..plies {
+            let Some((from, to)) = search::legal_moves(&board, turn).choose(&mut rng).copied() else {
+                ended = true;
+                break;
+            };
+            board.make_move(from, to);
+            turn = !turn;
+        }
+        //an opening has to leave both sides something to play
+        if ended || search::legal_moves(&board, turn).is_empty() {
+            continue;
+        }
+        let fullmove = plies / 2 + 1;
+        let fen = board.to_fen(turn, 0, fullmove);
+        openings.push(Opening { board, turn, fen, fullmove });
+    }
+    openings
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+
+    fn stats(wins: usize, draws: usize, losses: usize) -> MatchStats {
+        MatchStats { wins, draws, losses }
+    }
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-3
+    }
+
+    #[test]
+    fn elo_and_margin() {
+        assert_eq!(stats(10, 20, 10).elo().unwrap().0, 0.0);
+        //75% is 400 * log10(3)
+        let (elo, margin) = stats(60, 30, 10).elo().unwrap();
+        assert!(close(elo, 190.849), "{}", elo);
+        assert!(close(margin, 62.046), "{}", margin);
+        assert!(stats(5, 0, 0).elo().is_none());
+        assert!(stats(0, 3, 2).elo().is_some());
+    }
+
+    #[test]
+    fn sprt_bounds() {
+        let (lower, upper) = Sprt::new(0.0, 10.0).bounds();
+        assert!(close(lower, -2.944) && close(upper, 2.944), "{} {}", lower, upper);
+        let sprt = Sprt { alpha: 0.05, beta: 0.1, ..Sprt::new(0.0, 5.0) };
+        let (lower, upper) = sprt.bounds();
+        assert!(close(lower, (0.1_f64 / 0.95).ln()) && close(upper, 18_f64.ln()), "{} {}", lower, upper);
+    }
+
+    //the normal approximation worked out by hand: s = 0.75, variance = 0.1375 / 100
+    #[test]
+    fn llr_known_value() {
+        let sprt = Sprt::new(0.0, 10.0);
+        let llr = stats(60, 30, 10).llr(&sprt);
+        assert!(close(llr, 3.105), "{}", llr);
+        assert_eq!(sprt.verdict(&stats(60, 30, 10)), Some(true));
+        assert!(stats(10, 30, 60).llr(&sprt) < 0.0);
+        assert_eq!(MatchStats::default().llr(&sprt), 0.0);
+    }
+
+    #[test]
+    fn clean_sweeps_end() {
+        let sprt = Sprt::new(0.0, 10.0);
+        assert_eq!(sprt.verdict(&stats(1, 0, 0)), None);
+        assert_eq!(sprt.verdict(&stats(30, 0, 0)), Some(true));
+        assert_eq!(sprt.verdict(&stats(0, 0, 30)), Some(false));
+        //all draws is evidence against the stronger hypothesis
+        assert!(stats(0, 200, 0).llr(&sprt) < 0.0);
+    }
+}
@@ -0,0 +1,277 @@
+use std::collections::HashMap;
+
+use crate::{boardb::BoardB, pieces::Side, san, search};
+
+
+
+//What the side to move has to achieve within the given number of its moves
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum Stipulation {
+    //mate against any defense
+    #[default]
+    Direct,
+    //force the opponent to give mate, the opponent tries not to
+    Selfmate,
+    //the side to move helps, the other side mates, both play together
+    Helpmate,
+}
+
+#[derive(Clone, Copy, Debug, Default)]
+pub struct MateProblem {
+    pub stipulation: Stipulation,
+    pub moves: usize,
+    //the side that has to reach the goal may only give check
+    pub checks_only: bool,
+}
+
+//one move of the solution, children are the answers to it
+pub struct MateNode {
+    pub mv: (u8, u8),
+    pub san: String,
+    pub children: Vec<MateNode>,
+}
+
+pub struct MateSolution {
+    //first moves that solve, more than one is a cook, for helpmates the helper's first moves
+    pub keys: Vec<MateNode>,
+    //positions the prover looked at
+    pub nodes: u64,
+    turn: Side,
+    helpmate: bool,
+}
+
+//Exhaustive proof search over BoardB moves, results are cached by position and moves left. Pawns
+//only promote to queens, like everywhere on the board.
+pub fn solve(board: &BoardB, turn: Side, problem: MateProblem) -> MateSolution {
+    let mut solver = Solver { problem, cache: HashMap::new(), nodes: 0 };
+    let keys = match problem.stipulation {
+        Stipulation::Helpmate => solver.help_tree(board, turn, problem.moves),
+        _ => solver.key_tree(board, turn, problem.moves),
+    };
+    MateSolution { keys, nodes: solver.nodes, turn, helpmate: problem.stipulation == Stipulation::Helpmate }
+}
+
+impl MateSolution {
+    pub fn solved(&self) -> bool {
+        !self.keys.is_empty()
+    }
+
+    //number of complete solutions, helpmates often have several on purpose
+    pub fn solutions(&self) -> usize {
+        fn leaves(node: &MateNode) -> usize {
+            if node.children.is_empty() {1} else {node.children.iter().map(leaves).sum()}
+        }
+        if self.helpmate {self.keys.iter().map(leaves).sum()} else {self.keys.len()}
+    }
+
+    //more than one way to solve, the composer did not intend all of them
+    pub fn cooked(&self) -> bool {
+        self.solutions() > 1
+    }
+
+    //the tree in move notation, one move per line and answers indented below it
+    pub fn lines(&self) -> Vec<String> {
+        fn walk(node: &MateNode, ply: usize, first_black: bool, key: bool, lines: &mut Vec<String>) {
+            let white = (ply % 2 == 0) != first_black;
+            let number = (ply + first_black as usize) / 2 + 1;
+            lines.push(format!(
+                "{}{}{} {}{}",
+                "    ".repeat(ply), number, if white {"."} else {"..."}, node.san, if key {"!"} else {""}
+            ));
+            for child in &node.children {
+                walk(child, ply + 1, first_black, false, lines);
+            }
+        }
+        let mut lines = Vec::new();
+        for key in &self.keys {
+            walk(key, 0, self.turn == Side::Black, !self.helpmate, &mut lines);
+        }
+        lines
+    }
+}
+
+struct Solver {
+    problem: MateProblem,
+    //(position hash, moves left) -> goal reachable
+    cache: HashMap<(u64, usize), bool>,
+    nodes: u64,
+}
+
+fn play(board: &BoardB, mv: (u8, u8)) -> BoardB {
+    let mut board = *board;
+    board.make_move(mv.0, mv.1);
+    board
+}
+
+fn mated(board: &BoardB, turn: Side) -> bool {
+    search::legal_moves(board, turn).is_empty() && search::in_check(board, turn)
+}
+
+impl Solver {
+    //moves of the side that has to reach the goal
+    fn attacker_moves(&self, board: &BoardB, turn: Side) -> Vec<(u8, u8)> {
+        let mut mvs = search::legal_moves(board, turn);
+        if self.problem.checks_only {
+            mvs.retain(|mv| search::in_check(&play(board, *mv), !turn));
+        }
+        mvs
+    }
+
+    //the attacker to move reaches the goal within n moves
+    fn wins(&mut self, board: &BoardB, turn: Side, n: usize) -> bool {
+        if n == 0 {
+            return false;
+        }
+        let key = (board.hash(turn), n);
+        if let Some(result) = self.cache.get(&key) {
+            return *result;
+        }
+        self.nodes += 1;
+        let result = self.attacker_moves(board, turn).into_iter().any(|mv| self.works(board, turn, mv, n));
+        self.cache.insert(key, result);
+        result
+    }
+
+    //the attacker move reaches the goal within n moves, whatever the defender answers
+    fn works(&mut self, board: &BoardB, turn: Side, mv: (u8, u8), n: usize) -> bool {
+        let after = play(board, mv);
+        let replies = search::legal_moves(&after, !turn);
+        match self.problem.stipulation {
+            Stipulation::Direct => {
+                if replies.is_empty() {
+                    return search::in_check(&after, !turn);
+                }
+                n > 1 && replies.into_iter().all(|reply| self.wins(&play(&after, reply), turn, n - 1))
+            },
+            //mating or stalemating the defender ends the game without the selfmate
+            Stipulation::Selfmate => !replies.is_empty() && replies.into_iter().all(|reply| {
+                let next = play(&after, reply);
+                mated(&next, turn) || self.wins(&next, turn, n - 1)
+            }),
+            Stipulation::Helpmate => unreachable!("Helpmates are solved by helps"),
+        }
+    }
+
+    //the helper to move and the mater find a mate in exactly n moves together
+    fn helps(&mut self, board: &BoardB, helper: Side, n: usize) -> bool {
+        let key = (board.hash(helper), n);
+        if let Some(result) = self.cache.get(&key) {
+            return *result;
+        }
+        self.nodes += 1;
+        let result = search::legal_moves(board, helper).into_iter().any(|mv| {
+            let after = play(board, mv);
+            self.attacker_moves(&after, !helper).into_iter().any(|mate| self.helps_with(&after, helper, mate, n))
+        });
+        self.cache.insert(key, result);
+        result
+    }
+
+    fn helps_with(&mut self, after_helper: &BoardB, helper: Side, mv: (u8, u8), n: usize) -> bool {
+        let next = play(after_helper, mv);
+        if n == 1 {mated(&next, helper)} else {self.helps(&next, helper, n - 1)}
+    }
+
+    //every working attacker move with all defenses below it
+    fn key_tree(&mut self, board: &BoardB, turn: Side, n: usize) -> Vec<MateNode> {
+        if !self.wins(board, turn, n) {
+            return Vec::new();
+        }
+        let mut keys = Vec::new();
+        for mv in self.attacker_moves(board, turn) {
+            if !self.works(board, turn, mv, n) {
+                continue;
+            }
+            let after = play(board, mv);
+            let mut children = Vec::new();
+            for reply in search::legal_moves(&after, !turn) {
+                let next = play(&after, reply);
+                //the selfmate is done once the defender had to mate
+                let answers = if mated(&next, turn) {Vec::new()} else {self.key_tree(&next, turn, n - 1)};
+                children.push(MateNode { mv: reply, san: san::to_san(&after, !turn, reply.0, reply.1), children: answers });
+            }
+            keys.push(MateNode { mv, san: san::to_san(board, turn, mv.0, mv.1), children });
+        }
+        keys
+    }
+
+    fn help_tree(&mut self, board: &BoardB, helper: Side, n: usize) -> Vec<MateNode> {
+        if n == 0 || !self.helps(board, helper, n) {
+            return Vec::new();
+        }
+        let mut tree = Vec::new();
+        for mv in search::legal_moves(board, helper) {
+            let after = play(board, mv);
+            let mut children = Vec::new();
+            for mate in self.attacker_moves(&after, !helper) {
+                if !self.helps_with(&after, helper, mate, n) {
+                    continue;
+                }
+                let next = play(&after, mate);
+                children.push(MateNode { mv: mate, san: san::to_san(&after, !helper, mate.0, mate.1), children: self.help_tree(&next, helper, n - 1) });
+            }
+            if !children.is_empty() {
+                tree.push(MateNode { mv, san: san::to_san(board, helper, mv.0, mv.1), children });
+            }
+        }
+        tree
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+
+    fn solve_fen(fen: &str, stipulation: Stipulation, moves: usize) -> MateSolution {
+        let (board, turn) = BoardB::from_fen(fen).unwrap();
+        solve(&board, turn, MateProblem { stipulation, moves, checks_only: false })
+    }
+
+    #[test]
+    fn mate_in_two() {
+        //the king has to take g8 away, waiting with the rook stalemates
+        let fen = "7k/8/5K2/8/8/8/8/6R1 w - - 0 1";
+        assert!(!solve_fen(fen, Stipulation::Direct, 1).solved());
+        let solution = solve_fen(fen, Stipulation::Direct, 2);
+        assert!(solution.solved() && !solution.cooked());
+        assert_eq!(solution.lines(), ["1. Kf7!", "    1... Kh7", "        2. Rh1#"]);
+    }
+
+    //the rook ending tables agree on five plies
+    #[test]
+    fn mate_in_three() {
+        let fen = "7k/8/8/5K2/8/8/8/6R1 w - - 0 1";
+        assert!(!solve_fen(fen, Stipulation::Direct, 2).solved());
+        assert!(solve_fen(fen, Stipulation::Direct, 3).solved());
+    }
+
+    #[test]
+    fn cooked() {
+        //either rook mates on the back rank
+        let solution = solve_fen("7k/8/6K1/8/8/8/8/RR6 w - - 0 1", Stipulation::Direct, 1);
+        assert!(solution.cooked());
+        assert_eq!(solution.keys.iter().map(|key| key.san.as_str()).collect::<Vec<_>>(), ["Ra8#", "Rb8#"]);
+    }
+
+    #[test]
+    fn selfmate() {
+        //only Ra4 takes the king's last squares, then g2 is black's only move
+        let solution = solve_fen("8/8/8/R7/8/6pk/8/6BK w - - 0 1", Stipulation::Selfmate, 1);
+        assert_eq!(solution.solutions(), 1);
+        assert_eq!(solution.lines(), ["1. Ra4!", "    1... g2#"]);
+    }
+
+    #[test]
+    fn helpmate() {
+        //a g pawn move keeps h7 closed for Ra8, an h pawn move opens it
+        let solution = solve_fen("7k/5Kpp/8/8/8/8/8/R7 b - - 0 1", Stipulation::Helpmate, 1);
+        assert_eq!(solution.solutions(), 2);
+        let mut keys: Vec<&str> = solution.keys.iter().map(|key| key.san.as_str()).collect();
+        keys.sort();
+        assert_eq!(keys, ["g5", "g6"]);
+    }
+}
@@ -0,0 +1,242 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use rand::{rngs::StdRng, seq::SliceRandom, Rng, SeedableRng};
+
+use crate::{boardb::{BoardB, Pos, PosTrait}, pieces::Side, search::{self, CpuMove, SearchHandle}};
+
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Playout {
+    //random moves until the game ends or the ply limit is reached, then the evaluation decides
+    Random,
+    //no moves at all, the evaluation of the new leaf is its value and also sets the move priors
+    Eval,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct MctsConfig {
+    pub iterations: usize,
+    //weight of the prior against the average result, higher explores more
+    pub exploration: f64,
+    pub playout: Playout,
+    //random playouts are cut off after this many plies
+    pub playout_plies: usize,
+}
+
+impl Default for MctsConfig {
+    fn default() -> Self {
+        Self { iterations: 10_000, exploration: 1.4, playout: Playout::Eval, playout_plies: 20 }
+    }
+}
+
+pub struct MctsResult {
+    pub mv: Option<(u8, u8)>,
+    //expected result for the side to move, 1.0 is a win
+    pub value: f64,
+    pub iterations: usize,
+    //most visited line
+    pub pv: Vec<(u8, u8)>,
+}
+
+impl MctsResult {
+    //the expected result as a centipawn like score, the inverse of the value squashing
+    pub fn score(&self) -> i32 {
+        let value = self.value.clamp(0.001, 0.999);
+        (-400.0 * (1.0 / value - 1.0).log10()) as i32
+    }
+}
+
+struct Node {
+    mv: (u8, u8),
+    prior: f32,
+    visits: u32,
+    //sum of the results for the side that played mv
+    total: f64,
+    //children are stored next to each other, None until the node is expanded
+    children: Option<(usize, usize)>,
+}
+
+//Monte Carlo tree search with PUCT selection: children are picked by average result plus an
+//exploration bonus that grows with the prior and shrinks with the visits. The move played is
+//the most visited one.
+pub fn search<R: Rng>(board: BoardB, turn: Side, config: MctsConfig, stop: &AtomicBool, rng: &mut R) -> MctsResult {
+    let mut tree = vec![Node { mv: (0, 0), prior: 1.0, visits: 0, total: 0.0, children: None }];
+    let mut iterations = 0;
+    while iterations < config.iterations.max(1) && !stop.load(Ordering::Relaxed) {
+        iterations += 1;
+        let (mut board, mut turn) = (board, turn);
+        let mut path = vec![0];
+        let mut node = 0;
+        //select down to a leaf
+        while let Some((first, count)) = tree[node].children && count > 0 {
+            let parent_visits = tree[node].visits as f64;
+            let best = (first..first + count).max_by(|a, b| {
+                puct(&tree[*a], parent_visits, config.exploration).total_cmp(&puct(&tree[*b], parent_visits, config.exploration))
+            }).expect("Expanded node without children");
+            board.make_move(tree[best].mv.0, tree[best].mv.1);
+            turn = !turn;
+            path.push(best);
+            node = best;
+        }
+        //expand, value is the result for the side to move at the leaf
+        let value = match tree[node].children {
+            Some(_) => terminal_value(&board, turn),
+            None => {
+                //a dead draw gets no children, like a mate or stalemate
+                let mvs = if board.insufficient_material() {Vec::new()} else {search::legal_moves(&board, turn)};
+                let priors = priors(&board, turn, &mvs, config.playout);
+                tree[node].children = Some((tree.len(), mvs.len()));
+                tree.extend(mvs.iter().zip(priors).map(|(mv, prior)| Node { mv: *mv, prior, visits: 0, total: 0.0, children: None }));
+                if mvs.is_empty() {
+                    terminal_value(&board, turn)
+                } else {
+                    match config.playout {
+                        Playout::Random => random_playout(board, turn, config.playout_plies, rng),
+                        Playout::Eval => squash(board.evaluate(turn)),
+                    }
+                }
+            },
+        };
+        //every node stores the result of the side that moved into it
+        let mut value = value;
+        for node in path.into_iter().rev() {
+            tree[node].visits += 1;
+            tree[node].total += 1.0 - value;
+            value = 1.0 - value;
+        }
+    }
+
+    let mut pv = Vec::new();
+    let mut value = 0.5;
+    let mut node = 0;
+    while let Some((first, count)) = tree[node].children && count > 0 {
+        let best = (first..first + count).max_by(|a, b| {
+            tree[*a].visits.cmp(&tree[*b].visits).then(tree[*a].prior.total_cmp(&tree[*b].prior))
+        }).expect("Expanded node without children");
+        if tree[best].visits == 0 {
+            //a budget that ends right after expanding the root still plays the move with the best prior
+            if pv.is_empty() {
+                pv.push(tree[best].mv);
+            }
+            break;
+        }
+        if pv.is_empty() {
+            value = tree[best].total / tree[best].visits as f64;
+        }
+        pv.push(tree[best].mv);
+        node = best;
+    }
+    MctsResult { mv: pv.first().copied(), value, iterations, pv }
+}
+
+fn puct(node: &Node, parent_visits: f64, exploration: f64) -> f64 {
+    //unvisited moves count as even until they are tried
+    let average = if node.visits == 0 {0.5} else {node.total / node.visits as f64};
+    average + exploration * node.prior as f64 * parent_visits.sqrt() / (1.0 + node.visits as f64)
+}
+
+//centipawns to an expected result, 400 more is about 90%
+fn squash(score: i32) -> f64 {
+    1.0 / (1.0 + 10_f64.powf(-score as f64 / 400.0))
+}
+
+fn terminal_value(board: &BoardB, turn: Side) -> f64 {
+    if search::legal_moves(board, turn).is_empty() && search::in_check(board, turn) {0.0} else {0.5}
+}
+
+//uniform for random playouts, a softmax over the evaluation after each move otherwise
+fn priors(board: &BoardB, turn: Side, mvs: &[(u8, u8)], playout: Playout) -> Vec<f32> {
+    if playout == Playout::Random || mvs.is_empty() {
+        return vec![1.0 / mvs.len().max(1) as f32; mvs.len()];
+    }
+    let scores: Vec<f64> = mvs.iter().map(|(from, to)| {
+        let mut after = *board;
+        after.make_move(*from, *to);
+        -after.evaluate(!turn) as f64 / 100.0
+    }).collect();
+    let max = scores.iter().cloned().fold(f64::MIN, f64::max);
+    let weights: Vec<f64> = scores.iter().map(|s| (s - max).exp()).collect();
+    let sum: f64 = weights.iter().sum();
+    weights.iter().map(|w| (w / sum) as f32).collect()
+}
+
+fn random_playout<R: Rng>(mut board: BoardB, turn: Side, plies: usize, rng: &mut R) -> f64 {
+    let mut side = turn;
+    for _ in 0..plies {
+        let Some((from, to)) = search::legal_moves(&board, side).choose(rng).copied() else {
+            break;
+        };
+        board.make_move(from, to);
+        side = !side;
+        if board.insufficient_material() {
+            return 0.5;
+        }
+    }
+    let value = if search::legal_moves(&board, side).is_empty() {terminal_value(&board, side)} else {squash(board.evaluate(side))};
+    if side == turn {value} else {1.0 - value}
+}
+
+//the gui's cpu move, like search::spawn_move_computer
+pub fn spawn_move_computer(board: BoardB, turn: Side, config: MctsConfig, seed: u64, generation: u64) -> SearchHandle {
+    SearchHandle::spawn(generation, move |stop| {
+        let result = search(board, turn, config, stop, &mut StdRng::seed_from_u64(seed));
+        let mv = result.mv?;
+        println!(
+            "mcts: {} -> {} value: {:.3} iterations: {}",
+            Pos::from_i(mv.0), Pos::from_i(mv.1), result.value, result.iterations
+        );
+        Some(CpuMove { mv, ponder: result.pv.get(1).copied(), eval: Some((result.score(), result.pv.len())) })
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+
+    fn run(fen: &str, iterations: usize, playout: Playout, seed: u64) -> (BoardB, Side, MctsResult) {
+        let (board, turn) = BoardB::from_fen(fen).unwrap();
+        let config = MctsConfig { iterations, playout, ..MctsConfig::default() };
+        let result = search(board, turn, config, &AtomicBool::new(false), &mut StdRng::seed_from_u64(seed));
+        (board, turn, result)
+    }
+
+    #[test]
+    fn finds_mate_in_one() {
+        for playout in [Playout::Eval, Playout::Random] {
+            let (mut board, turn, result) = run("6k1/5ppp/8/8/8/8/5PPP/R5K1 w - - 0 1", 2000, playout, 1);
+            let (from, to) = result.mv.unwrap();
+            board.make_move(from, to);
+            assert!(search::in_check(&board, !turn) && search::legal_moves(&board, !turn).is_empty(), "{:?}", playout);
+            assert!(result.value > 0.99);
+        }
+    }
+
+    //the move and every move of the line are legal, whatever the budget
+    #[test]
+    fn only_legal_moves() {
+        let fens = [
+            "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            "r3k2r/p1ppqpb1/bn2pnp1/3PN3/1p2P3/2N2Q1p/PPPBBPPP/R3K2R w KQkq - 0 1",
+            "4k3/8/8/3pP3/8/8/8/4K3 w - d6 0 1",
+            "4k3/1P6/8/8/8/8/6p1/4K2R b K - 0 1",
+        ];
+        for fen in fens {
+            for (iterations, playout) in [(1, Playout::Eval), (50, Playout::Random), (300, Playout::Eval)] {
+                let (mut board, mut turn, result) = run(fen, iterations, playout, 2);
+                assert_eq!(result.iterations, iterations);
+                assert_eq!(result.mv, result.pv.first().copied());
+                assert!(result.mv.is_some(), "{}", fen);
+                for (from, to) in result.pv {
+                    assert!(search::legal_moves(&board, turn).contains(&(from, to)), "{} after {:?}", fen, (from, to));
+                    board.make_move(from, to);
+                    turn = !turn;
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,286 @@
+use std::{fs::File, io::{BufReader, BufWriter, Read, Write}, path::Path, sync::OnceLock};
+
+use crate::{boardb::{Piece, Pos, PosTrait}, pieces::{Side, PieceType}};
+
+
+
+//Efficiently updatable network: 768 inputs (6 piece types * 2 colors * 64 squares, seen from one
+//side) -> HIDDEN neurons per side -> 1 output.
+//Both sides keep their own accumulator of the first layer. Moving a piece only adds and subtracts
+//two weight columns, so the first layer never has to be recomputed during search.
+//
+//File format, all little endian:
+//  magic     b"NNUE"
+//  version   u32 = 1
+//  hidden    u32 = HIDDEN
+//  feature weights  i16 [768 * hidden], input-major (all hidden weights of input 0 first)
+//  feature biases   i16 [hidden]
+//  output weights   i16 [2 * hidden], side to move half first
+//  output bias      i32
+//Inputs are indexed (color * 6 + piece) * 64 + square, color 0 for pieces of the perspective side,
+//piece in the order pawn, knight, bishop, rook, queen, king and square a1 = 0 .. h8 = 63.
+//For black's perspective the board is flipped vertically, so both sides see themselves at the bottom.
+pub const HIDDEN: usize = 256;
+pub const INPUTS: usize = 768;
+const MAGIC: [u8; 4] = *b"NNUE";
+const VERSION: u32 = 1;
+
+//quantization: hidden activations are clipped to 0..QA, output weights are scaled by QB
+const QA: i32 = 255;
+const QB: i32 = 64;
+//network output to centipawns
+const SCALE: i32 = 400;
+
+#[derive(Debug)]
+pub enum NnueError {
+    Io(std::io::Error),
+    Magic,
+    Version(u32),
+    //file was written for a different hidden layer size
+    Hidden(u32),
+}
+
+pub struct Network {
+    feature_weights: Vec<i16>,
+    feature_biases: [i16; HIDDEN],
+    output_weights: [i16; 2 * HIDDEN],
+    output_bias: i32,
+}
+
+static NETWORK: OnceLock<Network> = OnceLock::new();
+
+//has to happen before the first board is built, boards keep their accumulator up to date with the
+//network that was loaded when they were created
+pub fn init(network: Network) {
+    if NETWORK.set(network).is_err() {
+        panic!("Network initialized twice");
+    }
+}
+
+pub fn get() -> Option<&'static Network> {
+    #[cfg(test)]
+    if let Some(network) = TEST_NETWORK.get() {
+        return Some(network);
+    }
+    NETWORK.get()
+}
+
+//tests load their network for their own thread only, the others keep the material evaluation
+#[cfg(test)]
+thread_local! {
+    static TEST_NETWORK: std::cell::Cell<Option<&'static Network>> = const { std::cell::Cell::new(None) };
+}
+
+impl Network {
+    pub fn load(path: &Path) -> Result<Self, NnueError> {
+        let mut reader = BufReader::new(File::open(path).map_err(NnueError::Io)?);
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic).map_err(NnueError::Io)?;
+        if magic != MAGIC {
+            return Err(NnueError::Magic);
+        }
+        let version = read_u32(&mut reader)?;
+        if version != VERSION {
+            return Err(NnueError::Version(version));
+        }
+        let hidden = read_u32(&mut reader)?;
+        if hidden as usize != HIDDEN {
+            return Err(NnueError::Hidden(hidden));
+        }
+        let mut network = Self {
+            feature_weights: vec![0; INPUTS * HIDDEN],
+            feature_biases: [0; HIDDEN],
+            output_weights: [0; 2 * HIDDEN],
+            output_bias: 0,
+        };
+        read_i16s(&mut reader, &mut network.feature_weights)?;
+        read_i16s(&mut reader, &mut network.feature_biases)?;
+        read_i16s(&mut reader, &mut network.output_weights)?;
+        network.output_bias = read_u32(&mut reader)? as i32;
+        Ok(network)
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), NnueError> {
+        let mut writer = BufWriter::new(File::create(path).map_err(NnueError::Io)?);
+        let mut bytes = Vec::with_capacity(12 + 2 * (INPUTS * HIDDEN + 3 * HIDDEN) + 4);
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(HIDDEN as u32).to_le_bytes());
+        for w in self.feature_weights.iter().chain(&self.feature_biases).chain(&self.output_weights) {
+            bytes.extend_from_slice(&w.to_le_bytes());
+        }
+        bytes.extend_from_slice(&self.output_bias.to_le_bytes());
+        writer.write_all(&bytes).and_then(|_| writer.flush()).map_err(NnueError::Io)
+    }
+
+    fn column(&self, feature: usize) -> &[i16] {
+        &self.feature_weights[feature * HIDDEN..(feature + 1) * HIDDEN]
+    }
+
+    //centipawns from the view of side
+    pub fn evaluate(&self, accumulator: &Accumulator, side: Side) -> i32 {
+        let (us, them) = match side {
+            Side::White => (&accumulator.white, &accumulator.black),
+            Side::Black => (&accumulator.black, &accumulator.white),
+        };
+        let (our_weights, their_weights) = self.output_weights.split_at(HIDDEN);
+        let output = self.output_bias + weighted_sum(us, our_weights) + weighted_sum(them, their_weights);
+        output * SCALE / (QA * QB)
+    }
+}
+
+//clipped relu, written as plain loops over fixed size slices so the compiler vectorizes them
+fn weighted_sum(values: &[i16; HIDDEN], weights: &[i16]) -> i32 {
+    values.iter()
+        .zip(weights)
+        .map(|(v, w)| (*v as i32).clamp(0, QA) * *w as i32)
+        .sum()
+}
+
+fn read_u32<R: Read>(reader: &mut R) -> Result<u32, NnueError> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes).map_err(NnueError::Io)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_i16s<R: Read>(reader: &mut R, out: &mut [i16]) -> Result<(), NnueError> {
+    let mut bytes = vec![0; out.len() * 2];
+    reader.read_exact(&mut bytes).map_err(NnueError::Io)?;
+    for (v, b) in out.iter_mut().zip(bytes.chunks_exact(2)) {
+        *v = i16::from_le_bytes([b[0], b[1]]);
+    }
+    Ok(())
+}
+
+fn feature(piece: Piece, i: u8, perspective: Side) -> usize {
+    let pos = Pos::from_i(i);
+    //board index 0 is a8, features count from a1
+    let rank = match perspective {
+        Side::White => 7 - pos.y,
+        Side::Black => pos.y,
+    };
+    let color = if piece.side == perspective {0} else {1};
+    let ty = match piece.ty {
+        PieceType::Pawn => 0,
+        PieceType::Knight => 1,
+        PieceType::Bishop => 2,
+        PieceType::Rook => 3,
+        PieceType::Queen => 4,
+        PieceType::King => 5,
+    };
+    (color * 6 + ty) * 64 + (rank * 8 + pos.x) as usize
+}
+
+//First layer output for both perspectives, stays zero while no network is loaded. Sums wrap instead
+//of overflowing, so adding and removing a piece always cancel out and an updated accumulator equals
+//one built from scratch.
+#[derive(Clone, Copy, PartialEq)]
+pub struct Accumulator {
+    white: [i16; HIDDEN],
+    black: [i16; HIDDEN],
+}
+
+impl Accumulator {
+    pub fn new(board: &[Option<Piece>; 64]) -> Self {
+        let mut accumulator = Self { white: [0; HIDDEN], black: [0; HIDDEN] };
+        if let Some(network) = get() {
+            accumulator.white = network.feature_biases;
+            accumulator.black = network.feature_biases;
+            for (i, piece) in board.iter().enumerate() {
+                if let Some(piece) = piece {
+                    accumulator.add(*piece, i as u8);
+                }
+            }
+        }
+        accumulator
+    }
+
+    pub fn add(&mut self, piece: Piece, i: u8) {
+        if let Some(network) = get() {
+            for (acc, w) in self.white.iter_mut().zip(network.column(feature(piece, i, Side::White))) {
+                *acc = acc.wrapping_add(*w);
+            }
+            for (acc, w) in self.black.iter_mut().zip(network.column(feature(piece, i, Side::Black))) {
+                *acc = acc.wrapping_add(*w);
+            }
+        }
+    }
+
+    pub fn remove(&mut self, piece: Piece, i: u8) {
+        if let Some(network) = get() {
+            for (acc, w) in self.white.iter_mut().zip(network.column(feature(piece, i, Side::White))) {
+                *acc = acc.wrapping_sub(*w);
+            }
+            for (acc, w) in self.black.iter_mut().zip(network.column(feature(piece, i, Side::Black))) {
+                *acc = acc.wrapping_sub(*w);
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::{boardb::BoardB, san::square_from_str};
+    use super::*;
+
+
+
+    fn load_test_network() {
+        let mut rng = StdRng::seed_from_u64(31);
+        let mut network = Network {
+            feature_weights: (0..INPUTS * HIDDEN).map(|_| rng.gen_range(-32..32)).collect(),
+            feature_biases: [0; HIDDEN],
+            output_weights: [0; 2 * HIDDEN],
+            output_bias: rng.gen_range(-1000..1000),
+        };
+        network.feature_biases.iter_mut().for_each(|b| *b = rng.gen_range(-32..32));
+        network.output_weights.iter_mut().for_each(|w| *w = rng.gen_range(-64..64));
+        TEST_NETWORK.set(Some(Box::leak(Box::new(network))));
+    }
+
+    //plays the moves on the board from the fen and compares its accumulator with a fresh one after each
+    fn assert_incremental(fen: &str, moves: &[(&str, &str, PieceType)]) {
+        load_test_network();
+        let network = get().unwrap();
+        let (mut board, _) = BoardB::from_fen(fen).unwrap();
+        for &(from, to, promotion) in moves {
+            board.make_move_promoting(square_from_str(from).unwrap(), square_from_str(to).unwrap(), promotion);
+            let fresh = Accumulator::new(&board.board);
+            for side in [Side::White, Side::Black] {
+                assert_eq!(board.evaluate(side), network.evaluate(&fresh, side), "{}{} in {}", from, to, fen);
+            }
+        }
+    }
+
+    #[test]
+    fn quiet_moves() {
+        assert_incremental("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[("g1", "f3", PieceType::Queen), ("g8", "f6", PieceType::Queen), ("e2", "e4", PieceType::Queen)]);
+    }
+
+    #[test]
+    fn captures() {
+        assert_incremental("rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1",
+            &[("e2", "e4", PieceType::Queen), ("d7", "d5", PieceType::Queen), ("e4", "d5", PieceType::Queen), ("d8", "d5", PieceType::Queen)]);
+    }
+
+    #[test]
+    fn castling() {
+        assert_incremental("r3k2r/pppppppp/8/8/8/8/PPPPPPPP/R3K2R w KQkq - 0 1",
+            &[("e1", "g1", PieceType::Queen), ("e8", "c8", PieceType::Queen)]);
+    }
+
+    #[test]
+    fn en_passant() {
+        assert_incremental("4k3/2p5/8/3P4/5p2/8/4P3/4K3 w - - 0 1",
+            &[("e2", "e4", PieceType::Queen), ("f4", "e3", PieceType::Queen), ("e1", "e2", PieceType::Queen), ("c7", "c5", PieceType::Queen), ("d5", "c6", PieceType::Queen)]);
+    }
+
+    #[test]
+    fn promotions() {
+        assert_incremental("r3k3/1P6/8/8/8/8/6p1/4K2R w - - 0 1",
+            &[("b7", "b8", PieceType::Queen), ("g2", "h1", PieceType::Knight), ("b8", "a8", PieceType::Queen)]);
+    }
+}
@@ -0,0 +1,205 @@
+use std::{fs::File, io::{BufWriter, Write}, path::{Path, PathBuf}, time::{Duration, SystemTime, UNIX_EPOCH}};
+
+use crate::{gameb::{Annotation, GameB}, pieces::Side, san::{self, SanError}, search};
+
+
+
+pub const START_FEN: &str = "rnbqkbnr/pppppppp/8/8/8/8/PPPPPPPP/RNBQKBNR w KQkq - 0 1";
+
+#[derive(Debug)]
+pub enum PgnError {
+    Io(std::io::Error),
+    //(line, column, reason)
+    Syntax(usize, usize, String),
+    //(line, column, san, reason)
+    IllegalMove(usize, usize, String, SanError),
+}
+
+pub fn parse_tag(line: &str) -> Option<(String, String)> {
+    let inner = line.strip_prefix('[')?.trim_end().strip_suffix(']')?;
+    let (name, value) = inner.split_once(' ')?;
+    let value = value.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name.to_owned(), value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+//Export format: tags in the given order, numbered movetext wrapped at 80 columns and the result.
+//first is the move number and side of the first move, every move can carry a comment.
+pub fn write_game<W: Write>(out: &mut W, tags: &[(String, String)], first: (usize, Side), moves: &[(String, Option<String>)], result: &str) -> std::io::Result<()> {
+    for (name, value) in tags {
+        writeln!(out, "[{} \"{}\"]", name, value.replace('\\', "\\\\").replace('"', "\\\""))?;
+    }
+    writeln!(out)?;
+
+    let (mut number, mut turn) = first;
+    let mut tokens = Vec::with_capacity(moves.len() * 2 + 1);
+    for (n, (san, comment)) in moves.iter().enumerate() {
+        match turn {
+            Side::White => tokens.push(format!("{}.", number)),
+            //black's move needs its number after the start or a comment
+            Side::Black if n == 0 || moves[n - 1].1.is_some() => tokens.push(format!("{}...", number)),
+            Side::Black => {},
+        }
+        tokens.push(san.clone());
+        if let Some(comment) = comment {
+            tokens.push(format!("{{{}}}", comment));
+        }
+        if turn == Side::Black {
+            number += 1;
+        }
+        turn = !turn;
+    }
+    tokens.push(result.to_owned());
+
+    let mut line = String::new();
+    for token in tokens {
+        if !line.is_empty() && line.len() + 1 + token.len() > 80 {
+            writeln!(out, "{}", line)?;
+            line.clear();
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line += &token;
+    }
+    writeln!(out, "{}", line)?;
+    writeln!(out)
+}
+
+//comments written after every move of an exported game
+#[derive(Clone, Copy, Default, Debug)]
+pub struct ExportOptions {
+    //[%emt h:mm:ss] with the time the move took
+    pub clock: bool,
+    //[%eval] from white's view for moves a search played
+    pub eval: bool,
+}
+
+//The game as played in the gui, finished or not. The start position goes into FEN and SetUp
+//tags if it isn't the normal one.
+pub fn export<W: Write>(out: &mut W, game: &GameB, options: ExportOptions) -> std::io::Result<()> {
+    let result = game.result();
+    let mut tags = vec![
+        ("Event".to_owned(), "chess-cp game".to_owned()),
+        ("Site".to_owned(), "?".to_owned()),
+        ("Date".to_owned(), today()),
+        ("Round".to_owned(), "-".to_owned()),
+        ("White".to_owned(), game.white.name()),
+        ("Black".to_owned(), game.black.name()),
+        ("Result".to_owned(), result.to_owned()),
+    ];
+    let start_fen = game.start_fen();
+    if start_fen != START_FEN {
+        tags.push(("SetUp".to_owned(), "1".to_owned()));
+        tags.push(("FEN".to_owned(), start_fen.to_owned()));
+    }
+    let fullmove = start_fen.split_whitespace().nth(5).and_then(|n| n.parse().ok()).unwrap_or(1);
+
+    let (mut board, mut turn) = game.start_position();
+    let first = turn;
+    let mut moves = Vec::with_capacity(game.moves.len());
+    for (n, (from, to)) in game.moves.iter().enumerate() {
+        let san = san::to_san(&board, turn, *from, *to);
+        let comment = comment(game.annotations.get(n), game.book_moves.contains(&n), turn, options);
+        moves.push((san, comment));
+        board.make_move(*from, *to);
+        turn = !turn;
+    }
+    write_game(out, &tags, (fullmove, first), &moves, result)
+}
+
+//book moves are marked as such, whatever the options
+fn comment(annotation: Option<&Annotation>, book: bool, mover: Side, options: ExportOptions) -> Option<String> {
+    let mut commands = Vec::new();
+    if book {
+        commands.push("book".to_owned());
+    }
+    if options.eval && let Some((score, depth)) = annotation.and_then(|annotation| annotation.eval) {
+        let white_score = if mover == Side::White {score} else {-score};
+        match search::mate_in(white_score) {
+            Some(mate) => commands.push(format!("[%eval #{},{}]", mate, depth)),
+            None => commands.push(format!("[%eval {:.2},{}]", white_score as f64 / 100.0, depth)),
+        }
+    }
+    if options.clock && let Some(annotation) = annotation {
+        commands.push(format!("[%emt {}]", clock_time(annotation.think)));
+    }
+    (!commands.is_empty()).then(|| commands.join(" "))
+}
+
+//h:mm:ss
+fn clock_time(time: Duration) -> String {
+    let secs = time.as_secs();
+    format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+}
+
+//writes the game to a new file in dir, named after the date and time
+pub fn save(dir: &Path, game: &GameB, options: ExportOptions) -> std::io::Result<PathBuf> {
+    std::fs::create_dir_all(dir)?;
+    let secs = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let stamp = format!("{}_{:02}{:02}{:02}", today().replace('.', "-"), secs / 3600 % 24, secs / 60 % 60, secs % 60);
+    let mut path = dir.join(format!("{}.pgn", stamp));
+    let mut n = 1;
+    while path.exists() {
+        n += 1;
+        path = dir.join(format!("{}_{}.pgn", stamp, n));
+    }
+    let mut out = BufWriter::new(File::create(&path)?);
+    export(&mut out, game, options)?;
+    out.flush()?;
+    Ok(path)
+}
+
+//"YYYY.MM.DD" in UTC, for the Date tag
+pub fn today() -> String {
+    let days = SystemTime::now().duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs() / 86400) as i64;
+    //civil from days, proleptic gregorian calendar
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 {mp + 3} else {mp - 9};
+    let year = yoe + era * 400 + if month <= 2 {1} else {0};
+    format!("{:04}.{:02}.{:02}", year, month, day)
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use crate::{gameb::PlayerType, san::square_from_str};
+    use super::*;
+
+
+
+    #[test]
+    fn export_marks_book_moves() {
+        let mut game = GameB::new(PlayerType::Me, PlayerType::Me, false);
+        let sq = |s| square_from_str(s).unwrap();
+        assert!(game.make_book_move(sq("e2"), sq("e4")));
+        assert!(game.make_book_move(sq("e7"), sq("e5")));
+        assert!(game.make_move(sq("g1"), sq("f3")));
+        let mut out = Vec::new();
+        export(&mut out, &game, ExportOptions::default()).unwrap();
+        let pgn = String::from_utf8(out).unwrap();
+        assert!(pgn.contains("1. e4 {book} 1... e5 {book} 2. Nf3 *"), "{}", pgn);
+    }
+
+    #[test]
+    fn export_from_black_to_move() {
+        let fen = "rnbqkbnr/pppp1ppp/8/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R b KQkq - 7 20";
+        let mut game = GameB::from_fen(PlayerType::Me, PlayerType::Me, fen, false).unwrap();
+        let sq = |s| square_from_str(s).unwrap();
+        assert!(game.make_move(sq("b8"), sq("c6")));
+        assert!(game.make_move(sq("f1"), sq("c4")));
+        assert!(game.make_move(sq("g8"), sq("f6")));
+        let mut out = Vec::new();
+        export(&mut out, &game, ExportOptions::default()).unwrap();
+        let pgn = String::from_utf8(out).unwrap();
+        assert!(pgn.contains(&format!("[FEN \"{}\"]", fen)), "{}", pgn);
+        assert!(pgn.contains("20... Nc6 21. Bc4 Nf6 *"), "{}", pgn);
+        assert_eq!(game.to_fen(), "r1bqkb1r/pppp1ppp/2n2n2/4p3/2B1P3/5N2/PPPP1PPP/RNBQK2R w KQkq - 10 22");
+    }
+}
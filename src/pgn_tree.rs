@@ -0,0 +1,480 @@
+use std::{collections::VecDeque, io::BufRead};
+
+use crate::{boardb::BoardB, pgn::{self, PgnError, START_FEN}, pieces::{PieceType, Side}, san::{self, SanMove}};
+
+
+
+//One move with everything written around it. Variations are alternatives to this move, they
+//start from the position before it.
+pub struct MoveNode {
+    pub san: String,
+    pub mv: SanMove,
+    //numeric annotation glyphs, suffixes like ! or ?! are turned into theirs
+    pub nags: Vec<u8>,
+    pub comment_before: Option<String>,
+    pub comment: Option<String>,
+    pub variations: Vec<Vec<MoveNode>>,
+}
+
+//A game with all its comments and variations, every move checked against the board
+pub struct PgnTree {
+    pub tags: Vec<(String, String)>,
+    pub start: (BoardB, Side),
+    pub moves: Vec<MoveNode>,
+    //comment of a game without moves
+    pub comment: Option<String>,
+    pub result: String,
+}
+
+impl PgnTree {
+    pub fn tag(&self, name: &str) -> Option<&str> {
+        self.tags.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    pub fn main_line(&self) -> Vec<(u8, u8)> {
+        self.moves.iter().map(|node| (node.mv.from, node.mv.to)).collect()
+    }
+
+    //every position of the main line together with the move played from it
+    pub fn replay(&self) -> Vec<(BoardB, Side, SanMove)> {
+        let (mut board, mut turn) = self.start;
+        self.moves.iter().map(|node| {
+            let position = (board, turn, node.mv);
+            board.make_move_promoting(node.mv.from, node.mv.to, node.mv.promotion.unwrap_or(PieceType::Queen));
+            turn = !turn;
+            position
+        }).collect()
+    }
+
+    //moves of the main line and all variations
+    pub fn move_count(&self) -> usize {
+        fn count(line: &[MoveNode]) -> usize {
+            line.iter().map(|node| 1 + node.variations.iter().map(|v| count(v)).sum::<usize>()).sum()
+        }
+        count(&self.moves)
+    }
+}
+
+enum Token {
+    Tag(String, String),
+    Comment(String),
+    Open,
+    Close,
+    Nag(u8),
+    Move(String),
+    Result(String),
+}
+
+//(line, column, token), both counted from 1
+type Located = (usize, usize, Token);
+
+//Splits the file into tokens one line at a time, only the tokens of the current line are kept
+struct Lexer<R: BufRead> {
+    reader: R,
+    buf: String,
+    line: usize,
+    tokens: VecDeque<Located>,
+    //(line, column, text) of a comment that goes on in the next line
+    comment: Option<(usize, usize, String)>,
+}
+
+impl<R: BufRead> Lexer<R> {
+    fn read_line(&mut self) -> Result<bool, PgnError> {
+        self.buf.clear();
+        let read = self.reader.read_line(&mut self.buf).map_err(PgnError::Io)?;
+        self.line += 1;
+        Ok(read > 0)
+    }
+
+    fn next(&mut self) -> Result<Option<Located>, PgnError> {
+        while self.tokens.is_empty() {
+            if !self.read_line()? {
+                return match self.comment.take() {
+                    Some((line, column, _)) => Err(PgnError::Syntax(line, column, "comment without end".to_owned())),
+                    None => Ok(None),
+                };
+            }
+            self.lex_line()?;
+        }
+        Ok(self.tokens.pop_front())
+    }
+
+    fn lex_line(&mut self) -> Result<(), PgnError> {
+        let line = self.line;
+        let chars: Vec<char> = self.buf.trim_end_matches(['\n', '\r']).chars().collect();
+        if self.comment.is_none() {
+            //escape lines are for other programs
+            if chars.first() == Some(&'%') {
+                return Ok(());
+            }
+            let text: String = chars.iter().collect();
+            if text.trim_start().starts_with('[') {
+                let column = chars.iter().take_while(|c| c.is_whitespace()).count() + 1;
+                let (name, value) = pgn::parse_tag(text.trim()).ok_or_else(|| PgnError::Syntax(line, column, "invalid tag".to_owned()))?;
+                self.tokens.push_back((line, column, Token::Tag(name, value)));
+                return Ok(());
+            }
+        }
+        let mut i = 0;
+        while i < chars.len() {
+            if let Some((start_line, start_column, text)) = &mut self.comment {
+                match chars[i..].iter().position(|c| *c == '}') {
+                    Some(end) => {
+                        text.extend(&chars[i..i + end]);
+                        let comment = Token::Comment(text.trim().to_owned());
+                        self.tokens.push_back((*start_line, *start_column, comment));
+                        self.comment = None;
+                        i += end + 1;
+                        continue;
+                    },
+                    None => {
+                        text.extend(&chars[i..]);
+                        text.push(' ');
+                        return Ok(());
+                    },
+                }
+            }
+            let column = i + 1;
+            match chars[i] {
+                c if c.is_whitespace() => {},
+                '{' => self.comment = Some((line, column, String::new())),
+                ';' => {
+                    let comment: String = chars[i + 1..].iter().collect();
+                    self.tokens.push_back((line, column, Token::Comment(comment.trim().to_owned())));
+                    return Ok(());
+                },
+                '(' => self.tokens.push_back((line, column, Token::Open)),
+                ')' => self.tokens.push_back((line, column, Token::Close)),
+                '*' => self.tokens.push_back((line, column, Token::Result("*".to_owned()))),
+                '$' => {
+                    let digits: String = chars[i + 1..].iter().take_while(|c| c.is_ascii_digit()).collect();
+                    let nag = digits.parse().map_err(|_| PgnError::Syntax(line, column, "invalid NAG".to_owned()))?;
+                    self.tokens.push_back((line, column, Token::Nag(nag)));
+                    i += digits.len();
+                },
+                c if c.is_alphanumeric() => {
+                    let symbol: String = chars[i..].iter().take_while(|c| !c.is_whitespace() && !"{}();$[]*".contains(**c)).collect();
+                    self.symbol(line, column, &symbol)?;
+                    i += symbol.chars().count() - 1;
+                },
+                c => return Err(PgnError::Syntax(line, column, format!("unexpected '{}'", c))),
+            }
+            i += 1;
+        }
+        Ok(())
+    }
+
+    //results, move numbers like 12. or 12... that may be glued to the move, and moves with suffixes
+    fn symbol(&mut self, line: usize, column: usize, symbol: &str) -> Result<(), PgnError> {
+        if matches!(symbol, "1-0" | "0-1" | "1/2-1/2") {
+            self.tokens.push_back((line, column, Token::Result(symbol.to_owned())));
+            return Ok(());
+        }
+        let digits = symbol.chars().take_while(|c| c.is_ascii_digit()).count();
+        let dots = symbol[digits..].chars().take_while(|c| *c == '.').count();
+        let number = if digits > 0 && (dots > 0 || digits == symbol.len()) {digits + dots} else {0};
+        let mv = &symbol[number..];
+        if mv.is_empty() {
+            return Ok(());
+        }
+        let column = column + number;
+        let san = mv.trim_end_matches(['!', '?']);
+        let nag = match &mv[san.len()..] {
+            "" => None,
+            "!" => Some(1),
+            "?" => Some(2),
+            "!!" => Some(3),
+            "??" => Some(4),
+            "!?" => Some(5),
+            "?!" => Some(6),
+            suffix => return Err(PgnError::Syntax(line, column + san.len(), format!("unknown suffix {}", suffix))),
+        };
+        self.tokens.push_back((line, column, Token::Move(san.to_owned())));
+        if let Some(nag) = nag {
+            self.tokens.push_back((line, column + san.len(), Token::Nag(nag)));
+        }
+        Ok(())
+    }
+
+    //drops the rest of a broken game, up to the tags of the next one
+    fn skip_game(&mut self, in_movetext: bool) -> Result<(), PgnError> {
+        self.tokens.clear();
+        self.comment = None;
+        //the line that failed may be the first of the movetext, before any of its tokens were read
+        let failed = self.buf.trim();
+        let mut seen_movetext = in_movetext || !failed.is_empty() && !failed.starts_with('[');
+        while self.read_line()? {
+            let text = self.buf.trim();
+            if text.starts_with('[') && seen_movetext {
+                if self.lex_line().is_ok() {
+                    return Ok(());
+                }
+                //the next game starts broken as well
+                seen_movetext = false;
+                continue;
+            }
+            if !text.is_empty() && !text.starts_with('[') {
+                seen_movetext = true;
+            }
+        }
+        Ok(())
+    }
+}
+
+//one line of play that is being read, the main line or a variation
+struct Frame {
+    moves: Vec<MoveNode>,
+    board: BoardB,
+    turn: Side,
+    //position before the last move, variations start there
+    before: (BoardB, Side),
+    //comment before the next move
+    comment: Option<String>,
+    //(line, column) of the '(' that started a variation
+    opened: (usize, usize),
+}
+
+impl Frame {
+    fn new(board: BoardB, turn: Side, opened: (usize, usize)) -> Self {
+        Self { moves: Vec::new(), board, turn, before: (board, turn), comment: None, opened }
+    }
+}
+
+fn append(comment: &mut Option<String>, text: String) {
+    match comment {
+        Some(existing) => {
+            existing.push(' ');
+            existing.push_str(&text);
+        },
+        None => *comment = Some(text),
+    }
+}
+
+//Reads one game after the other with the full move tree. A game with an error is reported and
+//skipped, reading goes on with the next one.
+pub struct PgnTreeReader<R: BufRead> {
+    lexer: Lexer<R>,
+    //tag of the next game, read while ending the previous one
+    pending: Option<Located>,
+    //io errors end the file
+    failed: bool,
+}
+
+impl<R: BufRead> PgnTreeReader<R> {
+    pub fn new(reader: R) -> Self {
+        let lexer = Lexer { reader, buf: String::new(), line: 0, tokens: VecDeque::new(), comment: None };
+        Self { lexer, pending: None, failed: false }
+    }
+
+    fn next_game(&mut self, in_movetext: &mut bool) -> Result<Option<PgnTree>, PgnError> {
+        let mut tags = Vec::new();
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut start = None;
+        loop {
+            let token = match self.pending.take() {
+                Some(token) => Some(token),
+                None => self.lexer.next()?,
+            };
+            let Some((line, column, token)) = token else {
+                break;
+            };
+            if let Token::Tag(name, value) = token {
+                if *in_movetext {
+                    //game without result token
+                    self.pending = Some((line, column, Token::Tag(name, value)));
+                    break;
+                }
+                tags.push((name, value));
+                continue;
+            }
+            if !*in_movetext {
+                *in_movetext = true;
+                let fen = tags.iter().find(|(name, _)| name == "FEN").map_or(START_FEN, |(_, fen)| fen.as_str());
+                let (board, turn) = BoardB::from_fen(fen).map_err(|err| PgnError::Syntax(line, column, format!("invalid FEN tag {:?}", err)))?;
+                start = Some((board, turn));
+                frames.push(Frame::new(board, turn, (line, column)));
+            }
+            let frame = frames.last_mut().expect("Movetext has the main line");
+            match token {
+                Token::Tag(..) => unreachable!("Tags are handled above"),
+                Token::Move(text) => {
+                    let mv = san::parse_san(&frame.board, frame.turn, &text).map_err(|err| PgnError::IllegalMove(line, column, text.clone(), err))?;
+                    frame.before = (frame.board, frame.turn);
+                    frame.board.make_move_promoting(mv.from, mv.to, mv.promotion.unwrap_or(PieceType::Queen));
+                    frame.turn = !frame.turn;
+                    let comment_before = frame.comment.take();
+                    frame.moves.push(MoveNode { san: text, mv, nags: Vec::new(), comment_before, comment: None, variations: Vec::new() });
+                },
+                Token::Nag(nag) => match frame.moves.last_mut() {
+                    Some(node) => node.nags.push(nag),
+                    None => return Err(PgnError::Syntax(line, column, "NAG before the first move".to_owned())),
+                },
+                Token::Comment(text) => match frame.moves.last_mut() {
+                    Some(node) => append(&mut node.comment, text),
+                    None => append(&mut frame.comment, text),
+                },
+                Token::Open => {
+                    if frame.moves.is_empty() {
+                        return Err(PgnError::Syntax(line, column, "variation before the first move".to_owned()));
+                    }
+                    let (board, turn) = frame.before;
+                    frames.push(Frame::new(board, turn, (line, column)));
+                },
+                Token::Close => {
+                    if frames.len() == 1 {
+                        return Err(PgnError::Syntax(line, column, "')' without '('".to_owned()));
+                    }
+                    let variation = frames.pop().expect("Checked above");
+                    let parent = frames.last_mut().expect("Checked above");
+                    if !variation.moves.is_empty() {
+                        parent.moves.last_mut().expect("Variations start after a move").variations.push(variation.moves);
+                    }
+                },
+                Token::Result(result) => {
+                    if frames.len() > 1 {
+                        return Err(PgnError::Syntax(line, column, "result inside a variation".to_owned()));
+                    }
+                    let main = frames.pop().expect("Movetext has the main line");
+                    return Ok(Some(Self::tree(tags, start.expect("Set with the main line"), main, result)));
+                },
+            }
+        }
+        if frames.len() > 1 {
+            let (line, column) = frames.last().expect("Checked above").opened;
+            return Err(PgnError::Syntax(line, column, "'(' without ')'".to_owned()));
+        }
+        match frames.pop() {
+            Some(main) => Ok(Some(Self::tree(tags, start.expect("Set with the main line"), main, "*".to_owned()))),
+            //tags without movetext at the end of the file
+            None if !tags.is_empty() => {
+                let (board, turn) = BoardB::from_fen(START_FEN).expect("Start position is valid");
+                Ok(Some(Self::tree(tags, (board, turn), Frame::new(board, turn, (0, 0)), "*".to_owned())))
+            },
+            None => Ok(None),
+        }
+    }
+
+    fn tree(tags: Vec<(String, String)>, start: (BoardB, Side), main: Frame, result: String) -> PgnTree {
+        PgnTree { tags, start, moves: main.moves, comment: main.comment, result }
+    }
+}
+
+impl<R: BufRead> Iterator for PgnTreeReader<R> {
+    type Item = Result<PgnTree, PgnError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
+        }
+        let mut in_movetext = false;
+        match self.next_game(&mut in_movetext) {
+            Ok(game) => game.map(Ok),
+            Err(err) => {
+                let recovered = match err {
+                    PgnError::Io(_) => Err(()),
+                    _ => self.lexer.skip_game(in_movetext).map_err(|_| ()),
+                };
+                self.failed = recovered.is_err();
+                self.pending = None;
+                Some(Err(err))
+            },
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+
+
+    fn read(pgn: &str) -> Vec<Result<PgnTree, PgnError>> {
+        PgnTreeReader::new(Cursor::new(pgn)).collect()
+    }
+
+    fn one(pgn: &str) -> PgnTree {
+        let mut games = read(pgn);
+        assert_eq!(games.len(), 1);
+        games.pop().unwrap().unwrap()
+    }
+
+    fn sans(line: &[MoveNode]) -> Vec<&str> {
+        line.iter().map(|node| node.san.as_str()).collect()
+    }
+
+    #[test]
+    fn error_positions() {
+        let error = |pgn: &str| read(pgn).into_iter().next().unwrap().err().unwrap();
+        assert!(matches!(error("[Event \"x\"]\n\n1. e4 e5 2. Ke3 *\n"), PgnError::IllegalMove(3, 13, san, _) if san == "Ke3"));
+        //a move number glued to the move doesn't count
+        assert!(matches!(error("1. e4 e5 2.Ke3 *\n"), PgnError::IllegalMove(1, 12, _, _)));
+        assert!(matches!(error("1. e4 & *\n"), PgnError::Syntax(1, 7, _)));
+        assert!(matches!(error("1. e4 e5)\n"), PgnError::Syntax(1, 9, _)));
+        assert!(matches!(error("1. e4\n(1. d4 *\n"), PgnError::Syntax(2, 8, _)));
+        assert!(matches!(error("1. e4!!! *\n"), PgnError::Syntax(1, 6, _)));
+        assert!(matches!(error("1. e4 {never closed\n"), PgnError::Syntax(1, 7, _)));
+    }
+
+    #[test]
+    fn comments() {
+        let tree = one("{before the\nfirst move} 1. e4 {best by test} ; and the rest\ne5 {a} {b} *\n");
+        assert_eq!(sans(&tree.moves), ["e4", "e5"]);
+        assert_eq!(tree.moves[0].comment_before.as_deref(), Some("before the first move"));
+        assert_eq!(tree.moves[0].comment.as_deref(), Some("best by test and the rest"));
+        assert_eq!(tree.moves[1].comment.as_deref(), Some("a b"));
+        let tree = one("[Event \"x\"]\n\n{only a comment} *\n");
+        assert!(tree.moves.is_empty());
+        assert_eq!(tree.comment.as_deref(), Some("only a comment"));
+    }
+
+    #[test]
+    fn nags_and_suffixes() {
+        let tree = one("1. e4! e5?! 2. Nf3 $14 $1 Nc6?? 3. Bb5!? a6!! 4. Ba4? *\n");
+        let nags: Vec<_> = tree.moves.iter().map(|node| node.nags.clone()).collect();
+        assert_eq!(nags, [vec![1], vec![6], vec![14, 1], vec![4], vec![5], vec![3], vec![2]]);
+        assert_eq!(sans(&tree.moves), ["e4", "e5", "Nf3", "Nc6", "Bb5", "a6", "Ba4"]);
+    }
+
+    #[test]
+    fn nested_variations() {
+        let tree = one("1. e4 (1. d4 d5 (1... Nf6 2. c4) 2. c4) (1. c4) e5 2. Nf3 *\n");
+        assert_eq!(sans(&tree.moves), ["e4", "e5", "Nf3"]);
+        let variations = &tree.moves[0].variations;
+        assert_eq!(variations.len(), 2);
+        assert_eq!(sans(&variations[0]), ["d4", "d5", "c4"]);
+        assert_eq!(sans(&variations[1]), ["c4"]);
+        assert_eq!(sans(&variations[0][1].variations[0]), ["Nf6", "c4"]);
+        assert_eq!(tree.move_count(), 9);
+        assert_eq!(tree.result, "*");
+    }
+
+    #[test]
+    fn several_games() {
+        let pgn = "[White \"a\"]\n\n1. e4 e5 1-0\n\n[White \"b\"]\n[FEN \"4k3/8/8/8/8/8/4P3/4K3 b - - 0 1\"]\n\n1... Kd7 2. e4 1/2-1/2\n[White \"c\"]\n1. d4\n";
+        let games: Vec<_> = read(pgn).into_iter().map(Result::unwrap).collect();
+        assert_eq!(games.len(), 3);
+        assert_eq!(games.iter().map(|g| g.tag("White").unwrap()).collect::<Vec<_>>(), ["a", "b", "c"]);
+        assert_eq!(games.iter().map(|g| g.result.as_str()).collect::<Vec<_>>(), ["1-0", "1/2-1/2", "*"]);
+        assert_eq!(games[1].start.1, Side::Black);
+        assert_eq!(sans(&games[1].moves), ["Kd7", "e4"]);
+        let positions = games[1].replay();
+        assert_eq!(positions.len(), 2);
+        assert_eq!(positions[1].1, Side::White);
+    }
+
+    #[test]
+    fn broken_games_are_skipped() {
+        let pgn = "[White \"a\"]\n\n1. e4 e5 2. Ke3 Nc6 3. Nf3 *\n\n[White \"b\"]\n\n1. d4 & d5 *\n\n[White \"c\"]\n\n1. c4 *\n";
+        let games = read(pgn);
+        assert_eq!(games.len(), 3);
+        assert!(matches!(games[0], Err(PgnError::IllegalMove(3, 13, _, _))));
+        assert!(matches!(games[1], Err(PgnError::Syntax(7, 7, _))));
+        let last = games[2].as_ref().unwrap();
+        assert_eq!(last.tag("White"), Some("c"));
+        assert_eq!(sans(&last.moves), ["c4"]);
+    }
+}
@@ -1,6 +1,6 @@
 use std::time::Instant;
 
-use sdl2::{render::Canvas, pixels::Color, rect::Rect, video::Window};
+use sdl2::{render::Canvas, pixels::Color, rect::{Rect, Point}, video::Window, ttf::Font};
 
 use crate::{atlas::{TextureAtlas}, pieces::{Piece, PieceType, Side}};
 
@@ -10,15 +10,23 @@ use crate::{atlas::{TextureAtlas}, pieces::{Piece, PieceType, Side}};
 pub struct Renderer<'a> {
     tex_atlas: &'a TextureAtlas<'a>,
     canvas: &'a mut Canvas<Window>,
+    font: &'a Font<'a, 'static>,
     rects: Vec<(Rect, Color, i32)>,
     images: Vec<((PieceType, Side), Rect, i32)>,
+    //drawn last, top left corner
+    texts: Vec<(String, Color, Point)>,
     last_frame_time: Instant,
 }
 
 
 impl<'a> Renderer<'a> {
-    pub fn new(tex_atlas: &'a TextureAtlas<'a>, canvas: &'a mut Canvas<Window>) -> Self {
-        Self {tex_atlas, rects: Vec::new(), images: Vec::new(), last_frame_time: Instant::now(), canvas}
+    pub fn new(tex_atlas: &'a TextureAtlas<'a>, canvas: &'a mut Canvas<Window>, font: &'a Font<'a, 'static>) -> Self {
+        Self {tex_atlas, font, rects: Vec::new(), images: Vec::new(), texts: Vec::new(), last_frame_time: Instant::now(), canvas}
+    }
+
+    pub fn set_window_width(&mut self, width: u32) {
+        let height = self.canvas.window().size().1;
+        self.canvas.window_mut().set_size(width, height).expect("could not resize window");
     }
     
     pub fn draw_rect(&mut self, rect: Rect, color: Color, depth: i32) {
@@ -29,6 +37,10 @@ impl<'a> Renderer<'a> {
         self.images.push(((piece_ty, side), dst, depth));
     }
 
+    pub fn draw_text(&mut self, text: &str, color: Color, pos: Point) {
+        self.texts.push((text.to_owned(), color, pos));
+    }
+
     pub fn render(&mut self) {
         let current_frame_time = Instant::now();
         let dt = (current_frame_time - self.last_frame_time).as_secs_f32();
@@ -52,8 +64,20 @@ impl<'a> Renderer<'a> {
             ).unwrap();
         }
 
+        //rendering texts
+        let texture_creator = self.canvas.texture_creator();
+        for (text, color, pos) in &self.texts {
+            if text.is_empty() {
+                continue;
+            }
+            let surface = self.font.render(text).blended(*color).unwrap();
+            let texture = texture_creator.create_texture_from_surface(&surface).unwrap();
+            self.canvas.copy(&texture, None, Rect::new(pos.x, pos.y, surface.width(), surface.height())).unwrap();
+        }
+
         self.rects.clear();
         self.images.clear();
+        self.texts.clear();
         self.canvas.present();
     }
 }
\ No newline at end of file
@@ -0,0 +1,628 @@
+use std::{collections::HashMap, fs::File, io::{BufReader, BufWriter, Read, Write}, path::{Path, PathBuf}};
+
+use rand::Rng;
+
+use crate::{boardb::{BoardB, Piece, Pos, PosTrait, BitMap}, pieces::{Side, PieceType}, search::{self, TB_WIN}, tablebase::{self, Wdl}};
+
+
+
+//material sets the generator is meant for, stronger side first
+pub const SUPPORTED: [&str; 5] = ["KQvK", "KRvK", "KPvK", "KBNvK", "KQvKR"];
+//the index is 2 * 64^pieces entries, 4 pieces are already 32MB
+const MAX_PIECES: usize = 4;
+//distances are stored in a signed byte
+const MAX_PLIES: usize = 126;
+
+const MAGIC: [u8; 4] = *b"DTM1";
+pub const EXTENSION: &str = "dtm";
+
+//position flags during generation
+const INVALID: u8 = 1;
+//the side to move has a move that keeps it from losing
+const ESCAPE: u8 = 2;
+const DONE: u8 = 4;
+const WIN_QUEUED: u8 = 8;
+
+//distance to mate in plies from the view of the side to move
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Dtm {
+    Win(u32),
+    Draw,
+    Loss(u32),
+}
+
+impl Dtm {
+    //stored as 0 = draw, n > 0 = mate in n plies, n < 0 = mated in -n - 1 plies
+    fn decode(v: i8) -> Self {
+        match v {
+            0 => Dtm::Draw,
+            v if v > 0 => Dtm::Win(v as u32),
+            v => Dtm::Loss((-(v as i32) - 1) as u32),
+        }
+    }
+
+    //result for the side that made the move leading here
+    pub fn parent(self) -> Self {
+        match self {
+            Dtm::Win(n) => Dtm::Loss(n + 1),
+            Dtm::Draw => Dtm::Draw,
+            Dtm::Loss(n) => Dtm::Win(n + 1),
+        }
+    }
+
+    pub fn wdl(self) -> Wdl {
+        match self {
+            Dtm::Win(_) => Wdl::Win,
+            Dtm::Draw => Wdl::Draw,
+            Dtm::Loss(_) => Wdl::Loss,
+        }
+    }
+
+    //search score, faster mates score higher
+    pub fn score(self, ply: usize) -> i32 {
+        match self {
+            Dtm::Win(n) => TB_WIN - ply as i32 - n as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(n) => -TB_WIN + ply as i32 + n as i32,
+        }
+    }
+
+    //wins before draws before losses, quick wins and slow losses first
+    pub fn rank(self) -> i32 {
+        match self {
+            Dtm::Win(n) => 1000 - n as i32,
+            Dtm::Draw => 0,
+            Dtm::Loss(n) => -1000 + n as i32,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum RetrogradeError {
+    Io(std::io::Error),
+    Magic(PathBuf),
+    //file is shorter or longer than its material needs
+    Size(PathBuf),
+    //unknown piece letters, missing kings or too many pieces
+    Material(String),
+}
+
+//one distance to mate table, indexed by side to move and the square of every piece
+pub struct DtmTable {
+    pub material: String,
+    pieces: Vec<Piece>,
+    values: Vec<i8>,
+}
+
+impl DtmTable {
+    fn size(pieces: usize) -> usize {
+        2 * 64_usize.pow(pieces as u32)
+    }
+
+    fn index(squares: &[u8], turn: Side) -> usize {
+        let side = match turn {Side::White => 0, Side::Black => 1};
+        squares.iter().fold(side, |i, sq| i * 64 + *sq as usize)
+    }
+
+    fn decode_index(&self, mut idx: usize) -> (Vec<u8>, Side) {
+        let mut squares = vec![0; self.pieces.len()];
+        for sq in squares.iter_mut().rev() {
+            *sq = (idx % 64) as u8;
+            idx /= 64;
+        }
+        (squares, if idx == 0 {Side::White} else {Side::Black})
+    }
+
+    //board for the given squares, None if pieces share a square or a pawn is on the first or last rank
+    fn board(&self, squares: &[u8]) -> Option<BoardB> {
+        let mut board = BoardB::empty();
+        for (piece, sq) in self.pieces.iter().zip(squares) {
+            let y = Pos::from_i(*sq).y;
+            if board.occupied(*sq) || piece.ty == PieceType::Pawn && (y == 0 || y == 7) {
+                return None;
+            }
+            board.set_piece(*sq, *piece);
+            if piece.ty == PieceType::King {
+                match piece.side {
+                    Side::White => board.kings.0 = *sq,
+                    Side::Black => board.kings.1 = *sq,
+                }
+            }
+        }
+        Some(board)
+    }
+
+    //legal position behind an index, the side that just moved may not be in check
+    fn position(&self, idx: usize) -> Option<(BoardB, Side)> {
+        let (squares, turn) = self.decode_index(idx);
+        let board = self.board(&squares)?;
+        (!search::in_check(&board, !turn)).then_some((board, turn))
+    }
+
+    //board has to have exactly the material of the table with white being the first side
+    fn probe(&self, board: &BoardB, turn: Side) -> Dtm {
+        let mut used = 0_u64;
+        let squares: Vec<u8> = self.pieces.iter().map(|piece| {
+            let sq = (0..64)
+                .find(|i| used & 1 << i == 0 && board.get_piece_at_pos(*i).is_some_and(|p| p.ty == piece.ty && p.side == piece.side))
+                .expect("Board does not match the table material");
+            used |= 1 << sq;
+            sq
+        }).collect();
+        Dtm::decode(self.values[Self::index(&squares, turn)])
+    }
+
+    //random legal position with the given side to move
+    pub fn random_position<R: Rng>(&self, turn: Side, rng: &mut R) -> Option<(BoardB, Dtm)> {
+        let half = Self::size(self.pieces.len()) / 2;
+        for _ in 0..10_000 {
+            let idx = rng.gen_range(0..half) + if turn == Side::White {0} else {half};
+            if let Some((board, _)) = self.position(idx) {
+                return Some((board, Dtm::decode(self.values[idx])));
+            }
+        }
+        None
+    }
+
+    pub fn longest_win(&self) -> u32 {
+        self.values.iter().map(|v| *v as i32).max().unwrap_or(0).max(0) as u32
+    }
+
+    pub fn load(path: &Path) -> Result<Self, RetrogradeError> {
+        let mut reader = BufReader::new(File::open(path).map_err(RetrogradeError::Io)?);
+        let mut header = [0; 5];
+        reader.read_exact(&mut header).map_err(RetrogradeError::Io)?;
+        if header[..4] != MAGIC {
+            return Err(RetrogradeError::Magic(path.to_owned()));
+        }
+        let mut material = vec![0; header[4] as usize];
+        reader.read_exact(&mut material).map_err(RetrogradeError::Io)?;
+        let material = String::from_utf8(material).map_err(|_| RetrogradeError::Magic(path.to_owned()))?;
+        let pieces = parse_material(&material)?;
+        let mut bytes = Vec::with_capacity(Self::size(pieces.len()));
+        reader.read_to_end(&mut bytes).map_err(RetrogradeError::Io)?;
+        if bytes.len() != Self::size(pieces.len()) {
+            return Err(RetrogradeError::Size(path.to_owned()));
+        }
+        let values = bytes.into_iter().map(|b| b as i8).collect();
+        Ok(Self { material, pieces, values })
+    }
+
+    //written as <material>.dtm into dir
+    pub fn save(&self, dir: &Path) -> Result<PathBuf, RetrogradeError> {
+        let path = dir.join(format!("{}.{}", self.material, EXTENSION));
+        let mut writer = BufWriter::new(File::create(&path).map_err(RetrogradeError::Io)?);
+        writer.write_all(&MAGIC).map_err(RetrogradeError::Io)?;
+        writer.write_all(&[self.material.len() as u8]).map_err(RetrogradeError::Io)?;
+        writer.write_all(self.material.as_bytes()).map_err(RetrogradeError::Io)?;
+        let bytes: Vec<u8> = self.values.iter().map(|v| *v as u8).collect();
+        writer.write_all(&bytes).map_err(RetrogradeError::Io)?;
+        writer.flush().map_err(RetrogradeError::Io)?;
+        Ok(path)
+    }
+}
+
+#[derive(Default)]
+pub struct DtmTables {
+    tables: HashMap<String, DtmTable>,
+}
+
+impl DtmTables {
+    //every .dtm file in dir
+    pub fn load(dir: &Path) -> Result<Self, RetrogradeError> {
+        let mut tables = Self::default();
+        for entry in std::fs::read_dir(dir).map_err(RetrogradeError::Io)? {
+            let path = entry.map_err(RetrogradeError::Io)?.path();
+            if path.extension().is_some_and(|ext| ext == EXTENSION) {
+                let table = DtmTable::load(&path)?;
+                tables.tables.insert(table.material.clone(), table);
+            }
+        }
+        Ok(tables)
+    }
+
+    pub fn len(&self) -> usize {
+        self.tables.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tables.is_empty()
+    }
+
+    pub fn table(&self, material: &str) -> Option<&DtmTable> {
+        self.tables.get(&canonical_material(material).ok()?)
+    }
+
+    pub fn tables(&self) -> impl Iterator<Item = &DtmTable> {
+        self.tables.values()
+    }
+
+    pub fn max_pieces(&self) -> usize {
+        self.tables.values().map(|t| t.pieces.len()).max().unwrap_or(0)
+    }
+
+    pub fn probe(&self, board: &BoardB, turn: Side) -> Option<Dtm> {
+        if let Some(table) = self.tables.get(&tablebase::material_key(board, Side::White)) {
+            return Some(table.probe(board, turn));
+        }
+        if let Some(table) = self.tables.get(&tablebase::material_key(board, Side::Black)) {
+            return Some(table.probe(&mirrored(board), !turn));
+        }
+        board.insufficient_material().then_some(Dtm::Draw)
+    }
+
+    //generates the table and every table its captures and promotions lead into
+    pub fn generate(&mut self, material: &str) -> Result<&DtmTable, RetrogradeError> {
+        let material = canonical_material(material)?;
+        if !self.tables.contains_key(&material) {
+            let pieces = parse_material(&material)?;
+            for dependency in dependencies(&pieces) {
+                self.generate(&dependency)?;
+            }
+            let table = Generator::new(&material, pieces, self).run();
+            self.tables.insert(material.clone(), table);
+        }
+        Ok(&self.tables[&material])
+    }
+}
+
+//"KQvKR" -> white king, white queen, black king, black rook
+fn parse_material(material: &str) -> Result<Vec<Piece>, RetrogradeError> {
+    let error = || RetrogradeError::Material(material.to_owned());
+    let (white, black) = material.split_once('v').ok_or_else(error)?;
+    let mut pieces = Vec::new();
+    for (side, letters) in [(Side::White, white), (Side::Black, black)] {
+        if letters.chars().filter(|c| *c == 'K').count() != 1 {
+            return Err(error());
+        }
+        for c in letters.chars() {
+            let ty = match c {
+                'K' => PieceType::King,
+                'Q' => PieceType::Queen,
+                'R' => PieceType::Rook,
+                'B' => PieceType::Bishop,
+                'N' => PieceType::Knight,
+                'P' => PieceType::Pawn,
+                _ => return Err(error()),
+            };
+            pieces.push(Piece::new(ty, side));
+        }
+    }
+    if pieces.len() > MAX_PIECES {
+        return Err(error());
+    }
+    Ok(pieces)
+}
+
+//same letter order as tablebase::material_key, the side with more material first
+fn canonical_material(material: &str) -> Result<String, RetrogradeError> {
+    let pieces = parse_material(material)?;
+    Ok(material_of(&pieces))
+}
+
+fn material_of(pieces: &[Piece]) -> String {
+    let order = [PieceType::King, PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight, PieceType::Pawn];
+    let letters = ['K', 'Q', 'R', 'B', 'N', 'P'];
+    let side_key = |side: Side| -> (i32, String) {
+        let mut key = String::new();
+        for (ty, c) in order.iter().zip(letters) {
+            for _ in pieces.iter().filter(|p| p.side == side && p.ty == *ty) {
+                key.push(c);
+            }
+        }
+        (pieces.iter().filter(|p| p.side == side).map(|p| p.value()).sum(), key)
+    };
+    let (white, black) = (side_key(Side::White), side_key(Side::Black));
+    if white >= black {
+        format!("{}v{}", white.1, black.1)
+    } else {
+        format!("{}v{}", black.1, white.1)
+    }
+}
+
+//tables reached by capturing a piece or promoting a pawn (to a queen, like every move on the board)
+fn dependencies(pieces: &[Piece]) -> Vec<String> {
+    let mut dependencies = Vec::new();
+    for (n, piece) in pieces.iter().enumerate() {
+        if piece.ty == PieceType::King {
+            continue;
+        }
+        let mut captured = pieces.to_vec();
+        captured.remove(n);
+        let mut promoted = pieces.to_vec();
+        promoted[n] = Piece::new(PieceType::Queen, piece.side);
+        let mut next = vec![captured];
+        if piece.ty == PieceType::Pawn {
+            next.push(promoted);
+        }
+        for next in next {
+            if !insufficient(&next) {
+                dependencies.push(material_of(&next));
+            }
+        }
+    }
+    dependencies.sort();
+    dependencies.dedup();
+    dependencies
+}
+
+//bare kings or a single minor piece can never mate
+fn insufficient(pieces: &[Piece]) -> bool {
+    let others: Vec<&Piece> = pieces.iter().filter(|p| p.ty != PieceType::King).collect();
+    others.is_empty() || others.len() == 1 && matches!(others[0].ty, PieceType::Bishop | PieceType::Knight)
+}
+
+//colors swapped and ranks flipped, so black's material can be looked up in a table for white
+fn mirrored(board: &BoardB) -> BoardB {
+    let mut mirror = BoardB::empty();
+    for (i, piece) in board.board.iter().enumerate() {
+        if let Some(piece) = piece {
+            let pos = Pos::from_i(i as u8);
+            let to = Pos::new(pos.x, 7 - pos.y).to_i();
+            mirror.set_piece(to, Piece::new(piece.ty, !piece.side));
+            if piece.ty == PieceType::King {
+                match piece.side {
+                    Side::White => mirror.kings.1 = to,
+                    Side::Black => mirror.kings.0 = to,
+                }
+            }
+        }
+    }
+    mirror
+}
+
+//Retrograde analysis: mates are found first, then positions are resolved backwards in order of
+//their distance. A position wins once one move leads to a lost position and loses once every move
+//leads to a won one. Captures and promotions leave the table and are looked up in smaller tables.
+//Castling and en passant are never possible in the supported material sets and are not modelled.
+struct Generator<'a> {
+    table: DtmTable,
+    subtables: &'a DtmTables,
+    flags: Vec<u8>,
+    //moves that stay inside the table and were not proven to lose yet
+    remaining: Vec<u8>,
+    //slowest loss through a capture or promotion, plies
+    conversion_loss: Vec<u8>,
+}
+
+//(index, distance in plies, win for the side to move)
+type Seed = (u32, u8, bool);
+
+impl<'a> Generator<'a> {
+    fn new(material: &str, pieces: Vec<Piece>, subtables: &'a DtmTables) -> Self {
+        let size = DtmTable::size(pieces.len());
+        Self {
+            table: DtmTable { material: material.to_owned(), pieces, values: vec![0; size] },
+            subtables,
+            flags: vec![0; size],
+            remaining: vec![0; size],
+            conversion_loss: vec![0; size],
+        }
+    }
+
+    fn run(mut self) -> DtmTable {
+        let seeds = self.classify_all();
+        let mut buckets: Vec<Vec<(u32, bool)>> = vec![Vec::new(); MAX_PLIES + 2];
+        for (idx, distance, win) in seeds {
+            buckets[distance as usize].push((idx, win));
+        }
+        let mut predecessors = Vec::with_capacity(64);
+        let mut resolved = 0;
+        for distance in 0..=MAX_PLIES {
+            for (idx, win) in std::mem::take(&mut buckets[distance]) {
+                let idx = idx as usize;
+                if self.flags[idx] & DONE != 0 {
+                    continue;
+                }
+                self.flags[idx] |= DONE;
+                self.table.values[idx] = if win {distance as i8} else {-(distance as i8) - 1};
+                resolved += 1;
+
+                predecessors.clear();
+                self.predecessors(idx, &mut predecessors);
+                for &prev in &predecessors {
+                    if self.flags[prev] & (INVALID | DONE) != 0 {
+                        continue;
+                    }
+                    if !win {
+                        //moving here wins for the side that moved
+                        if self.flags[prev] & WIN_QUEUED == 0 {
+                            self.flags[prev] |= WIN_QUEUED | ESCAPE;
+                            assert!(distance < MAX_PLIES, "{}: distance to mate does not fit the table", self.table.material);
+                            buckets[distance + 1].push((prev as u32, true));
+                        }
+                    } else {
+                        self.remaining[prev] -= 1;
+                        if self.remaining[prev] == 0 && self.flags[prev] & ESCAPE == 0 {
+                            let loss = (distance + 1).max(self.conversion_loss[prev] as usize);
+                            assert!(loss <= MAX_PLIES, "{}: distance to mate does not fit the table", self.table.material);
+                            buckets[loss].push((prev as u32, false));
+                        }
+                    }
+                }
+            }
+        }
+        println!("{}: {} decided positions, longest mate {} plies", self.table.material, resolved, self.table.longest_win());
+        self.table
+    }
+
+    //first pass over every index: legality, mates, stalemates and results of captures/promotions
+    fn classify_all(&mut self) -> Vec<Seed> {
+        let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk = self.flags.len().div_ceil(threads);
+        let (table, subtables) = (&self.table, self.subtables);
+        std::thread::scope(|s| {
+            let handles: Vec<_> = self.flags.chunks_mut(chunk)
+                .zip(self.remaining.chunks_mut(chunk))
+                .zip(self.conversion_loss.chunks_mut(chunk))
+                .enumerate()
+                .map(|(n, ((flags, remaining), conversion_loss))| s.spawn(move || {
+                    let mut seeds = Vec::new();
+                    let entries = flags.iter_mut().zip(remaining.iter_mut()).zip(conversion_loss.iter_mut());
+                    for (i, ((flags, remaining), conversion_loss)) in entries.enumerate() {
+                        if let Some(seed) = classify(table, subtables, n * chunk + i, flags, remaining, conversion_loss) {
+                            seeds.push(seed);
+                        }
+                    }
+                    seeds
+                }))
+                .collect();
+            handles.into_iter().flat_map(|h| h.join().expect("Generator thread panicked")).collect()
+        })
+    }
+
+    //positions with the other side to move that reach idx in one move
+    fn predecessors(&self, idx: usize, out: &mut Vec<usize>) {
+        let (squares, turn) = self.table.decode_index(idx);
+        let board = self.table.board(&squares).expect("Resolved an invalid position");
+        let occupied = squares.iter().fold(0_u64, |m, sq| m | 1 << sq);
+        let mover = !turn;
+        for (slot, piece) in self.table.pieces.iter().enumerate() {
+            if piece.side != mover {
+                continue;
+            }
+            let from = squares[slot];
+            //non-captures only, captures come from tables with more material
+            let origins = if piece.ty == PieceType::Pawn {
+                pawn_origins(from, piece.side, occupied)
+            } else {
+                board.pseudo_moves(from) & !occupied
+            };
+            let mut prev = squares.clone();
+            for origin in origins.ones() {
+                prev[slot] = origin;
+                out.push(DtmTable::index(&prev, mover));
+            }
+        }
+    }
+}
+
+fn classify(table: &DtmTable, subtables: &DtmTables, idx: usize, flags: &mut u8, remaining: &mut u8, conversion_loss: &mut u8) -> Option<Seed> {
+    let Some((board, turn)) = table.position(idx) else {
+        *flags = INVALID;
+        return None;
+    };
+    let mvs = search::legal_moves(&board, turn);
+    if mvs.is_empty() {
+        if search::in_check(&board, turn) {
+            return Some((idx as u32, 0, false));
+        }
+        //stalemate stays a draw
+        *flags = DONE;
+        return None;
+    }
+    let mut quickest_win: Option<u32> = None;
+    let mut slowest_loss: Option<u32> = None;
+    for (from, to) in mvs {
+        let promotes = board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::Pawn) && matches!(Pos::from_i(to).y, 0 | 7);
+        if !board.occupied(to) && !promotes {
+            *remaining += 1;
+            continue;
+        }
+        let mut b = board;
+        b.make_move(from, to);
+        match subtables.probe(&b, !turn).expect("Table of a capture or promotion is missing").parent() {
+            Dtm::Win(n) => quickest_win = Some(quickest_win.map_or(n, |w| w.min(n))),
+            Dtm::Draw => *flags |= ESCAPE,
+            Dtm::Loss(n) => slowest_loss = Some(slowest_loss.map_or(n, |l| l.max(n))),
+        }
+    }
+    if let Some(n) = quickest_win {
+        *flags |= ESCAPE;
+        return Some((idx as u32, n as u8, true));
+    }
+    *conversion_loss = slowest_loss.unwrap_or(0) as u8;
+    match slowest_loss {
+        Some(n) if *remaining == 0 && *flags & ESCAPE == 0 => Some((idx as u32, n as u8, false)),
+        _ => None,
+    }
+}
+
+//squares a pawn on sq could have come from without capturing
+fn pawn_origins(sq: u8, side: Side, occupied: u64) -> u64 {
+    let pos = Pos::from_i(sq);
+    //white pawns move towards y = 0
+    let (back, start) = match side {
+        Side::White => (1, 6),
+        Side::Black => (-1, 1),
+    };
+    let mut origins = 0;
+    let one = pos + Pos::new(0, back);
+    //pawns never stand behind their start rank
+    let behind_start = |y: i8| if back > 0 {y > start} else {y < start};
+    if !behind_start(one.y) && occupied & 1 << one.to_i() == 0 {
+        origins |= 1 << one.to_i();
+        let two = one + Pos::new(0, back);
+        if two.y == start && occupied & 1 << two.to_i() == 0 {
+            origins |= 1 << two.to_i();
+        }
+    }
+    origins
+}
+
+//generated once for every test that needs them, KPvK alone takes minutes in debug builds
+#[cfg(test)]
+pub fn test_tables() -> &'static DtmTables {
+    static TABLES: std::sync::OnceLock<DtmTables> = std::sync::OnceLock::new();
+    TABLES.get_or_init(|| {
+        let mut tables = DtmTables::default();
+        for material in ["KQvK", "KRvK", "KPvK"] {
+            tables.generate(material).expect("Test tables failed to generate");
+        }
+        tables
+    })
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+
+    fn probe(fen: &str) -> Dtm {
+        let (board, turn) = BoardB::from_fen(fen).unwrap();
+        test_tables().probe(&board, turn).unwrap()
+    }
+
+    #[test]
+    fn longest_mates() {
+        //mate in 10 and mate in 16 moves
+        assert_eq!(test_tables().table("KQvK").unwrap().longest_win(), 19);
+        assert_eq!(test_tables().table("KRvK").unwrap().longest_win(), 31);
+    }
+
+    #[test]
+    fn mates_and_stalemates() {
+        assert_eq!(probe("k7/1Q6/1K6/8/8/8/8/8 b - - 0 1"), Dtm::Loss(0));
+        assert_eq!(probe("k7/8/1QK5/8/8/8/8/8 w - - 0 1"), Dtm::Win(1));
+        assert_eq!(probe("k7/2Q5/1K6/8/8/8/8/8 b - - 0 1"), Dtm::Draw);
+        assert_eq!(probe("k7/P7/1K6/8/8/8/8/8 b - - 0 1"), Dtm::Draw);
+    }
+
+    #[test]
+    fn pawn_endings() {
+        //king in front of the rook pawn holds, the king on the sixth rank ahead of its pawn wins
+        assert_eq!(probe("k7/8/8/8/8/8/P7/K7 w - - 0 1"), Dtm::Draw);
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").wdl(), Wdl::Win);
+        assert_eq!(probe("4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").wdl(), Wdl::Loss);
+        //same position with colors swapped goes through the mirrored table
+        assert_eq!(probe("8/8/8/8/4p3/4k3/8/4K3 b - - 0 1"), probe("4k3/8/4K3/4P3/8/8/8/8 w - - 0 1"));
+    }
+
+    #[test]
+    fn save_load_roundtrip() {
+        let dir = std::env::temp_dir().join(format!("chess-cp-dtm-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let table = test_tables().table("KRvK").unwrap();
+        table.save(&dir).unwrap();
+        let loaded = DtmTables::load(&dir);
+        std::fs::remove_dir_all(&dir).unwrap();
+        let loaded = loaded.unwrap();
+        assert_eq!(loaded.len(), 1);
+        let loaded = loaded.table("KRvK").unwrap();
+        assert_eq!(loaded.material, table.material);
+        assert!(loaded.pieces == table.pieces && loaded.values == table.values);
+    }
+}
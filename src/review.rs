@@ -0,0 +1,204 @@
+use std::time::{Duration, Instant};
+
+use crate::{boardb::FenError, gameb::{GameB, PlayerType}, pgn::START_FEN, pgn_tree::PgnTree, pieces::{PieceType, Side}, san};
+
+
+
+//autoplay never gets faster or slower than this
+const MIN_SPEED: Duration = Duration::from_millis(100);
+const MAX_SPEED: Duration = Duration::from_secs(10);
+
+//Steps through a finished or loaded game on its own copy of the board. The moves can't be changed
+//until the user branches off into analysis, any navigation goes back to the game's line.
+pub struct Review {
+    //the position that is shown, undo and redo move along the line
+    pub game: GameB,
+    start_fen: String,
+    line: Vec<(u8, u8)>,
+    sans: Vec<String>,
+    //ply the analysis branched off at
+    branched: Option<usize>,
+    //last autoplay step, None while stopped
+    autoplay: Option<Instant>,
+    pub speed: Duration,
+}
+
+#[derive(Debug)]
+pub enum ReviewError {
+    Fen(FenError),
+    //(ply, move) the game can't play, it only knows promotions to a queen
+    Move(usize, String),
+}
+
+//a game between two humans with the moves played, pawns promote to queens
+fn replay(start_fen: &str, line: &[(u8, u8)]) -> Result<GameB, ReviewError> {
+    let mut game = GameB::from_fen(PlayerType::Me, PlayerType::Me, start_fen, false).map_err(ReviewError::Fen)?;
+    for (ply, (from, to)) in line.iter().enumerate() {
+        if !game.make_move(*from, *to) {
+            return Err(ReviewError::Move(ply, format!("{}{}", san::square_name(*from), san::square_name(*to))));
+        }
+    }
+    Ok(game)
+}
+
+//the main line of a loaded game, variations are left out
+pub fn game_from_pgn(tree: &PgnTree) -> Result<GameB, ReviewError> {
+    //the board would make a queen and the moves after it could turn illegal
+    let underpromotion = tree.moves.iter().position(|node| node.mv.promotion.is_some_and(|piece| piece != PieceType::Queen));
+    if let Some(ply) = underpromotion {
+        return Err(ReviewError::Move(ply, tree.moves[ply].san.clone()));
+    }
+    replay(tree.tag("FEN").unwrap_or(START_FEN), &tree.main_line())
+}
+
+impl Review {
+    //starts at the last position of the game
+    pub fn new(game: &GameB, speed: Duration) -> Result<Self, ReviewError> {
+        let (mut board, mut turn) = game.start_position();
+        let sans = game.moves.iter().map(|(from, to)| {
+            let san = san::to_san(&board, turn, *from, *to);
+            board.make_move(*from, *to);
+            turn = !turn;
+            san
+        }).collect();
+        Ok(Self {
+            game: replay(game.start_fen(), &game.moves)?,
+            start_fen: game.start_fen().to_owned(),
+            line: game.moves.clone(),
+            sans,
+            branched: None,
+            autoplay: None,
+            speed,
+        })
+    }
+
+    //plies of the line that are on the board, where the analysis started while branched
+    pub fn ply(&self) -> usize {
+        self.branched.unwrap_or(self.game.moves.len())
+    }
+
+    pub fn len(&self) -> usize {
+        self.line.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.line.is_empty()
+    }
+
+    pub fn sans(&self) -> &[String] {
+        &self.sans
+    }
+
+    pub fn first_side(&self) -> Side {
+        self.game.start_position().1
+    }
+
+    //move number of the first move, from the start fen
+    pub fn first_number(&self) -> usize {
+        self.start_fen.split_whitespace().nth(5).and_then(|n| n.parse().ok()).unwrap_or(1)
+    }
+
+    pub fn branched(&self) -> bool {
+        self.branched.is_some()
+    }
+
+    //moves on the board are allowed from here on, the line stays as it is
+    pub fn branch(&mut self) {
+        if self.branched.is_none() {
+            self.branched = Some(self.game.moves.len());
+            self.autoplay = None;
+        }
+    }
+
+    pub fn goto(&mut self, ply: usize) {
+        let ply = ply.min(self.line.len());
+        if let Some(branched) = self.branched.take() {
+            //the branch's moves replaced the rest of the line in the redo moves
+            self.game = replay(&self.start_fen, &self.line).expect("Line was played before");
+            while self.game.moves.len() > branched && self.game.undo() {}
+        }
+        while self.game.moves.len() > ply && self.game.undo() {}
+        while self.game.moves.len() < ply && self.game.redo() {}
+    }
+
+    pub fn first(&mut self) {
+        self.goto(0);
+    }
+
+    pub fn prev(&mut self) {
+        self.goto(self.ply().saturating_sub(1));
+    }
+
+    pub fn next(&mut self) {
+        self.goto(self.ply() + 1);
+    }
+
+    pub fn last(&mut self) {
+        self.goto(self.line.len());
+    }
+
+    //goes on from the shown move of the line, from the start when the end was reached
+    pub fn toggle_autoplay(&mut self) {
+        if self.autoplay.take().is_none() {
+            let ply = if self.ply() == self.line.len() {0} else {self.ply()};
+            self.goto(ply);
+            self.autoplay = Some(Instant::now());
+        }
+    }
+
+    pub fn faster(&mut self) {
+        self.speed = (self.speed / 2).max(MIN_SPEED);
+    }
+
+    pub fn slower(&mut self) {
+        self.speed = (self.speed * 2).min(MAX_SPEED);
+    }
+
+    //call every frame, autoplay stops at the end of the line
+    pub fn update(&mut self) {
+        if let Some(last_step) = self.autoplay && last_step.elapsed() >= self.speed {
+            self.next();
+            self.autoplay = (self.ply() < self.line.len()).then(Instant::now);
+        }
+    }
+
+    //first row of the move list panel
+    pub fn header(&self) -> String {
+        match (self.branched, self.autoplay) {
+            (Some(ply), _) => format!("analysis from ply {}", ply),
+            (None, Some(_)) => format!("move {}/{}  autoplay {:.1}s", self.ply(), self.len(), self.speed.as_secs_f32()),
+            (None, None) => format!("move {}/{}", self.ply(), self.len()),
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use crate::pgn_tree::PgnTreeReader;
+    use super::*;
+
+
+
+    fn tree(pgn: &str) -> PgnTree {
+        PgnTreeReader::new(Cursor::new(pgn)).next().unwrap().unwrap()
+    }
+
+    #[test]
+    fn underpromotions_are_errors() {
+        let queen = tree("[FEN \"8/P7/8/8/8/8/8/k6K w - - 0 1\"]\n\n1. a8=Q Kb2 2. Qb7+ *\n");
+        assert_eq!(game_from_pgn(&queen).unwrap().moves.len(), 3);
+        //on the board it would be a queen and Nb6 couldn't be played
+        let knight = tree("[FEN \"8/P7/8/8/8/8/8/1k5K w - - 0 1\"]\n\n1. a8=N Kb2 2. Nb6 *\n");
+        assert!(matches!(game_from_pgn(&knight), Err(ReviewError::Move(0, san)) if san == "a8=N"));
+    }
+
+    #[test]
+    fn replay_stops_at_illegal_moves() {
+        let e4 = (san::square_from_str("e2").unwrap(), san::square_from_str("e4").unwrap());
+        assert!(matches!(replay(START_FEN, &[e4, e4]), Err(ReviewError::Move(1, mv)) if mv == "e2e4"));
+    }
+}
@@ -0,0 +1,179 @@
+use std::collections::HashMap;
+
+use crate::{boardb::{BoardB, Pos, PosTrait}, pieces::{Side, PieceType}};
+
+
+
+#[derive(Debug, PartialEq)]
+pub enum SanError {
+    Syntax,
+    //no legal move matches
+    Illegal,
+    //more than one legal move matches
+    Ambiguous,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SanMove {
+    pub from: u8,
+    pub to: u8,
+    pub promotion: Option<PieceType>,
+}
+
+pub fn square_from_str(s: &str) -> Option<u8> {
+    let mut chars = s.chars();
+    let file = chars.next().filter(|c| ('a'..='h').contains(c))?;
+    let rank = chars.next().filter(|c| ('1'..='8').contains(c))?;
+    if chars.next().is_some() {
+        return None;
+    }
+    Some(Pos::new((file as u8 - b'a') as i8, 8 - (rank as u8 - b'0') as i8).to_i())
+}
+
+pub fn square_name(i: u8) -> String {
+    let pos = Pos::from_i(i);
+    format!("{}{}", (b'a' + pos.x as u8) as char, 8 - pos.y)
+}
+
+pub fn piece_char(ty: PieceType) -> char {
+    match ty {
+        PieceType::King => 'K',
+        PieceType::Queen => 'Q',
+        PieceType::Rook => 'R',
+        PieceType::Bishop => 'B',
+        PieceType::Knight => 'N',
+        PieceType::Pawn => 'P',
+    }
+}
+
+pub fn piece_from_char(c: char) -> Option<PieceType> {
+    match c.to_ascii_uppercase() {
+        'K' => Some(PieceType::King),
+        'Q' => Some(PieceType::Queen),
+        'R' => Some(PieceType::Rook),
+        'B' => Some(PieceType::Bishop),
+        'N' => Some(PieceType::Knight),
+        'P' => Some(PieceType::Pawn),
+        _ => None,
+    }
+}
+
+//resolves a move like "Nbd7", "exd6", "e8=Q+" or "O-O" against the legal moves of the position
+pub fn parse_san(board: &BoardB, turn: Side, san: &str) -> Result<SanMove, SanError> {
+    let san = san.trim_end_matches(['+', '#', '!', '?']);
+    let mut legal = HashMap::with_capacity(16);
+    board.valid_moves(turn, &mut legal);
+    let is_legal = |from: u8, to: u8| legal.get(&from).is_some_and(|tos| tos & 1 << to != 0);
+
+    if matches!(san, "O-O" | "0-0" | "O-O-O" | "0-0-0") {
+        let king = board.find_king(turn);
+        let x = if san.len() == 3 {6} else {2};
+        let to = Pos::new(x, Pos::from_i(king).y).to_i();
+        return if is_legal(king, to) {
+            Ok(SanMove { from: king, to, promotion: None })
+        } else {
+            Err(SanError::Illegal)
+        };
+    }
+
+    let (body, promotion) = match san.find('=') {
+        Some(i) => (&san[..i], Some(san[i + 1..].chars().next().and_then(piece_from_char).ok_or(SanError::Syntax)?)),
+        //some writers leave out the '=', like "e8Q"
+        None => match san.chars().last().filter(|c| c.is_ascii_uppercase()).and_then(piece_from_char) {
+            Some(piece) if san.len() > 2 => (&san[..san.len() - 1], Some(piece)),
+            _ => (san, None),
+        },
+    };
+    let body: String = body.chars().filter(|c| *c != 'x' && *c != '-').collect();
+    if body.len() < 2 {
+        return Err(SanError::Syntax);
+    }
+    let (piece, body) = match body.chars().next().filter(|c| c.is_ascii_uppercase()) {
+        Some(c) => (piece_from_char(c).ok_or(SanError::Syntax)?, &body[1..]),
+        None => (PieceType::Pawn, &body[..]),
+    };
+    if body.len() < 2 {
+        return Err(SanError::Syntax);
+    }
+    let to = square_from_str(&body[body.len() - 2..]).ok_or(SanError::Syntax)?;
+    let mut from_file = None;
+    let mut from_rank = None;
+    for c in body[..body.len() - 2].chars() {
+        match c {
+            'a'..='h' => from_file = Some((c as u8 - b'a') as i8),
+            '1'..='8' => from_rank = Some(8 - (c as u8 - b'0') as i8),
+            _ => return Err(SanError::Syntax),
+        }
+    }
+
+    let mut candidates = legal.iter()
+        .filter(|(from, tos)| *tos & 1 << to != 0 && board.get_piece_at_pos(**from).is_some_and(|p| p.ty == piece))
+        .map(|(from, _)| *from)
+        .filter(|from| {
+            let pos = Pos::from_i(*from);
+            from_file.is_none_or(|x| pos.x == x) && from_rank.is_none_or(|y| pos.y == y)
+        });
+    let from = candidates.next().ok_or(SanError::Illegal)?;
+    if candidates.next().is_some() {
+        return Err(SanError::Ambiguous);
+    }
+    let last_rank = Pos::from_i(to).y == 0 || Pos::from_i(to).y == 7;
+    let promotion = match (piece, last_rank) {
+        (PieceType::Pawn, true) => Some(promotion.unwrap_or(PieceType::Queen)),
+        _ => None,
+    };
+    Ok(SanMove { from, to, promotion })
+}
+
+//SAN of a legal move, pawns promote to a queen like on the board
+pub fn to_san(board: &BoardB, turn: Side, from: u8, to: u8) -> String {
+    let piece = board.get_piece_at_pos(from).expect("No piece on the from square");
+    let (from_pos, to_pos) = (Pos::from_i(from), Pos::from_i(to));
+    let mut san = String::new();
+    if piece.ty == PieceType::King && (from_pos.x - to_pos.x).abs() == 2 {
+        san += if to_pos.x == 6 {"O-O"} else {"O-O-O"};
+    } else {
+        //pawns moving diagonally always capture, en passant onto an empty square
+        let capture = board.occupied(to) || piece.ty == PieceType::Pawn && from_pos.x != to_pos.x;
+        if piece.ty == PieceType::Pawn {
+            if capture {
+                san.push((b'a' + from_pos.x as u8) as char);
+            }
+        } else {
+            san.push(piece_char(piece.ty));
+            let mut legal = HashMap::with_capacity(16);
+            board.valid_moves(turn, &mut legal);
+            //other pieces of the same type that could go there as well
+            let others: Vec<Pos> = legal.iter()
+                .filter(|(f, tos)| **f != from && *tos & 1 << to != 0 && board.get_piece_at_pos(**f).is_some_and(|p| p.ty == piece.ty))
+                .map(|(f, _)| Pos::from_i(*f))
+                .collect();
+            if !others.is_empty() {
+                let square = square_name(from);
+                if others.iter().all(|p| p.x != from_pos.x) {
+                    san += &square[..1];
+                } else if others.iter().all(|p| p.y != from_pos.y) {
+                    san += &square[1..];
+                } else {
+                    san += &square;
+                }
+            }
+        }
+        if capture {
+            san.push('x');
+        }
+        san += &square_name(to);
+        if piece.ty == PieceType::Pawn && (to_pos.y == 0 || to_pos.y == 7) {
+            san += "=Q";
+        }
+    }
+
+    let mut after = *board;
+    after.make_move(from, to);
+    if after.checkers(!turn) > 0 {
+        let mut replies = HashMap::with_capacity(16);
+        after.valid_moves(!turn, &mut replies);
+        san.push(if replies.is_empty() {'#'} else {'+'});
+    }
+    san
+}
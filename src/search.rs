@@ -0,0 +1,598 @@
+use std::{collections::HashMap, sync::{atomic::{AtomicBool, AtomicU64, Ordering}, mpsc::Sender, Arc}, thread::JoinHandle};
+
+use rand::{rngs::StdRng, SeedableRng};
+
+use crate::{boardb::{BoardB, BitMap, MoveList, Pos, PosTrait}, pieces::Side, tt::{TranspositionTable, TTEntry, Bound}, tablebase::{self, Tablebases}, skill::Skill};
+
+
+
+pub const MATE: i32 = 1_000_000;
+const INFINITY: i32 = 2_000_000;
+//scores beyond this are mates, the distance is the difference to MATE
+const MATE_BOUND: i32 = MATE - 1000;
+//tablebase wins rank below any mate the search actually sees
+pub const TB_WIN: i32 = MATE_BOUND - 1000;
+//helper threads deepen until the main thread is done, this just bounds them
+pub const MAX_DEPTH: usize = 64;
+//nodes a thread counts before adding them to the shared count
+const NODE_FLUSH: u64 = 1024;
+//quiescence stands pat this deep even with captures left
+const MAX_PLY: usize = 128;
+
+#[derive(Clone, Copy)]
+pub struct SearchConfig {
+    pub depth: usize,
+    pub threads: usize,
+    //stop once all threads together searched this many nodes
+    pub nodes: Option<u64>,
+    //number of root moves that get an exact score
+    pub multi_pv: usize,
+    //leaf evaluations are shifted by up to this many centipawns
+    pub eval_noise: i32,
+    //drives the eval noise and the skill pick, same seed same move
+    pub seed: u64,
+}
+
+impl Default for SearchConfig {
+    fn default() -> Self {
+        Self { depth: 4, threads: 1, nodes: None, multi_pv: 1, eval_noise: 0, seed: 0 }
+    }
+}
+
+#[derive(Clone)]
+pub struct SearchResult {
+    pub mv: Option<(u8, u8)>,
+    pub score: i32,
+    pub depth: usize,
+    pub nodes: u64,
+    //best root moves with their scores, best first, at most multi_pv long
+    pub lines: Vec<Line>,
+}
+
+#[derive(Clone)]
+pub struct Line {
+    pub mv: (u8, u8),
+    pub score: i32,
+    //the root move followed by the expected replies, taken from the transposition table
+    pub pv: Vec<(u8, u8)>,
+}
+
+struct Shared<'a> {
+    tt: &'a TranspositionTable,
+    tablebases: Option<&'static Tablebases>,
+    stop: &'a AtomicBool,
+    nodes: AtomicU64,
+    node_limit: Option<u64>,
+    multi_pv: usize,
+    eval_noise: i32,
+    //new noise for every search, so the same position is not misjudged the same way every time
+    noise_seed: u64,
+}
+
+struct Worker<'a> {
+    shared: &'a Shared<'a>,
+    nodes: u64,
+}
+
+//progress of the main thread: (sender, current depth, target depth)
+type Progress<'a> = Option<(&'a Sender<f32>, usize, usize)>;
+
+//Lazy SMP: all threads search the same position and only share the transposition table.
+//The main thread does iterative deepening up to the target depth, helpers run ahead/behind it
+//and fill the table with results the main thread can cut off with.
+pub fn search(board: BoardB, turn: Side, config: SearchConfig, tt: &TranspositionTable, progress: Option<&Sender<f32>>) -> SearchResult {
+    run(board, turn, config, tt, &AtomicBool::new(false), progress, &mut |_| {})
+}
+
+//Same search, but it can be stopped from outside and reports the result of every finished
+//iteration. stop is set once the search is done, so it can't be reused for the next one.
+pub fn analyse(board: BoardB, turn: Side, config: SearchConfig, tt: &TranspositionTable, stop: &AtomicBool, report: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+    run(board, turn, config, tt, stop, None, report)
+}
+
+fn run(board: BoardB, turn: Side, config: SearchConfig, tt: &TranspositionTable, stop: &AtomicBool, progress: Option<&Sender<f32>>, report: &mut dyn FnMut(&SearchResult)) -> SearchResult {
+    let tablebases = tablebase::get();
+    if let Some(tb) = tablebases && let Some((mv, score)) = tb.root_move(&board, turn) {
+        let result = SearchResult { mv: Some(mv), score, depth: 0, nodes: 0, lines: vec![Line { mv, score, pv: vec![mv] }] };
+        report(&result);
+        return result;
+    }
+    let shared = Shared {
+        tt,
+        tablebases,
+        stop,
+        nodes: AtomicU64::new(0),
+        node_limit: config.nodes,
+        multi_pv: config.multi_pv.max(1),
+        eval_noise: config.eval_noise,
+        noise_seed: config.seed,
+    };
+    let target_depth = config.depth.max(1);
+    let (lines, depth) = std::thread::scope(|s| {
+        for id in 1..config.threads.max(1) {
+            let shared = &shared;
+            s.spawn(move || {
+                let mut worker = Worker::new(shared);
+                //odd helpers start one ply deeper so not every thread works on the same iteration
+                let mut depth = 1 + id % 2;
+                while !worker.stopped() && depth <= MAX_DEPTH {
+                    worker.root(&board, turn, depth, None);
+                    depth += 1;
+                }
+                worker.flush_nodes();
+            });
+        }
+
+        let mut worker = Worker::new(&shared);
+        let mut best = (Vec::new(), 0);
+        for depth in 1..=target_depth {
+            let lines = worker.root(&board, turn, depth, progress.map(|p| (p, depth, target_depth)));
+            //an iteration cut short by the node limit still counts if it finished at least one move
+            if !lines.is_empty() || best.0.is_empty() {
+                best = (lines, depth);
+            }
+            if worker.stopped() {
+                break;
+            }
+            let nodes = shared.nodes.load(Ordering::Relaxed) + worker.nodes;
+            report(&shared.result(&board, turn, &best.0, depth, nodes));
+            //no need to look further if a forced mate was found
+            if best.0.first().is_some_and(|(_, score)| score.abs() >= MATE_BOUND) {
+                break;
+            }
+        }
+        shared.stop.store(true, Ordering::Relaxed);
+        worker.flush_nodes();
+        best
+    });
+    shared.result(&board, turn, &lines, depth, shared.nodes.load(Ordering::Relaxed))
+}
+
+//move the cpu plays and the reply it expects, to ponder on while the opponent thinks
+pub struct CpuMove {
+    pub mv: (u8, u8),
+    pub ponder: Option<(u8, u8)>,
+    //score of the move from the cpu's view and the depth it was searched to
+    pub eval: Option<(i32, usize)>,
+}
+
+//Cpu search running on its own thread. Dropping the handle stops the search, the thread then
+//ends on its own within a few nodes.
+pub struct SearchHandle {
+    //game generation the search was started for, its move is stale once the game moved on
+    pub generation: u64,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<Option<CpuMove>>>,
+}
+
+impl SearchHandle {
+    //runs the search on its own thread, it has to return soon after stop is set
+    pub fn spawn<F>(generation: u64, search: F) -> Self where F: FnOnce(&AtomicBool) -> Option<CpuMove> + Send + 'static {
+        let stop = Arc::new(AtomicBool::new(false));
+        let search_stop = stop.clone();
+        let thread = std::thread::spawn(move || search(&search_stop));
+        Self { generation, stop, thread: Some(thread) }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.as_ref().is_none_or(|t| t.is_finished())
+    }
+
+    //None if the search was stopped before it found a move
+    pub fn join(mut self) -> Option<CpuMove> {
+        self.thread.take()?.join().expect("Search thread panicked")
+    }
+
+    //stops the search and waits for the thread, for a clean shutdown
+    pub fn cancel(mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for SearchHandle {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
+
+pub fn spawn_move_computer(board: BoardB, turn: Side, config: SearchConfig, skill: Option<Skill>, tt: Arc<TranspositionTable>, progress_sender: Option<Sender<f32>>, generation: u64) -> SearchHandle {
+    SearchHandle::spawn(generation, move |stop| {
+        let config = skill.map_or(config, |skill| skill.apply(config));
+        let result = run(board, turn, config, &tt, stop, progress_sender.as_ref(), &mut |_| {});
+        let mut mv = result.mv?;
+        eprintln!("best: {} -> {} val: {} depth: {} nodes: {}", Pos::from_i(mv.0), Pos::from_i(mv.1), result.score, result.depth, result.nodes);
+        if let Some(skill) = skill {
+            mv = skill.pick(&result.lines, &mut StdRng::seed_from_u64(config.seed));
+            if Some(mv) != result.mv {
+                eprintln!("skill: playing {} -> {} instead", Pos::from_i(mv.0), Pos::from_i(mv.1));
+            }
+        }
+        let line = result.lines.iter().find(|line| line.mv == mv);
+        let ponder = line.and_then(|line| line.pv.get(1).copied());
+        let score = line.map_or(result.score, |line| line.score);
+        Some(CpuMove { mv, ponder, eval: Some((score, result.depth)) })
+    })
+}
+
+//Search of the position after the expected reply, started right after the cpu moved. If the
+//opponent plays that reply the search just goes on and becomes the next cpu move.
+pub struct Ponder {
+    //position the search is for
+    key: u64,
+    search: SearchHandle,
+}
+
+impl Ponder {
+    //board and turn are the position the reply is played in
+    pub fn start(board: BoardB, turn: Side, reply: (u8, u8), config: SearchConfig, skill: Option<Skill>, tt: Arc<TranspositionTable>) -> Option<Self> {
+        if !legal_moves(&board, turn).contains(&reply) {
+            return None;
+        }
+        let mut board = board;
+        board.make_move(reply.0, reply.1);
+        //the generation is only known once the reply is actually played
+        let search = spawn_move_computer(board, !turn, config, skill, tt, None, 0);
+        Some(Self { key: board.hash(!turn), search })
+    }
+
+    //the running search if the opponent played the expected reply, otherwise it is stopped
+    pub fn resolve(self, board: &BoardB, turn: Side, generation: u64) -> Option<SearchHandle> {
+        if board.hash(turn) == self.key {
+            println!("ponderhit");
+            let mut search = self.search;
+            search.generation = generation;
+            Some(search)
+        } else {
+            None
+        }
+    }
+
+    pub fn cancel(self) {
+        self.search.cancel();
+    }
+}
+
+impl Shared<'_> {
+    fn result(&self, board: &BoardB, turn: Side, lines: &[((u8, u8), i32)], depth: usize, nodes: u64) -> SearchResult {
+        let lines: Vec<Line> = lines.iter()
+            .take(self.multi_pv)
+            .map(|(mv, score)| Line { mv: *mv, score: *score, pv: principal_variation(self.tt, board, turn, *mv, depth) })
+            .collect();
+        let (mv, score) = match lines.first() {
+            Some(line) => (Some(line.mv), line.score),
+            None => (None, if in_check(board, turn) {-MATE} else {0}),
+        };
+        SearchResult { mv, score, depth, nodes, lines }
+    }
+}
+
+//Follows the table moves after the root move. Entries can be overwritten or belong to another
+//position with the same index, so every move is checked and the line ends at the first gap.
+fn principal_variation(tt: &TranspositionTable, board: &BoardB, turn: Side, mv: (u8, u8), max_len: usize) -> Vec<(u8, u8)> {
+    let mut pv = vec![mv];
+    let mut board = *board;
+    board.make_move(mv.0, mv.1);
+    let mut turn = !turn;
+    let mut seen = vec![board.hash(turn)];
+    while pv.len() < max_len {
+        let Some(mv) = tt.probe(board.hash(turn)).and_then(|e| e.mv) else {
+            break;
+        };
+        if !legal_moves(&board, turn).contains(&mv) {
+            break;
+        }
+        board.make_move(mv.0, mv.1);
+        turn = !turn;
+        //repetitions would loop forever
+        let key = board.hash(turn);
+        if seen.contains(&key) {
+            break;
+        }
+        seen.push(key);
+        pv.push(mv);
+    }
+    pv
+}
+
+impl<'a> Worker<'a> {
+    fn new(shared: &'a Shared<'a>) -> Self {
+        Self { shared, nodes: 0 }
+    }
+
+    //same position, same noise within one search, so the table stays consistent
+    fn noise(&self, board: &BoardB, turn: Side) -> i32 {
+        if self.shared.eval_noise == 0 {
+            return 0;
+        }
+        let mut x = board.hash(turn) ^ self.shared.noise_seed;
+        //splitmix64 finalizer
+        x = (x ^ (x >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+        x = (x ^ (x >> 27)).wrapping_mul(0x94d049bb133111eb);
+        x ^= x >> 31;
+        let range = 2 * self.shared.eval_noise as u64 + 1;
+        (x % range) as i32 - self.shared.eval_noise
+    }
+
+    fn stopped(&self) -> bool {
+        self.shared.stop.load(Ordering::Relaxed)
+    }
+
+    fn flush_nodes(&mut self) {
+        self.shared.nodes.fetch_add(self.nodes, Ordering::Relaxed);
+        self.nodes = 0;
+    }
+
+    //stops the search once all threads together reached the node limit. Small limits are
+    //checked more often, they would be overshot by a whole flush interval otherwise.
+    fn count_node(&mut self) {
+        self.nodes += 1;
+        if let Some(limit) = self.shared.node_limit && self.nodes >= NODE_FLUSH.min(limit.max(1)) {
+            self.flush_nodes();
+            if self.shared.nodes.load(Ordering::Relaxed) >= limit {
+                self.shared.stop.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    //scores of all root moves that were searched, best first. Only the first multi_pv are exact,
+    //the others are upper bounds.
+    fn root(&mut self, board: &BoardB, turn: Side, depth: usize, progress: Progress) -> Vec<((u8, u8), i32)> {
+        let key = board.hash(turn);
+        let tt_move = self.shared.tt.probe(key).and_then(|e| e.mv);
+        let mvs = ordered_moves(board, turn, tt_move);
+        let total = mvs.len();
+        let mut lines: Vec<((u8, u8), i32)> = Vec::with_capacity(total);
+        for (n, (from, to)) in mvs.into_iter().enumerate() {
+            //a move has to beat the last of the lines we keep to be interesting
+            let alpha = match lines.get(self.shared.multi_pv - 1) {
+                Some((_, score)) => *score,
+                None => -INFINITY,
+            };
+            let mut b = *board;
+            b.make_move(from, to);
+            let score = -self.negamax(&b, !turn, depth - 1, 1, -INFINITY, -alpha);
+            if self.stopped() {
+                break;
+            }
+            //stable, so earlier moves win ties
+            lines.push(((from, to), score));
+            lines.sort_by_key(|(_, score)| -score);
+            if let Some((sender, depth, target_depth)) = progress {
+                let done = (depth - 1) as f32 + (n + 1) as f32 / total as f32;
+                let _ = sender.send(done / target_depth as f32);
+            }
+        }
+        if !self.stopped() && let Some((mv, score)) = lines.first() {
+            self.shared.tt.store(key, TTEntry { mv: Some(*mv), score: *score, depth: depth as u8, bound: Bound::Exact });
+        }
+        lines
+    }
+
+    fn negamax(&mut self, board: &BoardB, turn: Side, depth: usize, ply: usize, mut alpha: i32, beta: i32) -> i32 {
+        self.count_node();
+        if self.stopped() {
+            return 0;
+        }
+        if depth == 0 {
+            return self.quiescence(board, turn, ply, alpha, beta, true);
+        }
+
+        let key = board.hash(turn);
+        let mut tt_move = None;
+        if let Some(entry) = self.shared.tt.probe(key) {
+            tt_move = entry.mv;
+            if entry.depth as usize >= depth {
+                let score = score_from_tt(entry.score, ply);
+                match entry.bound {
+                    Bound::Exact => return score,
+                    Bound::Lower if score >= beta => return score,
+                    Bound::Upper if score <= alpha => return score,
+                    _ => {}
+                }
+            }
+        }
+
+        if let Some(tb) = self.shared.tablebases && let Some(score) = tb.probe_score(board, turn, ply) {
+            return score;
+        }
+
+        let mvs = ordered_moves(board, turn, tt_move);
+        if mvs.is_empty() {
+            return if in_check(board, turn) {-MATE + ply as i32} else {0};
+        }
+
+        let alpha_orig = alpha;
+        let mut best = (None, -INFINITY);
+        for (from, to) in mvs {
+            let mut b = *board;
+            b.make_move(from, to);
+            let score = -self.negamax(&b, !turn, depth - 1, ply + 1, -beta, -alpha);
+            if score > best.1 {
+                best = (Some((from, to)), score);
+            }
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        //results of an aborted search are garbage, keep them out of the table
+        if self.stopped() {
+            return 0;
+        }
+
+        let bound = if best.1 <= alpha_orig {
+            Bound::Upper
+        } else if best.1 >= beta {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        self.shared.tt.store(key, TTEntry { mv: best.0, score: score_to_tt(best.1, ply), depth: depth as u8, bound });
+        best.1
+    }
+
+    //Captures and promotions until the position is quiet, so the evaluation never sees a piece
+    //hanging. Losing captures by SEE are skipped, in check every evasion is searched. Quiet checks
+    //are only tried right after the main search, deeper they would keep the search from ending.
+    fn quiescence(&mut self, board: &BoardB, turn: Side, ply: usize, mut alpha: i32, beta: i32, checks: bool) -> i32 {
+        self.count_node();
+        if self.stopped() {
+            return 0;
+        }
+        let mut mvs = MoveList::new();
+        let in_check = board.checkers(turn) > 0;
+        let mut best = -INFINITY;
+        if in_check {
+            board.evasions(turn, &mut mvs);
+            if mvs.is_empty() {
+                return -MATE + ply as i32;
+            }
+        } else {
+            best = board.evaluate(turn) + self.noise(board, turn);
+            if best >= beta || ply >= MAX_PLY {
+                return best;
+            }
+            alpha = alpha.max(best);
+            board.captures(turn, &mut mvs);
+        }
+        mvs.as_mut_slice().sort_unstable_by_key(|mv| capture_order(board, *mv));
+        if checks && !in_check {
+            let mut quiet = MoveList::new();
+            board.quiet_checks(turn, &mut quiet);
+            for &mv in quiet.as_slice() {
+                mvs.push(mv);
+            }
+        }
+        for &(from, to) in mvs.as_slice() {
+            if !in_check && board.occupied(to) && board.see(from, to) < 0 {
+                continue;
+            }
+            let mut b = *board;
+            b.make_move(from, to);
+            let score = -self.quiescence(&b, !turn, ply + 1, -beta, -alpha, false);
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        best
+    }
+}
+
+//moves until mate, negative if the side to move gets mated
+pub fn mate_in(score: i32) -> Option<i32> {
+    if score >= MATE_BOUND {
+        Some((MATE - score + 1) / 2)
+    } else if score <= -MATE_BOUND {
+        Some(-(MATE + score + 1) / 2)
+    } else {
+        None
+    }
+}
+
+//mate or tablebase result
+pub fn is_decisive(score: i32) -> bool {
+    score.abs() >= TB_WIN - 1000
+}
+
+pub fn legal_moves(board: &BoardB, turn: Side) -> Vec<(u8, u8)> {
+    let mut next_moves_by_piece = HashMap::with_capacity(16);
+    board.valid_moves(turn, &mut next_moves_by_piece);
+    let mut mvs: Vec<(u8, u8)> = next_moves_by_piece.iter().flat_map(|(from, tos)| tos.ones().into_iter().map(|to| (*from, to))).collect();
+    //the map has no fixed order, the search would depend on it
+    mvs.sort_unstable();
+    mvs
+}
+
+//table move first, then captures by most valuable victim / least valuable attacker
+fn ordered_moves(board: &BoardB, turn: Side, tt_move: Option<(u8, u8)>) -> Vec<(u8, u8)> {
+    let mut mvs = legal_moves(board, turn);
+    mvs.sort_by_cached_key(|mv| {
+        if Some(*mv) == tt_move {
+            return i32::MIN;
+        }
+        capture_order(board, *mv)
+    });
+    mvs
+}
+
+//most valuable victim / least valuable attacker, smaller comes first
+fn capture_order(board: &BoardB, (from, to): (u8, u8)) -> i32 {
+    match (board.get_piece_at_pos(to), board.get_piece_at_pos(from)) {
+        (Some(victim), Some(attacker)) => -(victim.value() * 10 - attacker.value()),
+        _ => 0,
+    }
+}
+
+pub fn in_check(board: &BoardB, turn: Side) -> bool {
+    board.checkers(turn) > 0
+}
+
+//mate scores are stored relative to the node, not the root
+fn score_to_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score + ply as i32
+    } else if score <= -MATE_BOUND {
+        score - ply as i32
+    } else {
+        score
+    }
+}
+
+fn score_from_tt(score: i32, ply: usize) -> i32 {
+    if score >= MATE_BOUND {
+        score - ply as i32
+    } else if score <= -MATE_BOUND {
+        score + ply as i32
+    } else {
+        score
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+
+
+    #[test]
+    fn small_node_limits() {
+        let tt = TranspositionTable::new(1);
+        for limit in [64, 512] {
+            let config = SearchConfig { depth: 8, nodes: Some(limit), ..SearchConfig::default() };
+            let result = search(BoardB::with_starting_pos(), Side::White, config, &tt, None);
+            assert!(result.mv.is_some());
+            assert!(result.nodes < limit + 200, "{} nodes for a limit of {}", result.nodes, limit);
+        }
+    }
+    //every map has its own hash seed, the order may not come from it
+    #[test]
+    fn same_position_same_search() {
+        let (board, turn) = BoardB::from_fen("r1bqkbnr/pppp1ppp/2n5/4p3/4P3/5N2/PPPP1PPP/RNBQKB1R w KQkq - 2 3").unwrap();
+        let mvs = legal_moves(&board, turn);
+        for _ in 0..10 {
+            assert_eq!(legal_moves(&board, turn), mvs);
+        }
+        let config = SearchConfig { depth: 3, eval_noise: 20, seed: 7, ..SearchConfig::default() };
+        let results: Vec<_> = (0..3).map(|_| {
+            let result = search(board, turn, config, &TranspositionTable::new(1), None);
+            (result.mv, result.score, result.nodes)
+        }).collect();
+        assert!(results.iter().all(|r| *r == results[0]));
+    }
+
+    //at depth one the pawn on d5 only looks free without the quiescence search
+    #[test]
+    fn quiescence_sees_recaptures() {
+        use crate::san::square_from_str;
+        let (board, turn) = BoardB::from_fen("4k3/8/2p5/3p4/8/8/3Q4/4K3 w - - 0 1").unwrap();
+        let config = SearchConfig { depth: 1, ..SearchConfig::default() };
+        let result = search(board, turn, config, &TranspositionTable::new(1), None);
+        let capture = (square_from_str("d2").unwrap(), square_from_str("d5").unwrap());
+        assert_ne!(result.mv, Some(capture));
+    }
+}
@@ -0,0 +1,26 @@
+use std::sync::OnceLock;
+
+use rand::{rngs::StdRng, SeedableRng};
+
+
+
+//Every random choice comes from one seed: the cpu color, book moves, skill noise and picks and
+//random openings. The same seed and start position repeat a game move for move, as long as the
+//search runs single threaded.
+static SEED: OnceLock<u64> = OnceLock::new();
+
+pub fn init(seed: u64) {
+    if SEED.set(seed).is_err() {
+        panic!("Seed initialized twice");
+    }
+}
+
+//a random one unless init came first
+pub fn get() -> u64 {
+    *SEED.get_or_init(rand::random)
+}
+
+//independent generator for one part of the run, like one game of many running in parallel
+pub fn rng(stream: u64) -> StdRng {
+    StdRng::seed_from_u64(get() ^ stream.wrapping_mul(0x9e3779b97f4a7c15))
+}
@@ -0,0 +1,135 @@
+use rand::Rng;
+
+use crate::search::{self, SearchConfig, Line};
+
+
+
+pub const MAX_LEVEL: u8 = 20;
+
+//Playing strength between the fixed depths. Level 20 is full strength, lower levels search fewer
+//nodes, misjudge positions by random noise and now and then play a worse candidate move.
+#[derive(Clone, Copy, Debug)]
+pub struct Skill {
+    pub level: u8,
+}
+
+impl Skill {
+    pub fn new(level: u8) -> Self {
+        Self { level: level.min(MAX_LEVEL) }
+    }
+
+    //rough mapping, 800 is level 0 and every 60 points add a level
+    pub fn from_elo(elo: u32) -> Self {
+        Self::new((elo.saturating_sub(800) / 60).min(MAX_LEVEL as u32) as u8)
+    }
+
+    fn weakness(&self) -> i32 {
+        (MAX_LEVEL - self.level) as i32
+    }
+
+    pub fn apply(&self, config: SearchConfig) -> SearchConfig {
+        if self.level == MAX_LEVEL {
+            return config;
+        }
+        //64 nodes at level 0, doubling every second level
+        let nodes = 64 << (self.level / 2);
+        SearchConfig {
+            nodes: Some(config.nodes.map_or(nodes, |n| n.min(nodes))),
+            multi_pv: config.multi_pv.max(4),
+            eval_noise: self.weakness() * 8,
+            ..config
+        }
+    }
+
+    //Humans rarely miss the best move by a lot: worse candidates get exponentially less likely
+    //and anything that loses more than a level dependent margin is never played
+    pub fn pick<R: Rng>(&self, lines: &[Line], rng: &mut R) -> (u8, u8) {
+        let first = lines.first().expect("Picked from an empty candidate list");
+        let (best_mv, best) = (first.mv, first.score);
+        //found mates are never given away
+        if self.level == MAX_LEVEL || search::is_decisive(best) {
+            return best_mv;
+        }
+        let temperature = self.weakness() as f64 * 6.0;
+        let max_loss = self.weakness() * 20;
+        let candidates: Vec<((u8, u8), f64)> = lines.iter()
+            .filter(|line| best - line.score <= max_loss)
+            .map(|line| (line.mv, (-(best - line.score) as f64 / temperature).exp()))
+            .collect();
+        let mut choice = rng.gen::<f64>() * candidates.iter().map(|(_, w)| w).sum::<f64>();
+        for (mv, weight) in &candidates {
+            if choice < *weight {
+                return *mv;
+            }
+            choice -= weight;
+        }
+        best_mv
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::search::MATE;
+    use super::*;
+
+
+
+    fn lines(scores: &[i32]) -> Vec<Line> {
+        scores.iter().enumerate().map(|(n, score)| Line { mv: (n as u8, n as u8 + 8), score: *score, pv: Vec::new() }).collect()
+    }
+
+    fn score_of(lines: &[Line], mv: (u8, u8)) -> i32 {
+        lines.iter().find(|line| line.mv == mv).unwrap().score
+    }
+
+    #[test]
+    fn never_loses_more_than_the_margin() {
+        let lines = lines(&[120, 110, 80, 30, -100, -400, -900]);
+        for level in 0..MAX_LEVEL {
+            let skill = Skill::new(level);
+            let max_loss = skill.weakness() * 20;
+            let mut worst = 120;
+            for seed in 0..500 {
+                worst = worst.min(score_of(&lines, skill.pick(&lines, &mut StdRng::seed_from_u64(seed))));
+            }
+            assert!(120 - worst <= max_loss, "level {} played {}", level, worst);
+        }
+        //the weakest level does play worse moves, not only the best one
+        let picks: Vec<(u8, u8)> = (0..500).map(|seed| Skill::new(0).pick(&lines, &mut StdRng::seed_from_u64(seed))).collect();
+        assert!(picks.iter().any(|mv| *mv != lines[0].mv));
+    }
+
+    #[test]
+    fn plays_found_mates() {
+        let lines = lines(&[MATE - 3, 500, 490, 480]);
+        for seed in 0..200 {
+            assert_eq!(Skill::new(0).pick(&lines, &mut StdRng::seed_from_u64(seed)), lines[0].mv);
+        }
+    }
+
+    #[test]
+    fn same_seed_same_move() {
+        let lines = lines(&[50, 45, 40, 35, 30, 25]);
+        for seed in 0..100 {
+            let skill = Skill::new(3);
+            let first = skill.pick(&lines, &mut StdRng::seed_from_u64(seed));
+            assert_eq!(skill.pick(&lines, &mut StdRng::seed_from_u64(seed)), first);
+        }
+    }
+
+    #[test]
+    fn node_cap() {
+        let config = SearchConfig::default();
+        assert_eq!(Skill::new(0).apply(config).nodes, Some(64));
+        assert_eq!(Skill::new(5).apply(config).nodes, Some(256));
+        assert_eq!(Skill::new(19).apply(config).nodes, Some(64 << 9));
+        //a lower limit of the caller stays, full strength changes nothing
+        assert_eq!(Skill::new(5).apply(SearchConfig { nodes: Some(100), ..config }).nodes, Some(100));
+        assert_eq!(Skill::new(5).apply(SearchConfig { nodes: Some(1000), ..config }).nodes, Some(256));
+        assert_eq!(Skill::new(MAX_LEVEL).apply(config).nodes, None);
+        assert_eq!(Skill::new(0).apply(config).multi_pv, 4);
+    }
+}
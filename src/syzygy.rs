@@ -0,0 +1,1050 @@
+use std::{collections::HashMap, fs::File, io::Read, path::{Path, PathBuf}, sync::OnceLock};
+
+use crate::{boardb::{BoardB, Piece}, pieces::{Side, PieceType}, search, tablebase::{self, TablebaseError, Wdl}};
+
+
+
+//Syzygy tables, .rtbw for win/draw/loss and .rtbz for the distance to the next capture or pawn move.
+//The format and the way positions are indexed follow the probing code of the generator
+//(https://github.com/syzygy1/tb) and Stockfish's tbprobe.cpp. Squares in this file are counted
+//from a1 like in the tables, a board index becomes one with ^ 56.
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+//flags of a file
+const SPLIT: u8 = 1;
+const HAS_PAWNS: u8 = 2;
+
+//flags of a sub table
+const STM: u8 = 1;
+const MAPPED: u8 = 2;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const WIDE: u8 = 16;
+const SINGLE_VALUE: u8 = 128;
+
+const MAX_PIECES: usize = 7;
+
+fn file(s: usize) -> usize {
+    s & 7
+}
+
+fn rank(s: usize) -> usize {
+    s >> 3
+}
+
+//negative below the a1-h8 diagonal
+fn off_diagonal(s: usize) -> i32 {
+    rank(s) as i32 - file(s) as i32
+}
+
+//lookup tables of the position index, built once
+struct Maps {
+    //squares below the a1-h8 diagonal to 0..28
+    b1h1h7: [u64; 64],
+    //the a1-d1-d4 triangle to 0..10, the diagonal last
+    a1d1d4: [u64; 64],
+    //the 462 legal placements of two kings with the first one in the triangle
+    kk: [[u64; 64]; 10],
+    //binomial[k][n] ways to pick k of n squares
+    binomial: [[u64; 64]; MAX_PIECES - 1],
+    //a2-h7 to 0..48, the leading pawn is the one with the highest value
+    pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; MAX_PIECES - 1],
+    lead_pawns_size: [[u64; 4]; MAX_PIECES - 1],
+}
+
+fn maps() -> &'static Maps {
+    static MAPS: OnceLock<Maps> = OnceLock::new();
+    MAPS.get_or_init(|| {
+        let mut m = Maps {
+            b1h1h7: [0; 64],
+            a1d1d4: [0; 64],
+            kk: [[0; 64]; 10],
+            binomial: [[0; 64]; MAX_PIECES - 1],
+            pawns: [0; 64],
+            lead_pawn_idx: [[0; 64]; MAX_PIECES - 1],
+            lead_pawns_size: [[0; 4]; MAX_PIECES - 1],
+        };
+        for (code, s) in (0..64).filter(|s| off_diagonal(*s) < 0).enumerate() {
+            m.b1h1h7[s] = code as u64;
+        }
+
+        //a1 to d4
+        let mut code = 0;
+        let mut diagonal = Vec::new();
+        for s in (0..28).filter(|s| file(*s) <= 3) {
+            if off_diagonal(s) < 0 {
+                m.a1d1d4[s] = code;
+                code += 1;
+            } else if off_diagonal(s) == 0 {
+                diagonal.push(s);
+            }
+        }
+        for s in diagonal {
+            m.a1d1d4[s] = code;
+            code += 1;
+        }
+
+        let mut code = 0;
+        let mut both_on_diagonal = Vec::new();
+        for idx in 0..10 {
+            //b1 is the only square mapped to 0
+            for s1 in (0..28).filter(|s| m.a1d1d4[*s] == idx as u64 && (idx > 0 || *s == 1)) {
+                for s2 in 0..64 {
+                    let touching = file(s1).abs_diff(file(s2)) <= 1 && rank(s1).abs_diff(rank(s2)) <= 1;
+                    if touching || off_diagonal(s1) == 0 && off_diagonal(s2) > 0 {
+                        continue;
+                    }
+                    if off_diagonal(s1) == 0 && off_diagonal(s2) == 0 {
+                        both_on_diagonal.push((idx, s2));
+                    } else {
+                        m.kk[idx][s2] = code;
+                        code += 1;
+                    }
+                }
+            }
+        }
+        for (idx, s2) in both_on_diagonal {
+            m.kk[idx][s2] = code;
+            code += 1;
+        }
+
+        m.binomial[0][0] = 1;
+        for n in 1..64 {
+            for k in 0..(MAX_PIECES - 1).min(n + 1) {
+                m.binomial[k][n] = if k > 0 {m.binomial[k - 1][n - 1]} else {0} + if k < n {m.binomial[k][n - 1]} else {0};
+            }
+        }
+
+        //every rank the leading pawn goes up takes away the two squares below on the edge files
+        let mut available = 48;
+        for lead_count in 1..MAX_PIECES - 1 {
+            for f in 0..4 {
+                let mut idx = 0;
+                for r in 1..7 {
+                    let s = f + r * 8;
+                    if lead_count == 1 {
+                        available -= 1;
+                        m.pawns[s] = available;
+                        available -= 1;
+                        m.pawns[s ^ 7] = available;
+                    }
+                    m.lead_pawn_idx[lead_count][s] = idx;
+                    idx += m.binomial[lead_count - 1][m.pawns[s] as usize];
+                }
+                m.lead_pawns_size[lead_count][f] = idx;
+            }
+        }
+        m
+    })
+}
+
+fn piece_code(piece: Piece) -> u8 {
+    let code = match piece.ty {
+        PieceType::Pawn => 1,
+        PieceType::Knight => 2,
+        PieceType::Bishop => 3,
+        PieceType::Rook => 4,
+        PieceType::Queen => 5,
+        PieceType::King => 6,
+    };
+    if piece.side == Side::Black {code + 8} else {code}
+}
+
+fn le16(bytes: &[u8], at: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(bytes.get(at..at + 2)?.try_into().ok()?))
+}
+
+fn le32(bytes: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(bytes.get(at..at + 4)?.try_into().ok()?))
+}
+
+//the bit reader runs over the end of the last block, missing bytes are never part of a symbol
+fn be32(bytes: &[u8], at: usize) -> u32 {
+    (0..4).fold(0, |n, i| n << 8 | *bytes.get(at + i).unwrap_or(&0) as u32)
+}
+
+//one compressed table of a file: for one side to move and, with pawns, one file of the leading pawn
+#[derive(Clone, Default)]
+struct PairsData {
+    flags: u8,
+    pieces: [u8; MAX_PIECES],
+    //pieces that are indexed together, 0 terminated
+    group_len: [usize; MAX_PIECES + 1],
+    //factor of each group in the index, the entry after the last group is the table size
+    group_idx: [u64; MAX_PIECES + 1],
+    block_size: usize,
+    //values between two entries of the sparse index
+    span: u64,
+    sparse_index_size: usize,
+    num_blocks: usize,
+    block_length_size: usize,
+    min_sym_len: u8,
+    //first symbol of every code length, longer codes have lower symbols
+    lowest_sym: Vec<u16>,
+    //lowest code of every length, left aligned
+    base64: Vec<u64>,
+    //values a symbol expands to, minus one
+    symlen: Vec<u32>,
+    //offsets into the file
+    btree: usize,
+    sparse_index: usize,
+    block_length: usize,
+    data: usize,
+    //dtz value maps for wins, losses, cursed wins and blessed losses
+    map_idx: [usize; 4],
+}
+
+impl PairsData {
+    //symbols are pairs of other symbols, or a value if the right one is 0xFFF
+    fn pair(&self, bytes: &[u8], sym: usize) -> Option<(usize, usize)> {
+        let lr = bytes.get(self.btree + 3 * sym..self.btree + 3 * sym + 3)?;
+        Some(((lr[1] as usize & 0xF) << 8 | lr[0] as usize, (lr[2] as usize) << 4 | (lr[1] as usize) >> 4))
+    }
+
+    fn symbol_len(&mut self, bytes: &[u8], sym: usize, visited: &mut [bool]) -> Option<u32> {
+        visited[sym] = true;
+        let (left, right) = self.pair(bytes, sym)?;
+        if right == 0xFFF {
+            return Some(0);
+        }
+        for s in [left, right] {
+            if !*visited.get(s)? {
+                self.symlen[s] = self.symbol_len(bytes, s, visited)?;
+            }
+        }
+        Some(self.symlen[left] + self.symlen[right] + 1)
+    }
+
+    //reads the huffman code and the pairs, returns the offset after them
+    fn set_sizes(&mut self, bytes: &[u8], mut at: usize) -> Option<usize> {
+        self.flags = *bytes.get(at)?;
+        if self.flags & SINGLE_VALUE != 0 {
+            //the value is stored in place of the symbol length
+            self.min_sym_len = *bytes.get(at + 1)?;
+            return Some(at + 2);
+        }
+        let table_size = self.group_idx[self.group_len.iter().position(|len| *len == 0)?];
+        let header = bytes.get(at + 1..at + 10)?;
+        if header[0] > 30 || header[1] > 40 {
+            return None;
+        }
+        self.block_size = 1 << header[0];
+        self.span = 1 << header[1];
+        self.sparse_index_size = table_size.div_ceil(self.span) as usize;
+        //padding keeps the sparse index from pointing past the block lengths
+        let padding = header[2] as usize;
+        self.num_blocks = le32(header, 3)? as usize;
+        self.block_length_size = self.num_blocks + padding;
+        let (max_sym_len, min_sym_len) = (header[7], header[8]);
+        if min_sym_len == 0 || min_sym_len > max_sym_len || max_sym_len > 32 {
+            return None;
+        }
+        self.min_sym_len = min_sym_len;
+        at += 10;
+
+        let lengths = (max_sym_len - min_sym_len + 1) as usize;
+        self.lowest_sym = (0..lengths).map(|i| le16(bytes, at + 2 * i)).collect::<Option<_>>()?;
+        self.base64 = vec![0; lengths];
+        for i in (0..lengths - 1).rev() {
+            self.base64[i] = self.base64[i + 1].wrapping_add(self.lowest_sym[i] as u64).wrapping_sub(self.lowest_sym[i + 1] as u64) / 2;
+        }
+        for (i, base) in self.base64.iter_mut().enumerate() {
+            *base <<= 64 - i - min_sym_len as usize;
+        }
+        at += 2 * lengths;
+
+        let symbols = le16(bytes, at)? as usize;
+        self.btree = at + 2;
+        self.symlen = vec![0; symbols];
+        let mut visited = vec![false; symbols];
+        for sym in 0..symbols {
+            if !visited[sym] {
+                self.symlen[sym] = self.symbol_len(bytes, sym, &mut visited)?;
+            }
+        }
+        Some(self.btree + 3 * symbols + (symbols & 1))
+    }
+
+    //value at idx: find the block with the sparse index and the block lengths, then decode
+    //symbols until the one that covers idx and walk down its pairs
+    fn decompress(&self, bytes: &[u8], idx: u64) -> Option<u16> {
+        if self.flags & SINGLE_VALUE != 0 {
+            return Some(self.min_sym_len as u16);
+        }
+        let k = (idx / self.span) as usize;
+        if k >= self.sparse_index_size {
+            return None;
+        }
+        //the entry points at value k * span + span / 2
+        let mut block = le32(bytes, self.sparse_index + 6 * k)? as usize;
+        let mut offset = le16(bytes, self.sparse_index + 6 * k + 4)? as i64 + (idx % self.span) as i64 - (self.span / 2) as i64;
+        let block_length = |block: usize| {
+            (block < self.block_length_size).then(|| le16(bytes, self.block_length + 2 * block).map(|len| len as i64)).flatten()
+        };
+        while offset < 0 {
+            block = block.checked_sub(1)?;
+            offset += block_length(block)? + 1;
+        }
+        while offset > block_length(block)? {
+            offset -= block_length(block)? + 1;
+            block += 1;
+        }
+
+        let mut at = self.data + block * self.block_size;
+        let mut buf = (be32(bytes, at) as u64) << 32 | be32(bytes, at + 4) as u64;
+        at += 8;
+        let mut buf_size = 64;
+        let mut sym;
+        loop {
+            let mut len = 0;
+            while buf < self.base64[len] {
+                len += 1;
+            }
+            sym = ((buf - self.base64[len]) >> (64 - len - self.min_sym_len as usize)) as usize + self.lowest_sym[len] as usize;
+            let values = *self.symlen.get(sym)? as i64 + 1;
+            if offset < values {
+                break;
+            }
+            offset -= values;
+            let len = len + self.min_sym_len as usize;
+            buf <<= len;
+            buf_size -= len;
+            if buf_size <= 32 {
+                buf_size += 32;
+                buf |= (be32(bytes, at) as u64) << (64 - buf_size);
+                at += 4;
+            }
+        }
+        while self.symlen[sym] != 0 {
+            let (left, right) = self.pair(bytes, sym)?;
+            let left_values = *self.symlen.get(left)? as i64 + 1;
+            if offset < left_values {
+                sym = left;
+            } else {
+                offset -= left_values;
+                sym = right;
+            }
+            if sym >= self.symlen.len() {
+                return None;
+            }
+        }
+        self.pair(bytes, sym).map(|(value, _)| value as u16)
+    }
+}
+
+enum Probe {
+    Value(i32),
+    //dtz tables only store one side to move
+    ChangeStm,
+}
+
+//a parsed .rtbw or .rtbz file
+struct Table {
+    name: String,
+    dtz: bool,
+    //same pieces on both sides, only white to move is stored
+    symmetric: bool,
+    has_pawns: bool,
+    //a piece other than a king that only one side has one of, the first three pieces are indexed together
+    has_unique_pieces: bool,
+    piece_count: usize,
+    //pawns of the leading side, the one with fewer pawns, and of the other side
+    pawn_count: [usize; 2],
+    //sub tables by side to move and file
+    items: Vec<Vec<PairsData>>,
+    map: usize,
+    bytes: Vec<u8>,
+}
+
+impl Table {
+    fn parse(name: &str, bytes: Vec<u8>, dtz: bool) -> Option<Self> {
+        let (white, black) = name.split_once('v')?;
+        let mut codes = Vec::new();
+        for (letters, side) in [(white, 0), (black, 8)] {
+            for c in letters.chars() {
+                codes.push(side + " PNBRQK".find(c).filter(|code| *code > 0)? as u8);
+            }
+        }
+        codes.sort_unstable();
+        let count = |letters: &str, c: char| letters.chars().filter(|l| *l == c).count();
+        let piece_count = codes.len();
+        if !(3..=MAX_PIECES).contains(&piece_count) || count(white, 'K') != 1 || count(black, 'K') != 1 {
+            return None;
+        }
+        let (white_pawns, black_pawns) = (count(white, 'P'), count(black, 'P'));
+        let white_leads = black_pawns == 0 || white_pawns > 0 && black_pawns >= white_pawns;
+        let mut table = Self {
+            name: name.to_owned(),
+            dtz,
+            symmetric: white == black,
+            has_pawns: white_pawns + black_pawns > 0,
+            has_unique_pieces: [white, black].iter().any(|letters| "PNBRQ".chars().any(|c| count(letters, c) == 1)),
+            piece_count,
+            pawn_count: if white_leads {[white_pawns, black_pawns]} else {[black_pawns, white_pawns]},
+            items: Vec::new(),
+            map: 0,
+            bytes: Vec::new(),
+        };
+
+        let flags = *bytes.get(4)?;
+        if (flags & HAS_PAWNS != 0) != table.has_pawns || (flags & SPLIT != 0) == table.symmetric {
+            return None;
+        }
+        let sides = if !dtz && !table.symmetric {2} else {1};
+        let files = if table.has_pawns {4} else {1};
+        let both_pawns = table.pawn_count[1] > 0;
+        table.items = vec![vec![PairsData::default(); files]; sides];
+        let mut at = 5;
+        for f in 0..files {
+            //group order of the leading group and of the other side's pawns, low nibble for white to move
+            let order = *bytes.get(at)?;
+            let pawn_order = if both_pawns {*bytes.get(at + 1)?} else {0xFF};
+            at += 1 + both_pawns as usize;
+            for k in 0..piece_count {
+                let b = *bytes.get(at + k)?;
+                for (i, side) in table.items.iter_mut().enumerate() {
+                    side[f].pieces[k] = if i == 0 {b & 0xF} else {b >> 4};
+                }
+            }
+            at += piece_count;
+            for i in 0..sides {
+                let shift = 4 * i;
+                let mut pieces = table.items[i][f].pieces[..piece_count].to_vec();
+                //pawn tables start with the leading pawns
+                let lead = pieces[0];
+                pieces.sort_unstable();
+                if pieces != codes || table.has_pawns && lead & 7 != 1 {
+                    return None;
+                }
+                let order = [order >> shift & 0xF, pawn_order >> shift & 0xF];
+                table.set_groups(i, f, order);
+            }
+        }
+        at += at & 1;
+        for f in 0..files {
+            for i in 0..sides {
+                at = table.items[i][f].set_sizes(&bytes, at)?;
+            }
+        }
+        if dtz {
+            at = table.set_dtz_map(&bytes, at)?;
+        }
+        for f in 0..files {
+            for d in table.items.iter_mut().map(|side| &mut side[f]) {
+                d.sparse_index = at;
+                at += 6 * d.sparse_index_size;
+            }
+        }
+        for f in 0..files {
+            for d in table.items.iter_mut().map(|side| &mut side[f]) {
+                d.block_length = at;
+                at += 2 * d.block_length_size;
+            }
+        }
+        for f in 0..files {
+            for d in table.items.iter_mut().map(|side| &mut side[f]) {
+                at = at.next_multiple_of(64);
+                d.data = at;
+                at += d.num_blocks * d.block_size;
+            }
+        }
+        if at > bytes.len() {
+            return None;
+        }
+        table.bytes = bytes;
+        Some(table)
+    }
+
+    //splits the pieces into groups and works out the factor of every group in the index
+    fn set_groups(&mut self, side: usize, f: usize, order: [u8; 2]) {
+        let m = maps();
+        let (has_pawns, unique, pawn_count, piece_count) = (self.has_pawns, self.has_unique_pieces, self.pawn_count, self.piece_count);
+        let d = &mut self.items[side][f];
+        //the leading pawns, the three unique pieces or the two kings
+        let mut first_len: i32 = if has_pawns {0} else if unique {3} else {2};
+        let mut n = 0;
+        d.group_len[0] = 1;
+        for i in 1..piece_count {
+            first_len -= 1;
+            if first_len > 0 || d.pieces[i] == d.pieces[i - 1] {
+                d.group_len[n] += 1;
+            } else {
+                n += 1;
+                d.group_len[n] = 1;
+            }
+        }
+        n += 1;
+        d.group_len[n] = 0;
+
+        //groups are multiplied in the stored order, the other groups of pieces follow in sequence
+        let both_pawns = has_pawns && pawn_count[1] > 0;
+        let mut next = if both_pawns {2} else {1};
+        let mut free_squares = 64 - d.group_len[0] - if both_pawns {d.group_len[1]} else {0};
+        let mut idx = 1;
+        let mut k = 0;
+        while next < n || k == order[0] || k == order[1] {
+            if k == order[0] {
+                d.group_idx[0] = idx;
+                idx *= if has_pawns {m.lead_pawns_size[d.group_len[0]][f]} else if unique {31332} else {462};
+            } else if k == order[1] {
+                d.group_idx[1] = idx;
+                idx *= m.binomial[d.group_len[1]][48 - d.group_len[0]];
+            } else {
+                d.group_idx[next] = idx;
+                idx *= m.binomial[d.group_len[next]][free_squares];
+                free_squares -= d.group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        d.group_idx[n] = idx;
+    }
+
+    //dtz tables can map stored values to real ones, one list for each result
+    fn set_dtz_map(&mut self, bytes: &[u8], mut at: usize) -> Option<usize> {
+        self.map = at;
+        for d in self.items[0].iter_mut().filter(|d| d.flags & MAPPED != 0) {
+            if d.flags & WIDE != 0 {
+                at += at & 1;
+                for i in 0..4 {
+                    d.map_idx[i] = (at - self.map) / 2 + 1;
+                    at += 2 * le16(bytes, at)? as usize + 2;
+                }
+            } else {
+                for i in 0..4 {
+                    d.map_idx[i] = at - self.map + 1;
+                    at += *bytes.get(at)? as usize + 1;
+                }
+            }
+        }
+        Some(at + (at & 1))
+    }
+
+    //stored dtz in plies, wdl says which map and unit is used
+    fn map_score(&self, f: usize, value: u16, wdl: Wdl) -> Option<i32> {
+        let d = &self.items[0][f];
+        let mut value = value as usize;
+        if d.flags & MAPPED != 0 {
+            let map = d.map_idx[match wdl {
+                Wdl::Loss => 1,
+                Wdl::BlessedLoss => 3,
+                Wdl::CursedWin => 2,
+                Wdl::Draw | Wdl::Win => 0,
+            }] + value;
+            value = if d.flags & WIDE != 0 {
+                le16(&self.bytes, self.map + 2 * map)? as usize
+            } else {
+                *self.bytes.get(self.map + map)? as usize
+            };
+        }
+        let moves = match wdl {
+            Wdl::Win => d.flags & WIN_PLIES == 0,
+            Wdl::Loss => d.flags & LOSS_PLIES == 0,
+            Wdl::CursedWin | Wdl::BlessedLoss => true,
+            Wdl::Draw => false,
+        };
+        Some(if moves {2 * value as i32} else {value as i32} + 1)
+    }
+
+    //board has the material of the table, with white or black as the first side in the name
+    fn probe(&self, board: &BoardB, turn: Side, wdl: Wdl) -> Option<Probe> {
+        let m = maps();
+        //tables have the first side in the name as white, symmetric ones only white to move
+        let flip = tablebase::material_key(board, Side::White) != self.name || self.symmetric && turn == Side::Black;
+        let (flip_color, flip_squares) = if flip {(8, 56)} else {(0, 0)};
+        let stm = flip as usize ^ (turn == Side::Black) as usize;
+
+        let mut squares = [0; MAX_PIECES];
+        let mut pieces = [0; MAX_PIECES];
+        let mut size = 0;
+        let mut lead_count = 0;
+        let mut lead_pawns = 0_u64;
+        let mut f = 0;
+        if self.has_pawns {
+            let lead_side = if self.items[0][0].pieces[0] ^ flip_color >= 8 {Side::Black} else {Side::White};
+            for s in 0..64 {
+                if let Some(piece) = board.board[s ^ 56] && piece.ty == PieceType::Pawn && piece.side == lead_side {
+                    squares[size] = s ^ flip_squares;
+                    size += 1;
+                    lead_pawns |= 1 << s;
+                }
+            }
+            lead_count = size;
+            let lead = (0..lead_count).max_by_key(|i| m.pawns[squares[*i]])?;
+            squares.swap(0, lead);
+            f = file(squares[0]).min(7 - file(squares[0]));
+        }
+        if self.dtz && (self.items[0][f].flags & STM) as usize != stm && !(self.symmetric && !self.has_pawns) {
+            return Some(Probe::ChangeStm);
+        }
+        for s in (0..64).filter(|s| lead_pawns >> s & 1 == 0) {
+            if let Some(piece) = board.board[s ^ 56] {
+                if size == MAX_PIECES || piece.ty == PieceType::Pawn && !(8..56).contains(&s) {
+                    return None;
+                }
+                squares[size] = s ^ flip_squares;
+                pieces[size] = piece_code(piece) ^ flip_color;
+                size += 1;
+            }
+        }
+        if size != self.piece_count {
+            return None;
+        }
+
+        let d = &self.items[stm % self.items.len()][f];
+        //same piece order as the table
+        for i in lead_count..size - 1 {
+            if let Some(j) = (i + 1..size).find(|j| d.pieces[i] == pieces[*j]) {
+                pieces.swap(i, j);
+                squares.swap(i, j);
+            }
+        }
+        //the leading piece goes to files a to d
+        if file(squares[0]) > 3 {
+            squares[..size].iter_mut().for_each(|s| *s ^= 7);
+        }
+
+        let mut idx;
+        if self.has_pawns {
+            idx = m.lead_pawn_idx[lead_count][squares[0]];
+            squares[1..lead_count].sort_by_key(|s| m.pawns[*s]);
+            for (i, s) in squares.iter().enumerate().take(lead_count).skip(1) {
+                idx += m.binomial[i][m.pawns[*s] as usize];
+            }
+        } else {
+            //without pawns the leading piece also goes to ranks 1 to 4 and below the diagonal
+            if rank(squares[0]) > 3 {
+                squares[..size].iter_mut().for_each(|s| *s ^= 56);
+            }
+            if let Some(i) = (0..d.group_len[0]).find(|i| off_diagonal(squares[*i]) != 0) && off_diagonal(squares[i]) > 0 {
+                squares[i..size].iter_mut().for_each(|s| *s = (*s >> 3 | *s << 3) & 63);
+            }
+            if self.has_unique_pieces {
+                let (s0, s1, s2) = (squares[0], squares[1], squares[2]);
+                let adjust1 = (s1 > s0) as usize;
+                let adjust2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                idx = if off_diagonal(s0) != 0 {
+                    (m.a1d1d4[s0] * 63 + (s1 - adjust1) as u64) * 62 + (s2 - adjust2) as u64
+                } else if off_diagonal(s1) != 0 {
+                    (6 * 63 + rank(s0) as u64 * 28 + m.b1h1h7[s1]) * 62 + (s2 - adjust2) as u64
+                } else if off_diagonal(s2) != 0 {
+                    6 * 63 * 62 + 4 * 28 * 62 + rank(s0) as u64 * 7 * 28 + (rank(s1) - adjust1) as u64 * 28 + m.b1h1h7[s2]
+                } else {
+                    6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) as u64 * 7 * 6 + (rank(s1) - adjust1) as u64 * 6 + (rank(s2) - adjust2) as u64
+                };
+            } else {
+                idx = m.kk[m.a1d1d4[squares[0]] as usize][squares[1]];
+            }
+        }
+
+        //the other groups by their squares in ascending order, skipping squares of the groups before
+        idx *= d.group_idx[0];
+        let mut start = d.group_len[0];
+        let mut other_pawns = self.has_pawns && self.pawn_count[1] > 0;
+        for next in 1.. {
+            let len = d.group_len[next];
+            if len == 0 {
+                break;
+            }
+            squares[start..start + len].sort_unstable();
+            let mut n = 0;
+            for i in 0..len {
+                let s = squares[start + i];
+                let adjust = squares[..start].iter().filter(|before| s > **before).count();
+                n += m.binomial[i + 1][s - adjust - if other_pawns {8} else {0}];
+            }
+            other_pawns = false;
+            idx += n * d.group_idx[next];
+            start += len;
+        }
+
+        let value = d.decompress(&self.bytes, idx)?;
+        Some(Probe::Value(if self.dtz {self.map_score(f, value, wdl)?} else {value as i32 - 2}))
+    }
+}
+
+//a file is read into memory on its first probe
+struct LazyTable {
+    path: PathBuf,
+    table: OnceLock<Option<Table>>,
+}
+
+impl LazyTable {
+    fn get(&self, name: &str, dtz: bool) -> Option<&Table> {
+        self.table.get_or_init(|| {
+            let table = std::fs::read(&self.path)
+                .map_err(TablebaseError::Io)
+                .and_then(|bytes| Table::parse(name, bytes, dtz).ok_or_else(|| TablebaseError::Format(self.path.clone())));
+            table.map_err(|err| eprintln!("Syzygy error: {:?}", err)).ok()
+        }).as_ref()
+    }
+}
+
+//a legal move with every promotion, the tables tell underpromotions apart
+struct Child {
+    board: BoardB,
+    capture: bool,
+    pawn: bool,
+}
+
+fn children(board: &BoardB, turn: Side) -> Vec<Child> {
+    let mut children = Vec::new();
+    for (from, to) in search::legal_moves(board, turn) {
+        let pawn = board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::Pawn);
+        let capture = board.occupied(to) || pawn && from % 8 != to % 8;
+        let promotions: &[PieceType] = if pawn && !(8..56).contains(&to) {
+            &[PieceType::Queen, PieceType::Rook, PieceType::Bishop, PieceType::Knight]
+        } else {
+            &[PieceType::Queen]
+        };
+        for promotion in promotions {
+            let mut child = *board;
+            child.make_move_promoting(from, to, *promotion);
+            children.push(Child { board: child, capture, pawn });
+        }
+    }
+    children
+}
+
+fn wdl_from(value: i32) -> Option<Wdl> {
+    Some(match value {
+        -2 => Wdl::Loss,
+        -1 => Wdl::BlessedLoss,
+        0 => Wdl::Draw,
+        1 => Wdl::CursedWin,
+        2 => Wdl::Win,
+        _ => return None,
+    })
+}
+
+fn sign(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss | Wdl::BlessedLoss => -1,
+        Wdl::Draw => 0,
+        Wdl::CursedWin | Wdl::Win => 1,
+    }
+}
+
+//dtz of the position before a capture or pawn move with this result
+fn dtz_before_zeroing(wdl: Wdl) -> i32 {
+    match wdl {
+        Wdl::Loss => -1,
+        Wdl::BlessedLoss => -101,
+        Wdl::Draw => 0,
+        Wdl::CursedWin => 101,
+        Wdl::Win => 1,
+    }
+}
+
+//syzygy tables in one directory
+pub struct Syzygy {
+    wdl: HashMap<String, LazyTable>,
+    dtz: HashMap<String, LazyTable>,
+    pub max_pieces: usize,
+}
+
+impl Syzygy {
+    pub fn open(dir: &Path) -> Result<Self, TablebaseError> {
+        let mut syzygy = Self { wdl: HashMap::new(), dtz: HashMap::new(), max_pieces: 0 };
+        for entry in std::fs::read_dir(dir).map_err(TablebaseError::Io)? {
+            let path = entry.map_err(TablebaseError::Io)?.path();
+            let (Some(name), Some(ext)) = (path.file_stem().and_then(|n| n.to_str()), path.extension().and_then(|e| e.to_str())) else {
+                continue
+            };
+            let (tables, magic) = match ext {
+                "rtbw" => (&mut syzygy.wdl, WDL_MAGIC),
+                "rtbz" => (&mut syzygy.dtz, DTZ_MAGIC),
+                _ => continue,
+            };
+            let mut header = [0; 4];
+            File::open(&path).and_then(|mut f| f.read_exact(&mut header)).map_err(TablebaseError::Io)?;
+            if header != magic {
+                return Err(TablebaseError::Magic(path));
+            }
+            syzygy.max_pieces = syzygy.max_pieces.max(name.chars().filter(|c| *c != 'v').count());
+            tables.insert(name.to_owned(), LazyTable { path, table: OnceLock::new() });
+        }
+        Ok(syzygy)
+    }
+
+    pub fn table_count(&self) -> usize {
+        self.wdl.len() + self.dtz.len()
+    }
+
+    fn table(&self, board: &BoardB, dtz: bool) -> Option<&Table> {
+        let tables = if dtz {&self.dtz} else {&self.wdl};
+        [Side::White, Side::Black].into_iter().find_map(|first| {
+            let name = tablebase::material_key(board, first);
+            tables.get(&name).and_then(|table| table.get(&name, dtz))
+        })
+    }
+
+    fn probe_wdl_table(&self, board: &BoardB, turn: Side) -> Option<Wdl> {
+        if board.piece_count() == 2 {
+            return Some(Wdl::Draw);
+        }
+        match self.table(board, false)?.probe(board, turn, Wdl::Draw)? {
+            Probe::Value(value) => wdl_from(value),
+            Probe::ChangeStm => None,
+        }
+    }
+
+    //Result with the captures searched, and the pawn moves too if zeroing is set. Tables store any
+    //value where a capture is best and don't know en passant. The flag is set if a capture or pawn
+    //move is best, the dtz table can't be used then.
+    fn search(&self, board: &BoardB, turn: Side, zeroing: bool) -> Option<(Wdl, bool)> {
+        let children = children(board, turn);
+        let mut best = Wdl::Loss;
+        let mut searched = 0;
+        for child in children.iter().filter(|c| c.capture || zeroing && c.pawn) {
+            searched += 1;
+            let wdl = self.search(&child.board, !turn, false)?.0.negate();
+            if wdl > best {
+                best = wdl;
+                if wdl == Wdl::Win {
+                    return Some((wdl, true));
+                }
+            }
+        }
+        let all_searched = searched > 0 && searched == children.len();
+        let wdl = if all_searched {best} else {self.probe_wdl_table(board, turn)?};
+        if best >= wdl {
+            return Some((best, best > Wdl::Draw || all_searched));
+        }
+        Some((wdl, false))
+    }
+
+    pub fn probe_wdl(&self, board: &BoardB, turn: Side) -> Option<Wdl> {
+        self.search(board, turn, false).map(|(wdl, _)| wdl)
+    }
+
+    //plies to the next capture or pawn move with best play, negative if the side to move loses,
+    //100 more for cursed wins and blessed losses and 0 for draws
+    pub fn probe_dtz(&self, board: &BoardB, turn: Side) -> Option<i32> {
+        let (wdl, zeroing) = self.search(board, turn, true)?;
+        if wdl == Wdl::Draw {
+            return Some(0);
+        }
+        if zeroing {
+            return Some(dtz_before_zeroing(wdl));
+        }
+        let cursed = matches!(wdl, Wdl::CursedWin | Wdl::BlessedLoss);
+        match self.table(board, true)?.probe(board, turn, wdl)? {
+            Probe::Value(dtz) => Some((dtz + if cursed {100} else {0}) * sign(wdl)),
+            Probe::ChangeStm => {
+                //the table has the other side to move, look one move ahead for the best dtz
+                let mut min_dtz = 0xFFFF;
+                for child in children(board, turn) {
+                    let zeroing = child.capture || child.pawn;
+                    let mut dtz = if zeroing {
+                        -dtz_before_zeroing(self.search(&child.board, !turn, false)?.0)
+                    } else {
+                        -self.probe_dtz(&child.board, !turn)?
+                    };
+                    //mating moves count as zeroing
+                    if dtz == 1 && search::in_check(&child.board, !turn) && search::legal_moves(&child.board, !turn).is_empty() {
+                        min_dtz = 1;
+                    }
+                    if !zeroing {
+                        dtz += dtz.signum();
+                    }
+                    if dtz < min_dtz && dtz.signum() == sign(wdl) {
+                        min_dtz = dtz;
+                    }
+                }
+                //no legal moves, mated
+                Some(if min_dtz == 0xFFFF {-1} else {min_dtz})
+            }
+        }
+    }
+
+    //Best root move by the tables: best result, then quickest to the next zeroing move when
+    //winning and slowest when losing. Pawns promote to queens like every move of the engine.
+    pub fn root_move(&self, board: &BoardB, turn: Side) -> Option<((u8, u8), Wdl)> {
+        let order = |dtz: i32| match dtz {
+            0 => 0,
+            1.. => 1000 - dtz,
+            _ => -1000 - dtz,
+        };
+        let mut best: Option<((u8, u8), Wdl, i32)> = None;
+        for (from, to) in search::legal_moves(board, turn) {
+            let zeroing = board.occupied(to) || board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::Pawn);
+            let mut b = *board;
+            b.make_move(from, to);
+            let wdl = self.probe_wdl(&b, !turn)?.negate();
+            let mut dtz = if zeroing {
+                dtz_before_zeroing(wdl)
+            } else {
+                let dtz = -self.probe_dtz(&b, !turn)?;
+                dtz + dtz.signum()
+            };
+            if dtz == 2 && search::in_check(&b, !turn) && search::legal_moves(&b, !turn).is_empty() {
+                dtz = 1;
+            }
+            if best.map(|(_, _, best_order)| best_order) < Some(order(dtz)) {
+                best = Some(((from, to), wdl, order(dtz)));
+            }
+        }
+        best.map(|(mv, wdl, _)| (mv, wdl))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::retrograde::{self, Dtm};
+    use super::*;
+
+
+
+    //tables written by tests/fixtures/syzygy/generate.rs
+    fn fixtures() -> Syzygy {
+        Syzygy::open(&Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/syzygy")).unwrap()
+    }
+
+    fn probe(tb: &Syzygy, fen: &str) -> (Wdl, i32) {
+        let (board, turn) = BoardB::from_fen(fen).unwrap();
+        (tb.probe_wdl(&board, turn).unwrap(), tb.probe_dtz(&board, turn).unwrap())
+    }
+
+    fn mated(board: &BoardB, turn: Side) -> bool {
+        search::in_check(board, turn) && search::legal_moves(board, turn).is_empty()
+    }
+
+    #[test]
+    fn opens_fixtures() {
+        let tb = fixtures();
+        assert_eq!(tb.table_count(), 12);
+        assert_eq!(tb.max_pieces, 4);
+    }
+
+    #[test]
+    fn known_results() {
+        let tb = fixtures();
+        assert_eq!(probe(&tb, "7k/8/6K1/8/8/8/8/1Q6 w - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe(&tb, "Q6k/8/6K1/8/8/8/8/8 b - - 0 1"), (Wdl::Loss, -1));
+        assert_eq!(probe(&tb, "7k/5Q2/6K1/8/8/8/8/8 b - - 0 1"), (Wdl::Draw, 0));
+        //the king takes the rook
+        assert_eq!(probe(&tb, "8/8/8/8/8/2K5/8/kR6 b - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe(&tb, "8/8/8/8/8/8/8/KB5k w - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe(&tb, "8/8/8/8/8/8/8/KN5k b - - 0 1"), (Wdl::Draw, 0));
+        //the king in front of the pawn on the sixth rank wins with either side to move
+        assert_eq!(probe(&tb, "4k3/8/4K3/4P3/8/8/8/8 w - - 0 1").0, Wdl::Win);
+        assert_eq!(probe(&tb, "4k3/8/4K3/4P3/8/8/8/8 b - - 0 1").0, Wdl::Loss);
+        assert_eq!(probe(&tb, "4k3/4P3/4K3/8/8/8/8/8 b - - 0 1"), (Wdl::Draw, 0));
+        //the queen takes the rook, or the rook takes the queen
+        assert_eq!(probe(&tb, "k7/8/8/8/3r4/8/8/K2Q4 w - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe(&tb, "k7/8/8/8/3r4/8/8/K2Q4 b - - 0 1"), (Wdl::Win, 1));
+    }
+
+    #[test]
+    fn black_pieces() {
+        let tb = fixtures();
+        assert_eq!(probe(&tb, "1q6/8/8/8/8/6k1/8/7K b - - 0 1"), (Wdl::Win, 1));
+        assert_eq!(probe(&tb, "8/8/8/8/8/6k1/8/5b1K w - - 0 1"), (Wdl::Draw, 0));
+        assert_eq!(probe(&tb, "8/8/8/4p3/4k3/8/4K3/8 w - - 0 1").0, Wdl::Loss);
+        let (white, white_turn) = BoardB::from_fen("8/8/8/2k5/8/3K4/3R4/8 w - - 0 1").unwrap();
+        let (black, black_turn) = BoardB::from_fen("8/3r4/3k4/8/2K5/8/8/8 b - - 0 1").unwrap();
+        assert_eq!(tb.probe_dtz(&white, white_turn), tb.probe_dtz(&black, black_turn));
+    }
+
+    #[test]
+    fn root_move_mates() {
+        let tb = fixtures();
+        let (mut board, turn) = BoardB::from_fen("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1").unwrap();
+        let ((from, to), wdl) = tb.root_move(&board, turn).unwrap();
+        assert_eq!(wdl, Wdl::Win);
+        board.make_move(from, to);
+        assert!(mated(&board, !turn));
+    }
+
+    //distance to mate is the distance to zeroing without anything to take or push
+    #[test]
+    fn matches_retrograde_tables() {
+        let tb = fixtures();
+        let mut rng = StdRng::seed_from_u64(1);
+        for material in ["KQvK", "KRvK"] {
+            let table = retrograde::test_tables().table(material).unwrap();
+            for turn in [Side::White, Side::Black] {
+                for _ in 0..500 {
+                    let (board, result) = table.random_position(turn, &mut rng).unwrap();
+                    let expected = match result {
+                        Dtm::Win(n) => n as i32,
+                        Dtm::Draw => 0,
+                        Dtm::Loss(0) => -1,
+                        Dtm::Loss(n) => -(n as i32),
+                    };
+                    assert_eq!(tb.probe_wdl(&board, turn), Some(result.wdl()), "{}", board.to_fen(turn, 0, 1));
+                    assert_eq!(tb.probe_dtz(&board, turn), Some(expected), "{}", board.to_fen(turn, 0, 1));
+                }
+            }
+        }
+    }
+
+    //pawn files and ranks against a solver that doesn't share any of the indexing
+    #[test]
+    fn pawn_results_match_retrograde_tables() {
+        let tb = fixtures();
+        let mut rng = StdRng::seed_from_u64(3);
+        let table = retrograde::test_tables().table("KPvK").unwrap();
+        for turn in [Side::White, Side::Black] {
+            for _ in 0..1000 {
+                let (board, result) = table.random_position(turn, &mut rng).unwrap();
+                assert_eq!(tb.probe_wdl(&board, turn), Some(result.wdl()), "{}", board.to_fen(turn, 0, 1));
+            }
+        }
+    }
+
+    //every result agrees with the results one move later
+    #[test]
+    fn four_pieces_consistent() {
+        let tb = fixtures();
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut checked = 0;
+        while checked < 200 {
+            let mut squares = [0_usize; 4];
+            for s in squares.iter_mut() {
+                *s = rng.gen_range(0..64);
+            }
+            let mut rows = [['1'; 8]; 8];
+            for (s, c) in squares.iter().zip(['K', 'Q', 'k', 'r']) {
+                rows[s / 8][s % 8] = c;
+            }
+            let turn = if rng.r#gen() {"w"} else {"b"};
+            let fen = format!("{} {} - - 0 1", rows.map(|r| r.iter().collect::<String>()).join("/"), turn);
+            let Ok((board, turn)) = BoardB::from_fen(&fen) else {continue};
+            if board.piece_count() != 4 || search::in_check(&board, !turn) {
+                continue;
+            }
+            checked += 1;
+            let wdl = tb.probe_wdl(&board, turn).unwrap();
+            let dtz = tb.probe_dtz(&board, turn).unwrap();
+            let mvs = search::legal_moves(&board, turn);
+            let mut best = if mvs.is_empty() && !search::in_check(&board, turn) {Wdl::Draw} else {Wdl::Loss};
+            let mut quickest = i32::MAX;
+            let mut slowest = 0;
+            for (from, to) in &mvs {
+                let capture = board.occupied(*to);
+                let mut b = board;
+                b.make_move(*from, *to);
+                let child = tb.probe_wdl(&b, !turn).unwrap().negate();
+                best = best.max(child);
+                let child_dtz = tb.probe_dtz(&b, !turn).unwrap();
+                if child == Wdl::Win {
+                    quickest = quickest.min(if capture || mated(&b, !turn) {1} else {1 - child_dtz});
+                }
+                if !capture {
+                    slowest = slowest.max(child_dtz);
+                }
+            }
+            assert_eq!(wdl, best, "{}", fen);
+            match wdl {
+                Wdl::Win => assert_eq!(dtz, quickest, "{}", fen),
+                Wdl::Loss => assert_eq!(dtz, -1 - slowest.max(0), "{}", fen),
+                _ => assert_eq!(dtz, 0, "{}", fen),
+            }
+        }
+    }
+}
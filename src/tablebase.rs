@@ -0,0 +1,206 @@
+use std::{path::PathBuf, sync::OnceLock};
+
+use crate::{boardb::BoardB, pieces::{Side, PieceType}, search::{self, TB_WIN}, retrograde::{DtmTables, Dtm}, syzygy::Syzygy};
+
+
+
+//from the view of the side to move, cursed wins and blessed losses can't be
+//forced within the 50 move rule
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub enum Wdl {
+    Loss,
+    BlessedLoss,
+    Draw,
+    CursedWin,
+    Win,
+}
+
+impl Wdl {
+    pub fn negate(self) -> Self {
+        match self {
+            Wdl::Loss => Wdl::Win,
+            Wdl::BlessedLoss => Wdl::CursedWin,
+            Wdl::Draw => Wdl::Draw,
+            Wdl::CursedWin => Wdl::BlessedLoss,
+            Wdl::Win => Wdl::Loss,
+        }
+    }
+
+    //search score, wins closer to the root are preferred
+    pub fn score(self, ply: usize) -> i32 {
+        match self {
+            Wdl::Loss => -TB_WIN + ply as i32,
+            Wdl::BlessedLoss => -1,
+            Wdl::Draw => 0,
+            Wdl::CursedWin => 1,
+            Wdl::Win => TB_WIN - ply as i32,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum TablebaseError {
+    Io(std::io::Error),
+    //file has a tablebase extension but not the matching header
+    Magic(PathBuf),
+    //header doesn't fit the material in the name or the file is cut short
+    Format(PathBuf),
+}
+
+//tables are named after their material, stronger side first, like KRvK
+pub fn material_key(board: &BoardB, first: Side) -> String {
+    let side_key = |side: Side| {
+        let mut key = String::new();
+        for (ty, c) in [(PieceType::King, 'K'), (PieceType::Queen, 'Q'), (PieceType::Rook, 'R'), (PieceType::Bishop, 'B'), (PieceType::Knight, 'N'), (PieceType::Pawn, 'P')] {
+            for _ in board.board.iter().flatten().filter(|p| p.side == side && p.ty == ty) {
+                key.push(c);
+            }
+        }
+        key
+    };
+    format!("{}v{}", side_key(first), side_key(!first))
+}
+
+pub struct Tablebases {
+    pub syzygy: Option<Syzygy>,
+    //generated distance to mate tables, preferred over syzygy where both exist
+    pub dtm: DtmTables,
+}
+
+static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+
+//tables are loaded once at startup and shared by every search thread
+pub fn init(tablebases: Tablebases) {
+    if TABLEBASES.set(tablebases).is_err() {
+        panic!("Tablebases initialized twice");
+    }
+}
+
+pub fn get() -> Option<&'static Tablebases> {
+    TABLEBASES.get()
+}
+
+impl Tablebases {
+    pub fn max_pieces(&self) -> usize {
+        self.syzygy.as_ref().map_or(0, |s| s.max_pieces).max(self.dtm.max_pieces())
+    }
+
+    fn probeable(&self, board: &BoardB) -> bool {
+        board.piece_count() <= self.max_pieces() && !board.has_castling_rights()
+    }
+
+    pub fn probe_wdl(&self, board: &BoardB, turn: Side) -> Option<Wdl> {
+        if !self.probeable(board) {
+            return None;
+        }
+        if let Some(dtm) = self.dtm.probe(board, turn) {
+            return Some(dtm.wdl());
+        }
+        self.syzygy.as_ref()?.probe_wdl(board, turn)
+    }
+
+    pub fn probe_dtm(&self, board: &BoardB, turn: Side) -> Option<Dtm> {
+        if !self.probeable(board) {
+            return None;
+        }
+        self.dtm.probe(board, turn)
+    }
+
+    //search score of a position, with distance to mate if a generated table has it
+    pub fn probe_score(&self, board: &BoardB, turn: Side, ply: usize) -> Option<i32> {
+        if let Some(dtm) = self.probe_dtm(board, turn) {
+            return Some(dtm.score(ply));
+        }
+        self.probe_wdl(board, turn).map(|wdl| wdl.score(ply))
+    }
+
+    pub fn probe_dtz(&self, board: &BoardB, turn: Side) -> Option<i32> {
+        if !self.probeable(board) {
+            return None;
+        }
+        self.syzygy.as_ref()?.probe_dtz(board, turn)
+    }
+
+    //tablebase-perfect root move with its score: keep the best result, win as fast and lose as slow as possible
+    pub fn root_move(&self, board: &BoardB, turn: Side) -> Option<((u8, u8), i32)> {
+        if !self.probeable(board) {
+            return None;
+        }
+        if let Some((mv, dtm)) = self.dtm_root_move(board, turn) {
+            return Some((mv, dtm.score(0)));
+        }
+        self.syzygy.as_ref()?.root_move(board, turn).map(|(mv, wdl)| (mv, wdl.score(0)))
+    }
+
+    fn dtm_root_move(&self, board: &BoardB, turn: Side) -> Option<((u8, u8), Dtm)> {
+        let mut best: Option<((u8, u8), Dtm)> = None;
+        for (from, to) in search::legal_moves(board, turn) {
+            let mut b = *board;
+            b.make_move(from, to);
+            let dtm = self.dtm.probe(&b, !turn)?.parent();
+            if best.is_none_or(|(_, best_dtm)| dtm.rank() > best_dtm.rank()) {
+                best = Some(((from, to), dtm));
+            }
+        }
+        best
+    }
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, SeedableRng};
+    use crate::retrograde;
+    use super::*;
+
+
+
+    //a copy of the shared test table, through a file because the tablebases own their tables
+    fn tablebases() -> &'static Tablebases {
+        static TABLEBASES: OnceLock<Tablebases> = OnceLock::new();
+        TABLEBASES.get_or_init(|| {
+            let dir = std::env::temp_dir().join(format!("chess-cp-tb-{}", std::process::id()));
+            std::fs::create_dir_all(&dir).unwrap();
+            retrograde::test_tables().table("KQvK").unwrap().save(&dir).unwrap();
+            let dtm = DtmTables::load(&dir);
+            std::fs::remove_dir_all(&dir).unwrap();
+            Tablebases { syzygy: None, dtm: dtm.unwrap() }
+        })
+    }
+
+    #[test]
+    fn root_move_mates_at_once() {
+        let tb = tablebases();
+        let (mut board, turn) = BoardB::from_fen("7k/8/6K1/8/8/8/8/1Q6 w - - 0 1").unwrap();
+        let ((from, to), score) = tb.root_move(&board, turn).unwrap();
+        assert_eq!(score, TB_WIN - 1);
+        board.make_move(from, to);
+        assert!(search::in_check(&board, !turn) && search::legal_moves(&board, !turn).is_empty());
+    }
+
+    //the root move wins fastest or loses slowest of all legal moves
+    #[test]
+    fn root_move_is_fastest() {
+        let tb = tablebases();
+        let table = tb.dtm.table("KQvK").unwrap();
+        let mut rng = StdRng::seed_from_u64(5);
+        for turn in [Side::White, Side::Black] {
+            for _ in 0..200 {
+                let (board, _) = table.random_position(turn, &mut rng).unwrap();
+                let after = |(from, to): (u8, u8)| {
+                    let mut b = board;
+                    b.make_move(from, to);
+                    tb.dtm.probe(&b, !turn).unwrap().parent()
+                };
+                let Some((mv, score)) = tb.root_move(&board, turn) else {
+                    assert!(search::legal_moves(&board, turn).is_empty());
+                    continue;
+                };
+                let best = search::legal_moves(&board, turn).into_iter().map(after).max_by_key(|dtm| dtm.rank()).unwrap();
+                assert_eq!(after(mv), best, "{}", board.to_fen(turn, 0, 1));
+                assert_eq!(score, best.score(0));
+            }
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use rand::Rng;
+
+use crate::{boardb::BoardB, gameb::{GameB, GameState}, pieces::Side, retrograde::{Dtm, DtmTables}};
+
+
+
+//Endgame trainer: the player gets a won position of one table and has to mate the perfectly
+//defending cpu. Every move is compared with the table.
+pub struct Trainer {
+    //plies to mate from the start position with perfect play
+    optimal: u32,
+    last: Dtm,
+    ply: usize,
+    finished: bool,
+}
+
+//white moves needed for a mate in plies
+fn moves(plies: u32) -> u32 {
+    plies.div_ceil(2)
+}
+
+impl Trainer {
+    //random position of the table where white to move mates in at least min_plies
+    pub fn start<R: Rng>(material: &str, tables: &DtmTables, min_plies: u32, rng: &mut R) -> Option<(Self, BoardB)> {
+        let table = tables.table(material)?;
+        let min_plies = min_plies.min(table.longest_win());
+        for _ in 0..100_000 {
+            if let Some((board, Dtm::Win(n))) = table.random_position(Side::White, rng) && n >= min_plies {
+                println!("{}: white mates in {} moves", table.material, moves(n));
+                return Some((Self { optimal: n, last: Dtm::Win(n), ply: 0, finished: false }, board));
+            }
+        }
+        None
+    }
+
+    //the same position once more, after the game was restarted
+    pub fn retry(&mut self) {
+        println!("Again: white mates in {} moves", moves(self.optimal));
+        *self = Self { optimal: self.optimal, last: Dtm::Win(self.optimal), ply: 0, finished: false };
+    }
+
+    //call every frame, reports once per move
+    pub fn update(&mut self, game: &GameB, tables: &DtmTables) {
+        if self.finished || game.ply == self.ply {
+            return;
+        }
+        self.ply = game.ply;
+        if let GameState::Winner(side) = game.state {
+            self.finished = true;
+            match side {
+                Side::White => println!("Mate in {} moves, the best possible was {}", moves(game.ply as u32), moves(self.optimal)),
+                Side::Black => println!("Black won"),
+            }
+            return;
+        }
+        let Some(dtm) = tables.probe(&game.board, game.turn) else {
+            return;
+        };
+        if game.turn == Side::Black {
+            match (self.last, dtm) {
+                (Dtm::Win(before), Dtm::Loss(after)) if after + 1 == before => println!("Best move, mate in {} moves", after / 2),
+                (Dtm::Win(before), Dtm::Loss(after)) => println!("Still winning, but mate is {} moves away instead of {}", after / 2, moves(before) - 1),
+                (Dtm::Win(_), _) => {
+                    println!("The win is gone");
+                    self.finished = true;
+                },
+                _ => {}
+            }
+        }
+        if game.state == GameState::Draw {
+            println!("Draw");
+            self.finished = true;
+        }
+        self.last = dtm;
+    }
+}
@@ -0,0 +1,104 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+
+
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Bound {
+    Exact,
+    //score is at least this high (beta cutoff)
+    Lower,
+    //score is at most this high (failed low)
+    Upper,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TTEntry {
+    pub mv: Option<(u8, u8)>,
+    pub score: i32,
+    pub depth: u8,
+    pub bound: Bound,
+}
+
+impl TTEntry {
+    //data layout: from 6 | to 6 | has move 1 | bound 2 | depth 8 | score 32
+    fn pack(&self) -> u64 {
+        let (from, to, has_move) = match self.mv {
+            Some((from, to)) => (from as u64, to as u64, 1),
+            None => (0, 0, 0),
+        };
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        from | to << 6 | has_move << 12 | bound << 13 | (self.depth as u64) << 15 | (self.score as u32 as u64) << 32
+    }
+
+    fn unpack(data: u64) -> Self {
+        let mv = (data >> 12 & 1 == 1).then_some(((data & 63) as u8, (data >> 6 & 63) as u8));
+        let bound = match data >> 13 & 3 {
+            0 => Bound::Exact,
+            1 => Bound::Lower,
+            _ => Bound::Upper,
+        };
+        Self {
+            mv,
+            bound,
+            depth: (data >> 15) as u8,
+            score: (data >> 32) as u32 as i32,
+        }
+    }
+}
+
+//key is stored xor'ed with the data, so a torn write from another thread just looks like a miss
+struct Slot {
+    key: AtomicU64,
+    data: AtomicU64,
+}
+
+//shared between all search threads, lockless
+pub struct TranspositionTable {
+    slots: Vec<Slot>,
+}
+
+impl TranspositionTable {
+    pub fn new(size_mb: usize) -> Self {
+        //power of two so the index is just a mask
+        let len = 1 << (size_mb.max(1) * 1024 * 1024 / std::mem::size_of::<Slot>()).ilog2();
+        let slots = (0..len).map(|_| Slot { key: AtomicU64::new(0), data: AtomicU64::new(0) }).collect();
+        Self { slots }
+    }
+
+    fn slot(&self, key: u64) -> &Slot {
+        &self.slots[key as usize & (self.slots.len() - 1)]
+    }
+
+    pub fn probe(&self, key: u64) -> Option<TTEntry> {
+        let slot = self.slot(key);
+        let data = slot.data.load(Ordering::Relaxed);
+        if slot.key.load(Ordering::Relaxed) ^ data == key && data != 0 {
+            Some(TTEntry::unpack(data))
+        } else {
+            None
+        }
+    }
+
+    pub fn store(&self, key: u64, entry: TTEntry) {
+        let slot = self.slot(key);
+        let old_data = slot.data.load(Ordering::Relaxed);
+        //prefer deeper entries for the same position, always replace other positions
+        if slot.key.load(Ordering::Relaxed) ^ old_data == key && TTEntry::unpack(old_data).depth > entry.depth && entry.bound != Bound::Exact {
+            return
+        }
+        let data = entry.pack();
+        slot.key.store(key ^ data, Ordering::Relaxed);
+        slot.data.store(data, Ordering::Relaxed);
+    }
+
+    pub fn clear(&self) {
+        for slot in &self.slots {
+            slot.key.store(0, Ordering::Relaxed);
+            slot.data.store(0, Ordering::Relaxed);
+        }
+    }
+}
@@ -0,0 +1,211 @@
+use std::{fs::File, io::{BufRead, BufReader}, path::Path};
+
+use crate::{boardb::{BoardB, Piece}, eval::EvalParams, pieces::Side};
+
+
+
+#[derive(Debug)]
+pub enum TuneError {
+    Io(std::io::Error),
+    NoPositions,
+}
+
+struct Sample {
+    board: [Option<Piece>; 64],
+    //game result from white's view, 1.0 / 0.5 / 0.0
+    result: f64,
+}
+
+//Texel tuning: finds the weights whose evaluation, squashed by a sigmoid, predicts the game
+//results of quiet positions best. Plain local search, every weight is moved up and down by a
+//step as long as the error drops, the step is halved when nothing improves anymore.
+pub fn tune(epd: &Path, start: EvalParams) -> Result<EvalParams, TuneError> {
+    let samples = load_samples(epd)?;
+    if samples.is_empty() {
+        return Err(TuneError::NoPositions);
+    }
+    println!("{} positions", samples.len());
+
+    let k = optimal_k(&samples, &start);
+    println!("scaling constant K = {:.4}", k);
+
+    let mut params = start;
+    let mut best = error(&samples, &params, k);
+    println!("start error {:.6}", best);
+    let mut step = 16;
+    while step > 0 {
+        let mut improved = false;
+        for n in 0..EvalParams::NAMES.len() {
+            for delta in [step, -step] {
+                let mut candidate = params.clone();
+                *candidate.get_mut(n) += delta;
+                let e = error(&samples, &candidate, k);
+                if e < best {
+                    best = e;
+                    params = candidate;
+                    improved = true;
+                    break;
+                }
+            }
+        }
+        println!("step {:>2}  error {:.6}  {:?}", step, best, params);
+        if !improved {
+            step /= 2;
+        }
+    }
+    Ok(params)
+}
+
+//reads "<fen> c9 \"1-0\";", "<fen> [1.0]" and the "<fen> | <score> | <result>" lines of the data generator,
+//lines without a result are skipped
+fn load_samples(path: &Path) -> Result<Vec<Sample>, TuneError> {
+    let reader = BufReader::new(File::open(path).map_err(TuneError::Io)?);
+    let mut samples = Vec::new();
+    for line in reader.lines() {
+        let line = line.map_err(TuneError::Io)?;
+        let Some((fen, result)) = split_result(&line) else {
+            continue;
+        };
+        //epd has no move counters
+        let fen = if fen.split_whitespace().count() == 4 {format!("{} 0 1", fen)} else {fen.to_owned()};
+        if let Ok((board, _)) = BoardB::from_fen(&fen) {
+            samples.push(Sample { board: board.board, result });
+        }
+    }
+    Ok(samples)
+}
+
+fn split_result(line: &str) -> Option<(&str, f64)> {
+    let parse = |r: &str| match r.trim().trim_matches(['"', ';', '[', ']']) {
+        "1-0" | "1.0" | "1" => Some(1.0),
+        "0-1" | "0.0" | "0" => Some(0.0),
+        "1/2-1/2" | "0.5" => Some(0.5),
+        _ => None,
+    };
+    if let Some((fen, rest)) = line.split_once(" | ") {
+        return Some((fen.trim(), parse(rest.rsplit('|').next()?)?));
+    }
+    if let Some(i) = line.find(" c9 ") {
+        return Some((line[..i].trim(), parse(line[i + 4..].split(';').next()?)?));
+    }
+    let i = line.rfind('[')?;
+    Some((line[..i].trim(), parse(&line[i..])?))
+}
+
+fn sigmoid(score: f64, k: f64) -> f64 {
+    1.0 / (1.0 + 10_f64.powf(-k * score / 400.0))
+}
+
+//mean squared error over all samples, split over all cores
+fn error(samples: &[Sample], params: &EvalParams, k: f64) -> f64 {
+    let threads = std::thread::available_parallelism().map_or(1, |n| n.get());
+    let chunk = samples.len().div_ceil(threads);
+    let sum: f64 = std::thread::scope(|s| {
+        let handles: Vec<_> = samples.chunks(chunk).map(|samples| s.spawn(move || {
+            samples.iter()
+                .map(|sample| (sample.result - sigmoid(params.evaluate(&sample.board, Side::White) as f64, k)).powi(2))
+                .sum::<f64>()
+        })).collect();
+        handles.into_iter().map(|h| h.join().expect("Tuner thread panicked")).sum()
+    });
+    sum / samples.len() as f64
+}
+
+//the K that fits the starting weights best, found by ternary search
+fn optimal_k(samples: &[Sample], params: &EvalParams) -> f64 {
+    let (mut low, mut high) = (0.05, 5.0);
+    for _ in 0..40 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(samples, params, a) < error(samples, params, b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    (low + high) / 2.0
+}
+
+
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+    use crate::{boardb::{Pos, PosTrait}, pieces::PieceType};
+    use super::*;
+
+
+
+    //kings and a few random pieces on distinct squares, no pawns on the first and last rank
+    fn random_board(rng: &mut StdRng) -> BoardB {
+        let mut board = BoardB::empty();
+        let mut pieces = vec![Piece::new(PieceType::King, Side::White), Piece::new(PieceType::King, Side::Black)];
+        for side in [Side::White, Side::Black] {
+            for ty in [PieceType::Pawn, PieceType::Knight, PieceType::Bishop, PieceType::Rook, PieceType::Queen] {
+                let max = if ty == PieceType::Pawn {4} else {2};
+                for _ in 0..rng.gen_range(0..=max) {
+                    pieces.push(Piece::new(ty, side));
+                }
+            }
+        }
+        for piece in pieces {
+            loop {
+                let i = rng.gen_range(0..64);
+                let y = Pos::from_i(i).y;
+                if !board.occupied(i) && (piece.ty != PieceType::Pawn || (1..7).contains(&y)) {
+                    board.set_piece(i, piece);
+                    break;
+                }
+            }
+        }
+        board
+    }
+
+    #[test]
+    fn k_fits_the_results() {
+        let mut rng = StdRng::seed_from_u64(33);
+        let params = EvalParams::default();
+        let samples: Vec<Sample> = (0..500).map(|_| {
+            let board = random_board(&mut rng).board;
+            Sample { board, result: sigmoid(params.evaluate(&board, Side::White) as f64, 1.3) }
+        }).collect();
+        let k = optimal_k(&samples, &params);
+        assert!((k - 1.3).abs() < 0.01, "{}", k);
+        assert!(error(&samples, &params, k) < 1e-6);
+    }
+
+    //results come from weights the tuner doesn't start with, tuning has to get closer to them
+    #[test]
+    fn tuning_lowers_the_error() {
+        let mut rng = StdRng::seed_from_u64(34);
+        let truth = EvalParams { knight: 350, rook: 450, ..EvalParams::default() };
+        let mut epd = String::new();
+        for _ in 0..1000 {
+            let board = random_board(&mut rng);
+            let result = match truth.evaluate(&board.board, Side::White) {
+                s if s > 50 => "1.0",
+                s if s < -50 => "0.0",
+                _ => "0.5",
+            };
+            epd += &format!("{} [{}]\n", board.to_fen(Side::White, 0, 1), result);
+        }
+        let dir = std::env::temp_dir().join(format!("chess-cp-tune-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("samples.epd");
+        std::fs::write(&path, epd).unwrap();
+        let start = EvalParams::default();
+        let tuned = tune(&path, start.clone()).unwrap();
+        let samples = load_samples(&path).unwrap();
+        assert_eq!(samples.len(), 1000);
+        let k = optimal_k(&samples, &start);
+        assert!(error(&samples, &tuned, k) < error(&samples, &start, k));
+        assert!(tuned.knight > tuned.bishop && tuned.rook < start.rook, "{:?}", tuned);
+
+        //what the tuner writes is what the engine loads
+        let params = dir.join("params.txt");
+        tuned.save(&params).unwrap();
+        let loaded = EvalParams::load(&params);
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(loaded.unwrap(), tuned);
+    }
+}
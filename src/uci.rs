@@ -0,0 +1,272 @@
+use std::{io::BufRead, sync::{atomic::{AtomicBool, Ordering}, Arc}, thread::JoinHandle, time::{Duration, Instant}};
+
+use crate::{boardb::{BoardB, Pos, PosTrait}, pieces::{Side, PieceType}, san, search::{self, SearchConfig, SearchResult}, tt::TranspositionTable};
+
+
+
+const NAME: &str = "chess-cp";
+const AUTHOR: &str = "iLePix";
+const MAX_MULTI_PV: usize = 64;
+const MAX_THREADS: usize = 64;
+const MAX_HASH_MB: usize = 4096;
+//never plan to use the last milliseconds of the clock, the gui needs time to receive the move
+const MOVE_OVERHEAD_MS: u64 = 50;
+
+//Universal Chess Interface on stdin/stdout, so the engine can be used from any chess gui.
+//Searches run on their own thread, input is still read while they run so stop and isready work.
+pub struct Uci {
+    board: BoardB,
+    turn: Side,
+    config: SearchConfig,
+    hash_mb: usize,
+    tt: Arc<TranspositionTable>,
+    search: Option<Running>,
+}
+
+struct Running {
+    stop: Arc<AtomicBool>,
+    //bestmove waits while this is set, for go infinite and go ponder
+    hold: Arc<AtomicBool>,
+    //time for the move once the gui sends ponderhit
+    ponder_budget: Option<u64>,
+    handle: JoinHandle<()>,
+}
+
+pub fn run(threads: usize, hash_mb: usize, multi_pv: usize) {
+    let mut uci = Uci {
+        board: BoardB::with_starting_pos(),
+        turn: Side::White,
+        config: SearchConfig { threads, multi_pv, ..Default::default() },
+        hash_mb,
+        tt: Arc::new(TranspositionTable::new(hash_mb)),
+        search: None,
+    };
+    for line in std::io::stdin().lock().lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if !uci.command(&line) {
+            break;
+        }
+    }
+    uci.stop();
+}
+
+impl Uci {
+    //false on quit
+    fn command(&mut self, line: &str) -> bool {
+        let tokens: Vec<&str> = line.split_whitespace().collect();
+        let Some((command, args)) = tokens.split_first() else {
+            return true;
+        };
+        match *command {
+            "uci" => {
+                println!("id name {}", NAME);
+                println!("id author {}", AUTHOR);
+                println!("option name Hash type spin default {} min 1 max {}", self.hash_mb, MAX_HASH_MB);
+                println!("option name Threads type spin default {} min 1 max {}", self.config.threads, MAX_THREADS);
+                println!("option name MultiPV type spin default {} min 1 max {}", self.config.multi_pv, MAX_MULTI_PV);
+                //pondering only needs the ponder move in bestmove and go ponder, the option tells the gui it's supported
+                println!("option name Ponder type check default false");
+                println!("uciok");
+            },
+            "isready" => println!("readyok"),
+            "setoption" => self.set_option(args),
+            "ucinewgame" => {
+                self.stop();
+                self.tt.clear();
+            },
+            "position" => {
+                self.stop();
+                if let Err(err) = self.position(args) {
+                    println!("info string {}", err);
+                }
+            },
+            "go" => self.go(args),
+            "ponderhit" => self.ponder_hit(),
+            "stop" => self.stop(),
+            "quit" => return false,
+            _ => println!("info string unknown command {}", command),
+        }
+        true
+    }
+
+    fn set_option(&mut self, args: &[&str]) {
+        //setoption name <name, can contain spaces> value <value>
+        let value_at = args.iter().position(|t| *t == "value").unwrap_or(args.len());
+        let name = args.get(1..value_at).unwrap_or(&[]).join(" ").to_lowercase();
+        let value = args.get(value_at + 1).and_then(|v| v.parse::<usize>().ok());
+        match (name.as_str(), value) {
+            ("hash", Some(value)) => {
+                self.stop();
+                self.hash_mb = value.clamp(1, MAX_HASH_MB);
+                self.tt = Arc::new(TranspositionTable::new(self.hash_mb));
+            },
+            ("threads", Some(value)) => self.config.threads = value.clamp(1, MAX_THREADS),
+            ("multipv", Some(value)) => self.config.multi_pv = value.clamp(1, MAX_MULTI_PV),
+            //the gui only sends go ponder when it's on, nothing to remember
+            ("ponder", _) => {},
+            ("hash" | "threads" | "multipv", None) => println!("info string option {} needs a number", name),
+            _ => println!("info string unknown option {}", name),
+        }
+    }
+
+    //position (startpos | fen <fen>) [moves <move>...]
+    fn position(&mut self, args: &[&str]) -> Result<(), String> {
+        let moves_at = args.iter().position(|t| *t == "moves").unwrap_or(args.len());
+        let (board, turn) = match args.first() {
+            Some(&"startpos") => (BoardB::with_starting_pos(), Side::White),
+            Some(&"fen") => {
+                let mut fen = args[1..moves_at].join(" ");
+                //move counters are optional in uci
+                if args[1..moves_at].len() == 4 {
+                    fen += " 0 1";
+                }
+                BoardB::from_fen(&fen).map_err(|err| format!("invalid fen {:?}", err))?
+            },
+            _ => return Err("position needs startpos or fen".to_owned()),
+        };
+        self.board = board;
+        self.turn = turn;
+        for mv in args.iter().skip(moves_at + 1) {
+            let (from, to, promotion) = parse_move(&self.board, self.turn, mv).ok_or_else(|| format!("illegal move {}", mv))?;
+            self.board.make_move_promoting(from, to, promotion);
+            self.turn = !self.turn;
+        }
+        Ok(())
+    }
+
+    fn go(&mut self, args: &[&str]) {
+        self.stop();
+        //without limits the search runs until stop
+        let mut config = SearchConfig { depth: search::MAX_DEPTH, ..self.config };
+        let (mut movetime, mut infinite, mut ponder) = (None, false, false);
+        let (mut time, mut inc, mut moves_to_go) = (None, 0, None);
+        let (our_time, our_inc) = match self.turn {
+            Side::White => ("wtime", "winc"),
+            Side::Black => ("btime", "binc"),
+        };
+        let mut tokens = args.iter();
+        while let Some(token) = tokens.next() {
+            let mut number = || tokens.next().and_then(|v| v.parse::<i64>().ok()).map(|v| v.max(0) as u64);
+            match *token {
+                "depth" => config.depth = number().map_or(config.depth, |d| d as usize),
+                "nodes" => config.nodes = number(),
+                "movetime" => movetime = number(),
+                "movestogo" => moves_to_go = number(),
+                "infinite" => infinite = true,
+                "ponder" => ponder = true,
+                t if t == our_time => time = number(),
+                t if t == our_inc => inc = number().unwrap_or(0),
+                //the other side's clock needs no handling
+                _ => {},
+            }
+        }
+        let budget = movetime.or(time.map(|time| time_budget(time, inc, moves_to_go)));
+
+        let stop = Arc::new(AtomicBool::new(false));
+        //while pondering the clock only starts with ponderhit
+        if !ponder && let Some(ms) = budget {
+            stop_after(stop.clone(), ms);
+        }
+        let hold = Arc::new(AtomicBool::new(infinite || ponder));
+        let (board, turn, tt) = (self.board, self.turn, self.tt.clone());
+        let (search_stop, search_hold) = (stop.clone(), hold.clone());
+        let handle = std::thread::spawn(move || {
+            let start = Instant::now();
+            let result = search::analyse(board, turn, config, &tt, &search_stop, &mut |result| print_info(&board, result, start));
+            //the gui decides when an infinite or ponder search ends, even if it finished early
+            while search_hold.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            let Some((from, to)) = result.mv else {
+                println!("bestmove 0000");
+                return;
+            };
+            let mut after = board;
+            after.make_move(from, to);
+            match result.lines.first().and_then(|line| line.pv.get(1)) {
+                Some((ponder_from, ponder_to)) => println!("bestmove {} ponder {}", move_to_uci(&board, from, to), move_to_uci(&after, *ponder_from, *ponder_to)),
+                None => println!("bestmove {}", move_to_uci(&board, from, to)),
+            }
+        });
+        self.search = Some(Running { stop, hold, ponder_budget: if ponder {budget} else {None}, handle });
+    }
+
+    //the opponent played the move we ponder on, from now on the search runs on our clock
+    fn ponder_hit(&mut self) {
+        if let Some(running) = &mut self.search {
+            if let Some(ms) = running.ponder_budget.take() {
+                stop_after(running.stop.clone(), ms);
+            }
+            running.hold.store(false, Ordering::Relaxed);
+        }
+    }
+
+    //waits for the bestmove of a running search
+    fn stop(&mut self) {
+        if let Some(running) = self.search.take() {
+            running.stop.store(true, Ordering::Relaxed);
+            running.hold.store(false, Ordering::Relaxed);
+            running.handle.join().expect("Search thread panicked");
+        }
+    }
+}
+
+pub fn stop_after(stop: Arc<AtomicBool>, ms: u64) {
+    std::thread::spawn(move || {
+        std::thread::sleep(Duration::from_millis(ms));
+        stop.store(true, Ordering::Relaxed);
+    });
+}
+
+//an even share of the remaining time plus most of the increment
+pub fn time_budget(time: u64, inc: u64, moves_to_go: Option<u64>) -> u64 {
+    let moves = moves_to_go.unwrap_or(30).max(1);
+    (time / moves + inc * 3 / 4).min(time.saturating_sub(MOVE_OVERHEAD_MS)).max(1)
+}
+
+fn print_info(board: &BoardB, result: &SearchResult, start: Instant) {
+    let ms = start.elapsed().as_millis() as u64;
+    let nps = result.nodes * 1000 / ms.max(1);
+    for (n, line) in result.lines.iter().enumerate() {
+        println!(
+            "info depth {} multipv {} score {} nodes {} nps {} time {} pv {}",
+            result.depth, n + 1, score_string(line.score), result.nodes, nps, ms, pv_string(board, &line.pv)
+        );
+    }
+}
+
+pub fn score_string(score: i32) -> String {
+    match search::mate_in(score) {
+        Some(moves) => format!("mate {}", moves),
+        None => format!("cp {}", score),
+    }
+}
+
+fn pv_string(board: &BoardB, pv: &[(u8, u8)]) -> String {
+    let mut board = *board;
+    let mvs: Vec<String> = pv.iter().map(|(from, to)| {
+        let mv = move_to_uci(&board, *from, *to);
+        board.make_move(*from, *to);
+        mv
+    }).collect();
+    mvs.join(" ")
+}
+
+//long algebraic notation like e2e4 or e7e8q, the board only promotes to queens
+pub fn move_to_uci(board: &BoardB, from: u8, to: u8) -> String {
+    let promotes = board.get_piece_at_pos(from).is_some_and(|p| p.ty == PieceType::Pawn) && matches!(Pos::from_i(to).y, 0 | 7);
+    format!("{}{}{}", san::square_name(from), san::square_name(to), if promotes {"q"} else {""})
+}
+
+//None if the move is malformed or illegal in the position
+pub fn parse_move(board: &BoardB, turn: Side, mv: &str) -> Option<(u8, u8, PieceType)> {
+    let from = san::square_from_str(mv.get(0..2)?)?;
+    let to = san::square_from_str(mv.get(2..4)?)?;
+    let promotion = match mv.get(4..) {
+        Some("") | None => PieceType::Queen,
+        Some(p) => san::piece_from_char(p.chars().next()?).filter(|p| !matches!(p, PieceType::King | PieceType::Pawn))?,
+    };
+    search::legal_moves(board, turn).contains(&(from, to)).then_some((from, to, promotion))
+}
@@ -0,0 +1,389 @@
+use std::{io::{BufRead, BufReader, Write}, path::Path, process::{Child, ChildStdin, Command, Stdio}, sync::mpsc::{self, Receiver, RecvTimeoutError, Sender}, time::{Duration, Instant}};
+
+use crate::{boardb::BoardB, pgn, pieces::{Side, PieceType}, search, uci};
+
+
+
+//answer time for uci and isready
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+//an engine on the clock gets this much longer than its time before it counts as hanging
+const GO_GRACE: Duration = Duration::from_secs(5);
+
+#[derive(Debug)]
+pub enum UciError {
+    Io(std::io::Error),
+    //no answer in time to the command
+    Timeout(&'static str),
+    //the engine closed its output
+    Exited,
+    //bestmove that can't be played in the position
+    IllegalMove(String),
+}
+
+//search limits of one go command, all times in milliseconds
+#[derive(Clone, Copy, Default, Debug)]
+pub struct Limits {
+    pub depth: Option<usize>,
+    pub nodes: Option<u64>,
+    pub movetime: Option<u64>,
+    pub clock: Option<Clock>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct Clock {
+    pub wtime: u64,
+    pub btime: u64,
+    pub winc: u64,
+    pub binc: u64,
+}
+
+impl Limits {
+    //longest the side to move may think, None if only depth or nodes limit the search
+    pub fn time(&self, turn: Side) -> Option<u64> {
+        let clock = self.clock.map(|c| match turn {
+            Side::White => c.wtime,
+            Side::Black => c.btime,
+        });
+        self.movetime.or(clock)
+    }
+
+    fn go_command(&self) -> String {
+        let mut go = "go".to_owned();
+        if let Some(depth) = self.depth {
+            go += &format!(" depth {}", depth);
+        }
+        if let Some(nodes) = self.nodes {
+            go += &format!(" nodes {}", nodes);
+        }
+        if let Some(movetime) = self.movetime {
+            go += &format!(" movetime {}", movetime);
+        }
+        if let Some(c) = self.clock {
+            go += &format!(" wtime {} btime {} winc {} binc {}", c.wtime, c.btime, c.winc, c.binc);
+        }
+        go
+    }
+}
+
+pub struct GoResult {
+    pub mv: (u8, u8, PieceType),
+    //last reported score and depth, score in search units from the engine's view
+    pub score: Option<i32>,
+    pub depth: usize,
+}
+
+//An external engine process spoken to over UCI. Its output is read by a separate thread, so
+//waiting for an answer can time out instead of hanging forever.
+pub struct UciEngine {
+    //from id name, the file name if the engine sends none
+    pub name: String,
+    child: Child,
+    stdin: ChildStdin,
+    lines: Receiver<String>,
+}
+
+impl UciEngine {
+    //starts the engine, waits for uciok and sets the options
+    pub fn start(path: &Path, options: &[(String, String)]) -> Result<Self, UciError> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::null())
+            .spawn()
+            .map_err(UciError::Io)?;
+        let stdin = child.stdin.take().expect("stdin is piped");
+        let stdout = child.stdout.take().expect("stdout is piped");
+        let (sender, lines) = mpsc::channel();
+        std::thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                let Ok(line) = line else {
+                    break;
+                };
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        let name = path.file_name().map_or("engine".to_owned(), |n| n.to_string_lossy().into_owned());
+        let mut engine = Self { name, child, stdin, lines };
+
+        engine.send("uci")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        loop {
+            let line = engine.read_line(Some(deadline), "uci")?;
+            if let Some(name) = line.strip_prefix("id name ") {
+                engine.name = name.trim().to_owned();
+            } else if line.trim() == "uciok" {
+                break;
+            }
+        }
+        for (name, value) in options {
+            engine.send(&format!("setoption name {} value {}", name, value))?;
+        }
+        engine.ready()?;
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) -> Result<(), UciError> {
+        writeln!(self.stdin, "{}", command).and_then(|_| self.stdin.flush()).map_err(UciError::Io)
+    }
+
+    fn read_line(&self, deadline: Option<Instant>, waiting_for: &'static str) -> Result<String, UciError> {
+        match deadline {
+            Some(deadline) => match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                Ok(line) => Ok(line),
+                Err(RecvTimeoutError::Timeout) => Err(UciError::Timeout(waiting_for)),
+                Err(RecvTimeoutError::Disconnected) => Err(UciError::Exited),
+            },
+            None => self.lines.recv().map_err(|_| UciError::Exited),
+        }
+    }
+
+    fn ready(&mut self) -> Result<(), UciError> {
+        self.send("isready")?;
+        let deadline = Instant::now() + HANDSHAKE_TIMEOUT;
+        while self.read_line(Some(deadline), "isready")?.trim() != "readyok" {}
+        Ok(())
+    }
+
+    pub fn new_game(&mut self) -> Result<(), UciError> {
+        self.send("ucinewgame")?;
+        self.ready()
+    }
+
+    //start_fen None is the start position, moves are in uci notation
+    pub fn go(&mut self, start_fen: Option<&str>, moves: &[String], board: &BoardB, turn: Side, limits: &Limits) -> Result<GoResult, UciError> {
+        let mut position = match start_fen {
+            Some(fen) => format!("position fen {}", fen),
+            None => "position startpos".to_owned(),
+        };
+        if !moves.is_empty() {
+            position += " moves ";
+            position += &moves.join(" ");
+        }
+        self.send(&position)?;
+        self.send(&limits.go_command())?;
+
+        let deadline = limits.time(turn).map(|ms| Instant::now() + Duration::from_millis(ms) + GO_GRACE);
+        let (mut score, mut depth) = (None, 0);
+        loop {
+            let line = self.read_line(deadline, "go")?;
+            let mut tokens = line.split_whitespace();
+            match tokens.next() {
+                Some("info") => {
+                    while let Some(token) = tokens.next() {
+                        match token {
+                            "depth" => depth = tokens.next().and_then(|d| d.parse().ok()).unwrap_or(depth),
+                            "score" => score = parse_score(tokens.next(), tokens.next()).or(score),
+                            //the rest of the line are moves
+                            "pv" => break,
+                            _ => {},
+                        }
+                    }
+                },
+                Some("bestmove") => {
+                    let mv = tokens.next().unwrap_or("");
+                    let (from, to, promotion) = uci::parse_move(board, turn, mv).ok_or_else(|| UciError::IllegalMove(mv.to_owned()))?;
+                    return Ok(GoResult { mv: (from, to, promotion), score, depth });
+                },
+                _ => {},
+            }
+        }
+    }
+}
+
+//"cp 35" or "mate -3" in search units
+fn parse_score(kind: Option<&str>, value: Option<&str>) -> Option<i32> {
+    let value = value?.parse::<i32>().ok()?;
+    match kind? {
+        "cp" => Some(value),
+        "mate" if value > 0 => Some(search::MATE - (2 * value - 1)),
+        "mate" => Some(-search::MATE - 2 * value),
+        _ => None,
+    }
+}
+
+impl Drop for UciEngine {
+    fn drop(&mut self) {
+        let _ = self.send("quit");
+        //give it a moment to exit on its own
+        for _ in 0..50 {
+            if let Ok(Some(_)) = self.child.try_wait() {
+                return;
+            }
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+
+
+//An external engine as a player in the gui. It thinks on its own thread, so the window keeps
+//drawing, and answers are tagged with the game generation they were asked for.
+pub struct UciPlayer {
+    pub name: String,
+    limits: Limits,
+    requests: Sender<Request>,
+    answers: Receiver<(u64, Result<GoResult, UciError>)>,
+    //generation of the last position the engine was asked about
+    asked: Option<u64>,
+    //ucinewgame goes out with the next request
+    new_game: bool,
+}
+
+struct Request {
+    new_game: bool,
+    start: (BoardB, Side),
+    moves: Vec<(u8, u8)>,
+    limits: Limits,
+    generation: u64,
+}
+
+impl UciPlayer {
+    //errors of the start show up here, later ones come with the next answer
+    pub fn start(path: &Path, options: &[(String, String)], limits: Limits) -> Result<Self, UciError> {
+        let mut engine = UciEngine::start(path, options)?;
+        engine.new_game()?;
+        let name = engine.name.clone();
+        let (requests, request_rx) = mpsc::channel::<Request>();
+        let (answer_sender, answers) = mpsc::channel();
+        std::thread::spawn(move || {
+            for Request { new_game, start, moves, limits, generation } in request_rx {
+                let (mut board, mut turn) = start;
+                let start_fen = board.to_fen(turn, 0, 1);
+                let uci_moves: Vec<String> = moves.iter().map(|(from, to)| {
+                    let mv = uci::move_to_uci(&board, *from, *to);
+                    board.make_move(*from, *to);
+                    turn = !turn;
+                    mv
+                }).collect();
+                let start_fen = (start_fen != pgn::START_FEN).then_some(start_fen.as_str());
+                let ready = if new_game {engine.new_game()} else {Ok(())};
+                let answer = ready.and_then(|_| engine.go(start_fen, &uci_moves, &board, turn, &limits));
+                let failed = answer.is_err();
+                if answer_sender.send((generation, answer)).is_err() || failed {
+                    break;
+                }
+            }
+        });
+        Ok(Self { name, limits, requests, answers, asked: None, new_game: false })
+    }
+
+    pub fn new_game(&mut self) {
+        self.asked = None;
+        self.new_game = true;
+    }
+
+    pub fn asked(&self, generation: u64) -> bool {
+        self.asked == Some(generation)
+    }
+
+    //moves are the ones played from start, the engine gets them all for its repetition detection
+    pub fn go(&mut self, start: (BoardB, Side), moves: Vec<(u8, u8)>, generation: u64) {
+        let request = Request { new_game: self.new_game, start, moves, limits: self.limits, generation };
+        if self.requests.send(request).is_ok() {
+            self.asked = Some(generation);
+            self.new_game = false;
+        }
+    }
+
+    //the answer for the position of this generation once the engine sent it, answers to older positions are dropped
+    pub fn try_answer(&mut self, generation: u64) -> Option<Result<GoResult, UciError>> {
+        while let Ok((answered, answer)) = self.answers.try_recv() {
+            match answer {
+                Ok(result) if answered == generation => return Some(Ok(result)),
+                Ok(_) => {},
+                Err(err) => return Some(Err(err)),
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::san::square_from_str;
+    use super::*;
+
+
+
+    fn stand_in(options: &[(&str, &str)]) -> (std::path::PathBuf, Vec<(String, String)>) {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/uci/stand_in_engine.sh");
+        (path, options.iter().map(|(n, v)| (n.to_string(), v.to_string())).collect())
+    }
+
+    fn sq(s: &str) -> u8 {
+        square_from_str(s).unwrap()
+    }
+
+    //polls like the gui does every frame
+    fn wait_for_answer(player: &mut UciPlayer, generation: u64) -> Result<GoResult, UciError> {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while Instant::now() < deadline {
+            if let Some(answer) = player.try_answer(generation) {
+                return answer;
+            }
+            std::thread::sleep(Duration::from_millis(5));
+        }
+        panic!("no answer from the engine");
+    }
+
+    #[test]
+    fn handshake_and_bestmove() {
+        let (path, options) = stand_in(&[]);
+        let mut engine = UciEngine::start(&path, &options).unwrap();
+        assert_eq!(engine.name, "Stand-in");
+        engine.new_game().unwrap();
+        let board = BoardB::with_starting_pos();
+        let result = engine.go(None, &[], &board, Side::White, &Limits { depth: Some(3), ..Limits::default() }).unwrap();
+        assert_eq!((result.mv.0, result.mv.1), (sq("e2"), sq("e4")));
+        assert_eq!((result.score, result.depth), (Some(25), 3));
+    }
+
+    #[test]
+    fn illegal_bestmove() {
+        let (path, options) = stand_in(&[("Move", "e2e5")]);
+        let mut engine = UciEngine::start(&path, &options).unwrap();
+        let board = BoardB::with_starting_pos();
+        let result = engine.go(None, &[], &board, Side::White, &Limits::default());
+        assert!(matches!(result, Err(UciError::IllegalMove(mv)) if mv == "e2e5"));
+    }
+
+    #[test]
+    fn go_timeout() {
+        let (path, options) = stand_in(&[("Mode", "hang")]);
+        let mut engine = UciEngine::start(&path, &options).unwrap();
+        let board = BoardB::with_starting_pos();
+        let result = engine.go(None, &[], &board, Side::White, &Limits { movetime: Some(10), ..Limits::default() });
+        assert!(matches!(result, Err(UciError::Timeout("go"))));
+    }
+
+    #[test]
+    fn missing_engine() {
+        assert!(matches!(UciEngine::start(Path::new("/nonexistent/engine"), &[]), Err(UciError::Io(_))));
+    }
+
+    #[test]
+    fn player_answers_latest_position() {
+        let (path, options) = stand_in(&[("Move", "e7e5")]);
+        let mut player = UciPlayer::start(&path, &options, Limits { depth: Some(1), ..Limits::default() }).unwrap();
+        let start = (BoardB::with_starting_pos(), Side::White);
+        //the answer to the first position comes too late to count
+        player.go(start, vec![(sq("d2"), sq("d4"))], 1);
+        player.go(start, vec![(sq("e2"), sq("e4"))], 2);
+        assert!(player.asked(2));
+        let result = wait_for_answer(&mut player, 2).unwrap();
+        assert_eq!((result.mv.0, result.mv.1), (sq("e7"), sq("e5")));
+    }
+
+    #[test]
+    fn player_reports_illegal_move() {
+        let (path, options) = stand_in(&[("Move", "e7e5")]);
+        let mut player = UciPlayer::start(&path, &options, Limits::default()).unwrap();
+        //white to move, e7e5 isn't legal
+        player.go((BoardB::with_starting_pos(), Side::White), Vec::new(), 1);
+        assert!(matches!(wait_for_answer(&mut player, 1), Err(UciError::IllegalMove(_))));
+    }
+}
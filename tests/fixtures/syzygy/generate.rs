@@ -0,0 +1,904 @@
+//Writes the small syzygy tables the tests in src/syzygy.rs probe: KQvK, KRvK, KBvK, KNvK, KPvK and
+//KQvKR. The official files can't be checked in, so these are generated in the same format from a
+//retrograde analysis of their own, with all promotions and the dtz counting of the real tables.
+//It has its own move generator and doesn't use the engine's code.
+//
+//  rustc -O tests/fixtures/syzygy/generate.rs -o /tmp/generate-syzygy && /tmp/generate-syzygy tests/fixtures/syzygy
+
+use std::{cmp::Reverse, collections::{BinaryHeap, HashMap, VecDeque}, path::Path};
+
+
+
+const PAWN: u8 = 1;
+const KNIGHT: u8 = 2;
+const BISHOP: u8 = 3;
+const ROOK: u8 = 4;
+const QUEEN: u8 = 5;
+const KING: u8 = 6;
+const BLACK: u8 = 8;
+
+const WDL_MAGIC: [u8; 4] = [0x71, 0xE8, 0x23, 0x5D];
+const DTZ_MAGIC: [u8; 4] = [0xD7, 0x66, 0x0C, 0xA5];
+
+const STM: u8 = 1;
+const WIN_PLIES: u8 = 4;
+const LOSS_PLIES: u8 = 8;
+const SINGLE_VALUE: u8 = 128;
+
+const WIN: i8 = 2;
+const DRAW: i8 = 0;
+const LOSS: i8 = -2;
+const UNKNOWN: i8 = 1;
+const INVALID: i8 = 100;
+
+const MAX: usize = 4;
+
+//a1 = 0, h8 = 63
+fn file(s: usize) -> usize {
+    s & 7
+}
+
+fn rank(s: usize) -> usize {
+    s >> 3
+}
+
+fn color(code: u8) -> usize {
+    (code >> 3) as usize
+}
+
+fn step(s: usize, df: i32, dr: i32) -> Option<usize> {
+    let (f, r) = (file(s) as i32 + df, rank(s) as i32 + dr);
+    ((0..8).contains(&f) && (0..8).contains(&r)).then_some((f + 8 * r) as usize)
+}
+
+const KNIGHT_STEPS: [(i32, i32); 8] = [(1, 2), (2, 1), (2, -1), (1, -2), (-1, -2), (-2, -1), (-2, 1), (-1, 2)];
+const KING_STEPS: [(i32, i32); 8] = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+
+fn attacks(code: u8, s: usize, occupied: u64) -> u64 {
+    let mut bits = 0;
+    let mut slide = |dirs: &[(i32, i32)]| {
+        for (df, dr) in dirs {
+            let mut at = s;
+            while let Some(to) = step(at, *df, *dr) {
+                bits |= 1 << to;
+                if occupied >> to & 1 == 1 {
+                    break;
+                }
+                at = to;
+            }
+        }
+    };
+    match code & 7 {
+        PAWN => {
+            let dr = if color(code) == 0 {1} else {-1};
+            return [-1, 1].iter().filter_map(|df| step(s, *df, dr)).fold(0, |b, to| b | 1 << to);
+        },
+        KNIGHT => return KNIGHT_STEPS.iter().filter_map(|(df, dr)| step(s, *df, *dr)).fold(0, |b, to| b | 1 << to),
+        KING => return KING_STEPS.iter().filter_map(|(df, dr)| step(s, *df, *dr)).fold(0, |b, to| b | 1 << to),
+        BISHOP => slide(&KING_STEPS[1..].iter().step_by(2).copied().collect::<Vec<_>>()),
+        ROOK => slide(&KING_STEPS.iter().step_by(2).copied().collect::<Vec<_>>()),
+        _ => slide(&KING_STEPS),
+    }
+    bits
+}
+
+#[derive(Clone, Copy)]
+struct Position {
+    codes: [u8; MAX],
+    squares: [usize; MAX],
+    n: usize,
+    stm: usize,
+}
+
+impl Position {
+    fn occupied(&self) -> u64 {
+        self.squares[..self.n].iter().fold(0, |b, s| b | 1 << s)
+    }
+
+    fn attacked(&self, s: usize, by: usize) -> bool {
+        let occupied = self.occupied();
+        (0..self.n).any(|i| color(self.codes[i]) == by && attacks(self.codes[i], self.squares[i], occupied) >> s & 1 == 1)
+    }
+
+    fn in_check(&self, side: usize) -> bool {
+        let king = (0..self.n).find(|i| self.codes[*i] == KING | side as u8 * BLACK).expect("Both kings are on the board");
+        self.attacked(self.squares[king], 1 - side)
+    }
+
+    fn remove(&mut self, i: usize) {
+        for j in i..self.n - 1 {
+            self.codes[j] = self.codes[j + 1];
+            self.squares[j] = self.squares[j + 1];
+        }
+        self.n -= 1;
+    }
+
+    //pieces on different squares, pawns between the second and seventh rank, no king to take
+    fn valid(&self) -> bool {
+        let distinct = self.occupied().count_ones() as usize == self.n;
+        let pawns = (0..self.n).all(|i| self.codes[i] & 7 != PAWN || (1..7).contains(&rank(self.squares[i])));
+        distinct && pawns && !self.in_check(1 - self.stm)
+    }
+
+    fn index(&self) -> usize {
+        let squares = (0..self.n).fold(0, |idx, i| idx | self.squares[i] << (6 * i));
+        squares | self.stm << (6 * self.n)
+    }
+
+    fn key(&self) -> ([u8; MAX], usize) {
+        let mut codes = [0; MAX];
+        codes[..self.n].copy_from_slice(&self.codes[..self.n]);
+        (codes, self.n)
+    }
+}
+
+struct Move {
+    child: Position,
+    //leaves the table by a capture or promotion
+    exit: bool,
+    //capture or pawn move
+    zeroing: bool,
+}
+
+fn moves(pos: &Position) -> Vec<Move> {
+    let mut mvs = Vec::new();
+    let occupied = pos.occupied();
+    let own = (0..pos.n).filter(|i| color(pos.codes[*i]) == pos.stm).fold(0_u64, |b, i| b | 1 << pos.squares[i]);
+    for i in (0..pos.n).filter(|i| color(pos.codes[*i]) == pos.stm) {
+        let (code, from) = (pos.codes[i], pos.squares[i]);
+        let mut targets = if code & 7 == PAWN {
+            let dr = if pos.stm == 0 {1} else {-1};
+            let mut t = attacks(code, from, occupied) & occupied & !own;
+            if let Some(one) = step(from, 0, dr) && occupied >> one & 1 == 0 {
+                t |= 1 << one;
+                let start = if pos.stm == 0 {1} else {6};
+                if let Some(two) = step(one, 0, dr) && rank(from) == start && occupied >> two & 1 == 0 {
+                    t |= 1 << two;
+                }
+            }
+            t
+        } else {
+            attacks(code, from, occupied) & !own
+        };
+        while targets != 0 {
+            let to = targets.trailing_zeros() as usize;
+            targets &= targets - 1;
+            let mut child = *pos;
+            child.squares[i] = to;
+            child.stm = 1 - pos.stm;
+            let capture = occupied >> to & 1 == 1;
+            if capture {
+                let victim = (0..pos.n).find(|j| pos.squares[*j] == to).expect("Captured piece is on the board");
+                child.remove(victim);
+            }
+            if child.in_check(pos.stm) {
+                continue;
+            }
+            let i = (0..child.n).find(|j| child.squares[*j] == to).expect("Moved piece is on the board");
+            if code & 7 == PAWN && (rank(to) == 0 || rank(to) == 7) {
+                for promotion in [QUEEN, ROOK, BISHOP, KNIGHT] {
+                    let mut promoted = child;
+                    promoted.codes[i] = promotion | code & BLACK;
+                    mvs.push(Move { child: promoted, exit: true, zeroing: true });
+                }
+            } else {
+                mvs.push(Move { child, exit: capture, zeroing: capture || code & 7 == PAWN });
+            }
+        }
+    }
+    mvs
+}
+
+//positions one move before pos that don't leave the table, only piece moves if quiet
+fn unmoves(pos: &Position, quiet: bool) -> Vec<Position> {
+    let mut prev = Vec::new();
+    let mover = 1 - pos.stm;
+    let occupied = pos.occupied();
+    for i in (0..pos.n).filter(|i| color(pos.codes[*i]) == mover) {
+        let (code, to) = (pos.codes[i], pos.squares[i]);
+        let mut origins = 0_u64;
+        if code & 7 == PAWN {
+            if quiet {
+                continue;
+            }
+            let dr = if mover == 0 {-1} else {1};
+            if let Some(one) = step(to, 0, dr) && occupied >> one & 1 == 0 && (1..7).contains(&rank(one)) {
+                origins |= 1 << one;
+                let start = if mover == 0 {1} else {6};
+                if let Some(two) = step(one, 0, dr) && rank(two) == start && occupied >> two & 1 == 0 {
+                    origins |= 1 << two;
+                }
+            }
+        } else {
+            origins = attacks(code, to, occupied) & !occupied;
+        }
+        while origins != 0 {
+            let from = origins.trailing_zeros() as usize;
+            origins &= origins - 1;
+            let mut p = *pos;
+            p.squares[i] = from;
+            p.stm = mover;
+            if !p.in_check(pos.stm) {
+                prev.push(p);
+            }
+        }
+    }
+    prev
+}
+
+//results and dtz of every position of a material, indexed by Position::index
+struct Solved {
+    wdl: Vec<i8>,
+    dtz: Vec<i16>,
+}
+
+struct Solver {
+    tables: HashMap<([u8; MAX], usize), Solved>,
+}
+
+impl Solver {
+    fn value(&self, pos: &Position) -> i8 {
+        self.tables[&pos.key()].wdl[pos.index()]
+    }
+
+    fn solve(&mut self, codes: &[u8]) {
+        let n = codes.len();
+        let mut first = Position { codes: [0; MAX], squares: [0; MAX], n, stm: 0 };
+        first.codes[..n].copy_from_slice(codes);
+        if self.tables.contains_key(&first.key()) {
+            return;
+        }
+        //tables reached by captures and promotions
+        for i in 0..n {
+            if codes[i] & 7 != KING {
+                let mut smaller = first;
+                smaller.remove(i);
+                self.solve(&smaller.codes[..n - 1]);
+            }
+            if codes[i] & 7 == PAWN {
+                for promotion in [QUEEN, ROOK, BISHOP, KNIGHT] {
+                    let mut promoted = codes.to_vec();
+                    promoted[i] = promotion | codes[i] & BLACK;
+                    self.solve(&promoted);
+                }
+            }
+        }
+
+        let size = 2 << (6 * n);
+        let position = |idx: usize| {
+            let mut pos = first;
+            for i in 0..n {
+                pos.squares[i] = idx >> (6 * i) & 63;
+            }
+            pos.stm = idx >> (6 * n);
+            pos
+        };
+        let mut wdl = vec![INVALID; size];
+        //moves that stay in the table and aren't decided yet
+        let mut count = vec![0_u8; size];
+        let mut draw_exit = vec![false; size];
+        let mut mated = vec![false; size];
+        let mut queue = VecDeque::new();
+        for idx in 0..size {
+            let pos = position(idx);
+            if !pos.valid() {
+                continue;
+            }
+            let mvs = moves(&pos);
+            wdl[idx] = UNKNOWN;
+            if mvs.is_empty() {
+                mated[idx] = pos.in_check(pos.stm);
+                wdl[idx] = if mated[idx] {LOSS} else {DRAW};
+            }
+            for mv in &mvs {
+                if mv.exit {
+                    match self.value(&mv.child) {
+                        LOSS => wdl[idx] = WIN,
+                        DRAW => draw_exit[idx] = true,
+                        _ => {},
+                    }
+                } else {
+                    count[idx] += 1;
+                }
+            }
+            if wdl[idx] == UNKNOWN && count[idx] == 0 {
+                wdl[idx] = if draw_exit[idx] {DRAW} else {LOSS};
+            }
+            if wdl[idx] == WIN || wdl[idx] == LOSS {
+                queue.push_back(idx);
+            }
+        }
+        while let Some(idx) = queue.pop_front() {
+            for prev in unmoves(&position(idx), false) {
+                let p = prev.index();
+                if wdl[p] != UNKNOWN {
+                    continue;
+                }
+                if wdl[idx] == LOSS {
+                    wdl[p] = WIN;
+                    queue.push_back(p);
+                } else {
+                    count[p] -= 1;
+                    if count[p] == 0 && !draw_exit[p] {
+                        wdl[p] = LOSS;
+                        queue.push_back(p);
+                    }
+                }
+            }
+        }
+        for v in wdl.iter_mut().filter(|v| **v == UNKNOWN) {
+            *v = DRAW;
+        }
+
+        //plies to the next capture, pawn move or mate with the winner hurrying and the loser not
+        let mut dtz = vec![0_i16; size];
+        for idx in 0..size {
+            if wdl[idx] != WIN && wdl[idx] != LOSS {
+                continue;
+            }
+            let pos = position(idx);
+            let mvs = moves(&pos);
+            let value = |mv: &Move| if mv.exit {self.value(&mv.child)} else {wdl[mv.child.index()]};
+            count[idx] = mvs.iter().filter(|mv| !mv.zeroing).count() as u8;
+            let done = if wdl[idx] == WIN {
+                mvs.iter().any(|mv| mv.zeroing && value(mv) == LOSS || !mv.exit && mated[mv.child.index()])
+            } else {
+                count[idx] == 0
+            };
+            if done {
+                dtz[idx] = if wdl[idx] == WIN {1} else {-1};
+                queue.push_back(idx);
+            }
+        }
+        while let Some(idx) = queue.pop_front() {
+            let plies = dtz[idx].abs() + 1;
+            for prev in unmoves(&position(idx), true) {
+                let p = prev.index();
+                if dtz[p] != 0 {
+                    continue;
+                }
+                if wdl[idx] == LOSS {
+                    dtz[p] = plies;
+                    queue.push_back(p);
+                } else if wdl[p] == LOSS {
+                    count[p] -= 1;
+                    if count[p] == 0 {
+                        dtz[p] = -plies;
+                        queue.push_back(p);
+                    }
+                }
+            }
+        }
+        for idx in 0..size {
+            let decided = wdl[idx] == WIN || wdl[idx] == LOSS;
+            assert!(!decided || dtz[idx] != 0, "no dtz for a decided position");
+            //the 50 move rule never comes into play in these tables
+            assert!(dtz[idx].abs() <= 100, "cursed results are not supported");
+        }
+        self.tables.insert(first.key(), Solved { wdl, dtz });
+    }
+}
+
+//index tables, the same as in the probing code
+struct Maps {
+    b1h1h7: [u64; 64],
+    a1d1d4: [u64; 64],
+    kk: [[u64; 64]; 10],
+    binomial: [[u64; 64]; 6],
+    pawns: [u64; 64],
+    lead_pawn_idx: [[u64; 64]; 6],
+    lead_pawns_size: [[u64; 4]; 6],
+}
+
+fn off_diagonal(s: usize) -> i32 {
+    rank(s) as i32 - file(s) as i32
+}
+
+fn maps() -> Maps {
+    let mut m = Maps {
+        b1h1h7: [0; 64],
+        a1d1d4: [0; 64],
+        kk: [[0; 64]; 10],
+        binomial: [[0; 64]; 6],
+        pawns: [0; 64],
+        lead_pawn_idx: [[0; 64]; 6],
+        lead_pawns_size: [[0; 4]; 6],
+    };
+    for (code, s) in (0..64).filter(|s| off_diagonal(*s) < 0).enumerate() {
+        m.b1h1h7[s] = code as u64;
+    }
+    let triangle: Vec<usize> = (0..28).filter(|s| file(*s) <= 3 && off_diagonal(*s) < 0).collect();
+    let diagonal: Vec<usize> = (0..28).filter(|s| file(*s) <= 3 && off_diagonal(*s) == 0).collect();
+    for (code, s) in triangle.iter().chain(&diagonal).enumerate() {
+        m.a1d1d4[*s] = code as u64;
+    }
+    let mut code = 0;
+    let mut last = Vec::new();
+    for (idx, s1) in triangle.iter().chain(&diagonal).enumerate() {
+        for s2 in 0..64 {
+            if file(*s1).abs_diff(file(s2)) <= 1 && rank(*s1).abs_diff(rank(s2)) <= 1 {
+                continue;
+            }
+            match (off_diagonal(*s1), off_diagonal(s2)) {
+                (0, 1..) => {},
+                (0, 0) => last.push((idx, s2)),
+                _ => {m.kk[idx][s2] = code; code += 1},
+            }
+        }
+    }
+    for (idx, s2) in last {
+        m.kk[idx][s2] = code;
+        code += 1;
+    }
+    assert_eq!(code, 462);
+    m.binomial[0][0] = 1;
+    for n in 1..64 {
+        for k in 0..6.min(n + 1) {
+            m.binomial[k][n] = if k > 0 {m.binomial[k - 1][n - 1]} else {0} + if k < n {m.binomial[k][n - 1]} else {0};
+        }
+    }
+    let mut available = 48;
+    for f in 0..4 {
+        for r in 1..7 {
+            m.pawns[f + 8 * r] = available - 1;
+            m.pawns[(f + 8 * r) ^ 7] = available - 2;
+            available -= 2;
+        }
+    }
+    for lead in 1..6 {
+        for f in 0..4 {
+            let mut idx = 0;
+            for r in 1..7 {
+                m.lead_pawn_idx[lead][f + 8 * r] = idx;
+                idx += m.binomial[lead - 1][m.pawns[f + 8 * r] as usize];
+            }
+            m.lead_pawns_size[lead][f] = idx;
+        }
+    }
+    m
+}
+
+//layout of one sub table
+#[derive(Clone)]
+struct Sub {
+    pieces: Vec<u8>,
+    order: u8,
+    group_len: Vec<usize>,
+    group_idx: Vec<u64>,
+}
+
+struct Spec {
+    name: &'static str,
+    //solver order: the pieces of the name, white then black
+    codes: Vec<u8>,
+    has_pawns: bool,
+    unique: bool,
+    //dtz is stored for this side to move
+    dtz_stm: usize,
+    //piece order in the file for white and black to move
+    pieces: [Vec<u8>; 2],
+    order: [u8; 2],
+}
+
+impl Spec {
+    fn new(name: &'static str, pieces: [Vec<u8>; 2], order: [u8; 2], dtz_stm: usize) -> Self {
+        let (white, black) = name.split_once('v').unwrap();
+        let mut codes = Vec::new();
+        for (letters, side) in [(white, 0), (black, BLACK)] {
+            for c in letters.chars() {
+                codes.push(side + " PNBRQK".find(c).unwrap() as u8);
+            }
+        }
+        let count = |letters: &str, c: char| letters.chars().filter(|l| *l == c).count();
+        let unique = [white, black].iter().any(|letters| "PNBRQ".chars().any(|c| count(letters, c) == 1));
+        assert!(!name.contains('P') || count(black, 'P') == 0, "only white pawns are supported");
+        Self { name, codes, has_pawns: name.contains('P'), unique, dtz_stm, pieces, order }
+    }
+
+    fn sub(&self, m: &Maps, side: usize, f: usize) -> Sub {
+        let pieces = self.pieces[side].clone();
+        let mut first_len: i32 = if self.has_pawns {0} else if self.unique {3} else {2};
+        let mut group_len = vec![1];
+        for i in 1..pieces.len() {
+            first_len -= 1;
+            if first_len > 0 || pieces[i] == pieces[i - 1] {
+                *group_len.last_mut().unwrap() += 1;
+            } else {
+                group_len.push(1);
+            }
+        }
+        let mut group_idx = vec![0; group_len.len() + 1];
+        let mut free = 64 - group_len[0];
+        let mut next = 1;
+        let mut idx = 1;
+        let mut k = 0;
+        while next < group_len.len() || k == self.order[side] {
+            if k == self.order[side] {
+                group_idx[0] = idx;
+                idx *= if self.has_pawns {m.lead_pawns_size[group_len[0]][f]} else if self.unique {31332} else {462};
+            } else {
+                group_idx[next] = idx;
+                idx *= m.binomial[group_len[next]][free];
+                free -= group_len[next];
+                next += 1;
+            }
+            k += 1;
+        }
+        group_idx[group_len.len()] = idx;
+        Sub { pieces, order: self.order[side], group_len, group_idx }
+    }
+
+    //file of the leading pawn and index in the sub table, white is always the first side of the name here
+    fn encode(&self, m: &Maps, subs: &[Sub], pos: &Position) -> (usize, u64) {
+        let mut squares = Vec::new();
+        let mut pieces = Vec::new();
+        let mut f = 0;
+        let lead_count;
+        if self.has_pawns {
+            let mut lead: Vec<usize> = (0..pos.n).filter(|i| pos.codes[*i] == PAWN).map(|i| pos.squares[i]).collect();
+            lead.sort_by_key(|s| Reverse(m.pawns[*s]));
+            f = file(lead[0]).min(7 - file(lead[0]));
+            lead_count = lead.len();
+            pieces.extend(std::iter::repeat_n(PAWN, lead_count));
+            squares.extend(lead);
+        } else {
+            lead_count = 0;
+        }
+        let sub = &subs[f];
+        //the remaining pieces in the order of the sub table
+        let mut rest: Vec<(u8, usize)> = (0..pos.n).filter(|i| !self.has_pawns || pos.codes[*i] != PAWN).map(|i| (pos.codes[i], pos.squares[i])).collect();
+        for code in &sub.pieces[lead_count..] {
+            let i = rest.iter().position(|(c, _)| c == code).unwrap();
+            let (c, s) = rest.remove(i);
+            pieces.push(c);
+            squares.push(s);
+        }
+        if file(squares[0]) > 3 {
+            squares.iter_mut().for_each(|s| *s ^= 7);
+        }
+        let mut idx;
+        if self.has_pawns {
+            idx = m.lead_pawn_idx[lead_count][squares[0]];
+            for i in 1..lead_count {
+                idx += m.binomial[i][m.pawns[squares[i]] as usize];
+            }
+        } else {
+            if rank(squares[0]) > 3 {
+                squares.iter_mut().for_each(|s| *s ^= 56);
+            }
+            if let Some(i) = (0..sub.group_len[0]).find(|i| off_diagonal(squares[*i]) != 0) && off_diagonal(squares[i]) > 0 {
+                squares[i..].iter_mut().for_each(|s| *s = (*s % 8) * 8 + *s / 8);
+            }
+            let (s0, s1) = (squares[0], squares[1]);
+            if self.unique {
+                let s2 = squares[2];
+                let a1 = (s1 > s0) as usize;
+                let a2 = (s2 > s0) as usize + (s2 > s1) as usize;
+                idx = match (off_diagonal(s0), off_diagonal(s1), off_diagonal(s2)) {
+                    (o, _, _) if o != 0 => (m.a1d1d4[s0] * 63 + (s1 - a1) as u64) * 62 + (s2 - a2) as u64,
+                    (_, o, _) if o != 0 => (6 * 63 + rank(s0) as u64 * 28 + m.b1h1h7[s1]) * 62 + (s2 - a2) as u64,
+                    (_, _, o) if o != 0 => 6 * 63 * 62 + 4 * 28 * 62 + rank(s0) as u64 * 7 * 28 + (rank(s1) - a1) as u64 * 28 + m.b1h1h7[s2],
+                    _ => 6 * 63 * 62 + 4 * 28 * 62 + 4 * 7 * 28 + rank(s0) as u64 * 7 * 6 + (rank(s1) - a1) as u64 * 6 + (rank(s2) - a2) as u64,
+                };
+            } else {
+                idx = m.kk[m.a1d1d4[s0] as usize][s1];
+            }
+        }
+        idx *= sub.group_idx[0];
+        let mut start = sub.group_len[0];
+        for g in 1..sub.group_len.len() {
+            let len = sub.group_len[g];
+            let mut group = squares[start..start + len].to_vec();
+            group.sort();
+            let mut n = 0;
+            for (i, s) in group.iter().enumerate() {
+                let below = squares[..start].iter().filter(|b| *b < s).count();
+                n += m.binomial[i + 1][s - below];
+            }
+            idx += n * sub.group_idx[g];
+            start += len;
+        }
+        (f, idx)
+    }
+}
+
+//a sub table after recursive pairing and huffman coding
+enum Packed {
+    Single(u16),
+    Pairs {
+        min_len: u8,
+        max_len: u8,
+        lowest_sym: Vec<u16>,
+        //left and right of every symbol, a value and 0xFFF for single values
+        btree: Vec<(u16, u16)>,
+        blocks: Vec<Vec<u8>>,
+        block_lengths: Vec<u16>,
+        sparse: Vec<(u32, u16)>,
+    },
+}
+
+const BLOCK_LOG: u8 = 8;
+const MAX_PAIRS: usize = 1000;
+
+fn pack(values: &[u16], span_log: u8) -> Packed {
+    if values.iter().all(|v| *v == values[0]) {
+        return Packed::Single(values[0]);
+    }
+    //recursive pairing: replace the most frequent pair of adjacent symbols by a new one
+    let mut leaves: Vec<u16> = values.to_vec();
+    leaves.sort();
+    leaves.dedup();
+    let mut pairs: Vec<(u16, u16)> = leaves.iter().map(|v| (*v, 0xFFF)).collect();
+    let mut lens: Vec<usize> = vec![1; leaves.len()];
+    let mut seq: Vec<u16> = values.iter().map(|v| leaves.binary_search(v).unwrap() as u16).collect();
+    for _ in 0..MAX_PAIRS {
+        let k = pairs.len();
+        let mut counts = vec![0_u32; k * k];
+        for w in seq.windows(2) {
+            counts[w[0] as usize * k + w[1] as usize] += 1;
+        }
+        let best = (0..k * k).filter(|p| lens[p / k] + lens[p % k] <= 256).max_by_key(|p| (counts[*p], Reverse(*p))).unwrap();
+        if counts[best] < 8 {
+            break;
+        }
+        let (a, b) = ((best / k) as u16, (best % k) as u16);
+        pairs.push((a, b));
+        lens.push(lens[a as usize] + lens[b as usize]);
+        let mut next = Vec::with_capacity(seq.len());
+        let mut i = 0;
+        while i < seq.len() {
+            if i + 1 < seq.len() && seq[i] == a && seq[i + 1] == b {
+                next.push(k as u16);
+                i += 2;
+            } else {
+                next.push(seq[i]);
+                i += 1;
+            }
+        }
+        seq = next;
+    }
+
+    //huffman code lengths of the symbols left in the sequence
+    let k = pairs.len();
+    let mut freq = vec![0_u64; k];
+    for s in &seq {
+        freq[*s as usize] += 1;
+    }
+    let mut len = vec![0_u8; k];
+    let coded: Vec<usize> = (0..k).filter(|s| freq[*s] > 0).collect();
+    if coded.len() == 1 {
+        len[coded[0]] = 1;
+    } else {
+        let mut parent = vec![usize::MAX; k];
+        let mut heap: BinaryHeap<Reverse<(u64, usize)>> = coded.iter().map(|s| Reverse((freq[*s], *s))).collect();
+        while heap.len() > 1 {
+            let Reverse((f1, n1)) = heap.pop().unwrap();
+            let Reverse((f2, n2)) = heap.pop().unwrap();
+            parent.push(usize::MAX);
+            let node = parent.len() - 1;
+            parent[n1] = node;
+            parent[n2] = node;
+            heap.push(Reverse((f1 + f2, node)));
+        }
+        for s in &coded {
+            let mut depth = 0;
+            let mut at = *s;
+            while parent[at] != usize::MAX {
+                at = parent[at];
+                depth += 1;
+            }
+            len[*s] = depth;
+        }
+    }
+    let max_len = *len.iter().max().unwrap();
+    let min_len = coded.iter().map(|s| len[*s]).min().unwrap();
+    assert!(max_len <= 32, "huffman code too long");
+
+    //canonical code: longer codes get lower symbols, symbols without a code come last
+    let mut order: Vec<usize> = (0..k).collect();
+    order.sort_by_key(|s| (len[*s] == 0, Reverse(len[*s]), *s));
+    let mut id = vec![0; k];
+    for (new, old) in order.iter().enumerate() {
+        id[*old] = new;
+    }
+    let lengths = (max_len - min_len + 1) as usize;
+    let count = |l: u8| coded.iter().filter(|s| len[**s] == l).count() as u64;
+    let mut lowest_sym = vec![0; lengths];
+    let mut base = vec![0_u64; lengths];
+    for i in (0..lengths - 1).rev() {
+        let longer = min_len + i as u8 + 1;
+        lowest_sym[i] = lowest_sym[i + 1] + count(longer) as u16;
+        base[i] = (base[i + 1] + count(longer)) / 2;
+    }
+    let code = |s: usize| {
+        let i = (len[s] - min_len) as usize;
+        base[i] + (id[s] - lowest_sym[i] as usize) as u64
+    };
+    let btree = order.iter().map(|old| match pairs[*old] {
+        (v, 0xFFF) => (v, 0xFFF),
+        (a, b) => (id[a as usize] as u16, id[b as usize] as u16),
+    }).collect();
+
+    //blocks of whole symbols
+    let block_bits = 8 << BLOCK_LOG;
+    let mut blocks = Vec::new();
+    let mut block_lengths = Vec::new();
+    let mut starts = Vec::new();
+    let mut bits: Vec<bool> = Vec::new();
+    let mut block_values = 0;
+    let mut total = 0;
+    let mut flush = |bits: &mut Vec<bool>, block_values: &mut usize, total: usize| {
+        let mut bytes = vec![0_u8; 1 << BLOCK_LOG];
+        for (i, bit) in bits.iter().enumerate() {
+            bytes[i / 8] |= (*bit as u8) << (7 - i % 8);
+        }
+        blocks.push(bytes);
+        block_lengths.push((*block_values - 1) as u16);
+        starts.push(total - *block_values);
+        bits.clear();
+        *block_values = 0;
+    };
+    for s in &seq {
+        let s = *s as usize;
+        if bits.len() + len[s] as usize > block_bits || block_values + lens[s] > 65536 {
+            flush(&mut bits, &mut block_values, total);
+        }
+        let c = code(s);
+        for b in (0..len[s]).rev() {
+            bits.push(c >> b & 1 == 1);
+        }
+        block_values += lens[s];
+        total += lens[s];
+    }
+    flush(&mut bits, &mut block_values, total);
+    assert_eq!(total, values.len());
+
+    //the sparse index points at the middle of every span
+    let span = 1 << span_log;
+    let sparse = (0..values.len().div_ceil(span)).map(|k| {
+        let target = k * span + span / 2;
+        let block = starts.partition_point(|start| *start <= target) - 1;
+        (block as u32, u16::try_from(target - starts[block]).expect("sparse offset fits"))
+    }).collect();
+    Packed::Pairs { min_len, max_len, lowest_sym, btree, blocks, block_lengths, sparse }
+}
+
+fn write_table(m: &Maps, solver: &Solver, spec: &Spec, dtz: bool, dir: &Path) {
+    let files = if spec.has_pawns {4} else {1};
+    let sides = if dtz {1} else {2};
+    let subs: Vec<Vec<Sub>> = (0..2).map(|side| (0..files).map(|f| spec.sub(m, side, f)).collect()).collect();
+    let solved = &solver.tables[&{
+        let mut codes = [0; MAX];
+        codes[..spec.codes.len()].copy_from_slice(&spec.codes);
+        (codes, spec.codes.len())
+    }];
+    let table_side = |i: usize| if dtz {spec.dtz_stm} else {i};
+
+    let mut values: Vec<Vec<Vec<Option<u16>>>> = (0..sides).map(|i| {
+        subs[table_side(i)].iter().map(|sub| vec![None; *sub.group_idx.last().unwrap() as usize]).collect()
+    }).collect();
+    let n = spec.codes.len();
+    let mut pos = Position { codes: [0; MAX], squares: [0; MAX], n, stm: 0 };
+    pos.codes[..n].copy_from_slice(&spec.codes);
+    for idx in 0..2 << (6 * n) {
+        for i in 0..n {
+            pos.squares[i] = idx >> (6 * i) & 63;
+        }
+        pos.stm = idx >> (6 * n);
+        let wdl = solved.wdl[idx];
+        if wdl == INVALID || dtz && (pos.stm != spec.dtz_stm || wdl == DRAW) {
+            continue;
+        }
+        let side = if dtz {0} else {pos.stm};
+        let (f, i) = spec.encode(m, &subs[pos.stm], &pos);
+        let value = if dtz {solved.dtz[idx].unsigned_abs() - 1} else {(wdl + 2) as u16};
+        let slot = &mut values[side][f][i as usize];
+        assert!(slot.is_none_or(|v| v == value), "{}: positions with different values share index {}", spec.name, i);
+        *slot = Some(value);
+    }
+
+    //positions that can't happen or aren't looked up repeat the value before them
+    let packed: Vec<Vec<Packed>> = values.iter().map(|side| side.iter().map(|sub| {
+        let mut last = sub.iter().flatten().next().copied().unwrap_or(0);
+        let filled: Vec<u16> = sub.iter().map(|v| {
+            last = v.unwrap_or(last);
+            last
+        }).collect();
+        pack(&filled, if filled.len() > 100_000 {12} else {10})
+    }).collect()).collect();
+
+    let mut bytes = Vec::from(if dtz {DTZ_MAGIC} else {WDL_MAGIC});
+    bytes.push(1 | (spec.has_pawns as u8) << 1);
+    for f in 0..files {
+        let nibbles = |get: &dyn Fn(&Sub) -> u8| get(&subs[table_side(0)][f]) | if sides == 2 {get(&subs[1][f]) << 4} else {0};
+        bytes.push(nibbles(&|sub| sub.order));
+        for k in 0..n {
+            bytes.push(nibbles(&|sub| sub.pieces[k]));
+        }
+    }
+    if bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    let flags = if dtz {WIN_PLIES | LOSS_PLIES | if spec.dtz_stm == 1 {STM} else {0}} else {0};
+    for f in 0..files {
+        for side in &packed {
+            match &side[f] {
+                Packed::Single(value) => bytes.extend([flags | SINGLE_VALUE, *value as u8]),
+                Packed::Pairs { min_len, max_len, lowest_sym, btree, blocks, .. } => {
+                    let span_log = if values[0][f].len() > 100_000 {12} else {10};
+                    bytes.extend([flags, BLOCK_LOG, span_log, 1]);
+                    bytes.extend((blocks.len() as u32).to_le_bytes());
+                    bytes.extend([*max_len, *min_len]);
+                    for sym in lowest_sym {
+                        bytes.extend(sym.to_le_bytes());
+                    }
+                    bytes.extend((btree.len() as u16).to_le_bytes());
+                    for (left, right) in btree {
+                        bytes.extend([*left as u8, (left >> 8) as u8 | (right << 4) as u8, (right >> 4) as u8]);
+                    }
+                    if btree.len() % 2 == 1 {
+                        bytes.push(0);
+                    }
+                },
+            }
+        }
+    }
+    if dtz && bytes.len() % 2 == 1 {
+        bytes.push(0);
+    }
+    for f in 0..files {
+        for side in &packed {
+            if let Packed::Pairs { sparse, .. } = &side[f] {
+                for (block, offset) in sparse {
+                    bytes.extend(block.to_le_bytes());
+                    bytes.extend(offset.to_le_bytes());
+                }
+            }
+        }
+    }
+    for f in 0..files {
+        for side in &packed {
+            if let Packed::Pairs { block_lengths, .. } = &side[f] {
+                //one length of padding
+                for len in block_lengths.iter().chain([&0]) {
+                    bytes.extend(len.to_le_bytes());
+                }
+            }
+        }
+    }
+    for f in 0..files {
+        for side in &packed {
+            bytes.resize(bytes.len().next_multiple_of(64), 0);
+            if let Packed::Pairs { blocks, .. } = &side[f] {
+                for block in blocks {
+                    bytes.extend(block);
+                }
+            }
+        }
+    }
+    let path = dir.join(format!("{}.{}", spec.name, if dtz {"rtbz"} else {"rtbw"}));
+    std::fs::write(&path, &bytes).expect("Table can be written");
+    println!("{}: {} bytes", path.display(), bytes.len());
+}
+
+fn main() {
+    let dir = std::env::args().nth(1).expect("output directory expected");
+    let m = maps();
+    let w = |code: u8| code;
+    let b = |code: u8| code | BLACK;
+    let specs = [
+        Spec::new("KQvK", [vec![w(KING), w(QUEEN), b(KING)], vec![w(QUEEN), w(KING), b(KING)]], [0, 0], 0),
+        Spec::new("KRvK", [vec![w(KING), b(KING), w(ROOK)], vec![w(ROOK), w(KING), b(KING)]], [0, 0], 1),
+        Spec::new("KBvK", [vec![w(KING), w(BISHOP), b(KING)], vec![w(KING), w(BISHOP), b(KING)]], [0, 0], 0),
+        Spec::new("KNvK", [vec![w(KING), w(KNIGHT), b(KING)], vec![w(KING), w(KNIGHT), b(KING)]], [0, 0], 0),
+        Spec::new("KPvK", [vec![w(PAWN), w(KING), b(KING)], vec![w(PAWN), b(KING), w(KING)]], [0, 0], 0),
+        Spec::new("KQvKR", [vec![w(KING), w(QUEEN), b(KING), b(ROOK)], vec![w(QUEEN), b(ROOK), w(KING), b(KING)]], [0, 1], 0),
+    ];
+    let mut solver = Solver { tables: HashMap::new() };
+    for spec in &specs {
+        solver.solve(&spec.codes);
+        for dtz in [false, true] {
+            write_table(&m, &solver, spec, dtz, Path::new(&dir));
+        }
+    }
+}
@@ -0,0 +1,20 @@
+#!/bin/sh
+#Stand-in UCI engine for the tests of src/uci_client.rs. It answers every go with the move set by
+#"setoption name Move value <move>", e2e4 without one, and never answers go after
+#"setoption name Mode value hang".
+move=e2e4
+mode=normal
+while read -r line; do
+    case "$line" in
+        uci) echo "id name Stand-in"; echo "uciok";;
+        isready) echo "readyok";;
+        "setoption name Move value "*) move=${line#setoption name Move value };;
+        "setoption name Mode value "*) mode=${line#setoption name Mode value };;
+        go*)
+            if [ "$mode" != hang ]; then
+                echo "info depth 3 score cp 25 nodes 100 pv $move"
+                echo "bestmove $move"
+            fi;;
+        quit) exit 0;;
+    esac
+done